@@ -8,15 +8,24 @@ use crate::{
     error::Error::BadRequestError,
     model::{
         hyperliquid::{
-            Agent, BookKind, ChannelConnection, Condition,
-            DepthCalculationResponse, Exchange, Info, InternalRequest, LiquidityResponse,
-            QueueElem, Request, ValueKind, CONNECTIONS,
+            Agent, Algorithm, BookKind, DepthCalculationResponse, Exchange, Info, InternalRequest,
+            LiquidityResponse, QueueElem, Request, TwapOrderRequest, ValueKind,
         },
         Response,
     },
     prelude::Result,
-    service::hyperliquid::{info, pair::pair_candle},
-    ws::hyperliquid::book_price::BookPrice,
+    service::{
+        bracket::Brackets,
+        chase::Chases,
+        grid::GridBots,
+        hyperliquid::{info, pair::pair_candle},
+        iceberg::Icebergs,
+        indicator, market,
+        queue::CondQueue,
+        scaled,
+        store::Stores,
+        twap::Twaps,
+    },
 };
 use actix_session::Session;
 use actix_web::{web, HttpResponse, Responder};
@@ -24,6 +33,7 @@ use anyhow::Context;
 use ethers::{
     core::rand,
     signers::{LocalWallet, Signer},
+    types::Address,
     utils::hex::ToHex,
 };
 use hyperliquid::{
@@ -35,7 +45,21 @@ use hyperliquid::{
 };
 
 use std::sync::Arc;
-use tokio::sync::{mpsc::Sender, RwLock};
+use tokio::sync::mpsc::Sender;
+
+/// Address of the user the session's agent trades for.
+///
+/// Info requests about a user's queued or running orders answer for this
+/// address rather than one taken from the request body, so a session can only
+/// see its own orders.
+fn session_user(session: &Session) -> Result<Address> {
+    let agent = session
+        .get::<Agent>("agent")
+        .context("Failed to get agent")?
+        .ok_or_else(|| BadRequestError("Establish a connection first".to_string()))?;
+
+    Ok(agent.user)
+}
 
 /// Entry point for `/hyperliquid` requests coming from the frontend.
 ///
@@ -49,7 +73,8 @@ pub async fn hyperliquid(
     req: web::Json<Request>,
     session: Session,
     sender: web::Data<Sender<InternalRequest>>,
-    queue: web::Data<RwLock<Vec<QueueElem>>>,
+    queue: web::Data<CondQueue>,
+    stores: web::Data<Stores>,
 ) -> Result<impl Responder> {
     let req = req.into_inner();
     let chain = **chain;
@@ -270,6 +295,119 @@ pub async fn hyperliquid(
                         msg: Some("Delta endpoint not implemented".into()),
                     })
                 }
+                Info::CondOrders => {
+                    let user = session_user(&session)?;
+                    let queue_r = queue.read().await;
+
+                    let data = queue_r
+                        .iter()
+                        .filter(|elem| elem.owner == user)
+                        .map(QueueElem::view)
+                        .collect::<Vec<_>>();
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                Info::CondOrder { id } => {
+                    let user = session_user(&session)?;
+                    let elem = queue
+                        .get(id)
+                        .await
+                        .filter(|elem| elem.owner == user)
+                        .ok_or_else(|| BadRequestError("Conditional order not found".into()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(elem.view()),
+                        msg: None,
+                    })
+                }
+                Info::Indicator { req } => {
+                    let data = indicator::query(&info, &req)
+                        .await
+                        .map_err(|msg| BadRequestError(msg.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                Info::CondOrderHistory => {
+                    let user = session_user(&session)?;
+                    let data = queue.history(&user).await?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                Info::BracketOrders => {
+                    let user = session_user(&session)?;
+                    let data = Brackets::list(&user).await;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                Info::IcebergOrders => {
+                    let user = session_user(&session)?;
+                    let data = Icebergs::list(&user).await;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                Info::ChaseOrders => {
+                    let user = session_user(&session)?;
+                    let data = Chases::list(&user).await;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                Info::GridBots => {
+                    let user = session_user(&session)?;
+                    let data = GridBots::list(&user).await;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                Info::TwapOrders => {
+                    let user = session_user(&session)?;
+                    let data = Twaps::list(&user).await;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                Info::TwapOrder { id } => {
+                    let user = session_user(&session)?;
+                    let data = Twaps::get(&user, id)
+                        .await
+                        .ok_or_else(|| BadRequestError("TWAP not found".into()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
             }
         }
 
@@ -281,6 +419,8 @@ pub async fn hyperliquid(
                 .context("Failed to get agent")?
                 .ok_or_else(|| BadRequestError("Establish a connection first".to_string()))?;
 
+            let user = agent.user;
+
             let agent: Arc<LocalWallet> = Arc::new(
                 agent
                     .private_key
@@ -386,62 +526,301 @@ pub async fn hyperliquid(
 
                 // Conditional orders enqueue work for the background worker and
                 // optionally create shared websocket subscriptions so multiple
-                // jobs can reuse the same price feed.
+                // jobs can reuse the same price feed. The entry is persisted
+                // before it becomes visible to the worker so a restart cannot
+                // lose it.
                 Exchange::CondOrder {
                     action,
                     condition,
                     source,
                     vault_address,
+                    expires_at,
+                    group,
                 } => {
-                    match &condition {
-                        Condition::PairPrice(pair_price) => {
-                            let mut connections = CONNECTIONS.lock().await;
-
-                            if let Some(connection) = connections.get_mut(&pair_price.left_symbol) {
-                                connection.count += 1;
-                            } else {
-                                let (receiver, stop_sender) =
-                                    BookPrice::init(&pair_price.left_symbol).await?;
-
-                                connections.insert(
-                                    pair_price.left_symbol.clone(),
-                                    ChannelConnection {
-                                        count: 1,
-                                        receiver,
-                                        stop_sender,
-                                    },
-                                );
-                            }
-
-                            if let Some(connection) = connections.get_mut(&pair_price.right_symbol)
-                            {
-                                connection.count += 1;
-                            } else {
-                                let (receiver, stop_sender) =
-                                    BookPrice::init(&pair_price.right_symbol).await?;
-
-                                connections.insert(
-                                    pair_price.right_symbol.clone(),
-                                    ChannelConnection {
-                                        count: 1,
-                                        receiver,
-                                        stop_sender,
-                                    },
-                                );
-                            }
-                        }
+                    condition
+                        .validate()
+                        .map_err(|msg| BadRequestError(msg.to_string()))?;
+                    action.validate().map_err(BadRequestError)?;
+
+                    if expires_at.is_some_and(|expires_at| expires_at <= queue.now()) {
+                        return Err(BadRequestError("Expiry is already in the past".into()));
                     }
-                    queue.write().await.push(QueueElem {
-                        source,
+
+                    let mut elem =
+                        QueueElem::new(user, source, agent, action, condition, vault_address);
+                    elem.expires_at = expires_at;
+                    elem.group = group;
+                    let id = elem.id;
+
+                    queue.insert(elem).await?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(id),
+                        msg: None,
+                    })
+                }
+                Exchange::TrailingStopOrder {
+                    action,
+                    vault_address,
+                    expires_at,
+                    group,
+                } => {
+                    let (condition, action) = action.into_parts();
+                    condition
+                        .validate()
+                        .map_err(|msg| BadRequestError(msg.to_string()))?;
+                    action.validate().map_err(BadRequestError)?;
+
+                    if expires_at.is_some_and(|expires_at| expires_at <= queue.now()) {
+                        return Err(BadRequestError("Expiry is already in the past".into()));
+                    }
+
+                    let mut elem = QueueElem::new(
+                        user,
+                        Default::default(),
                         agent,
                         action,
                         condition,
                         vault_address,
-                    });
+                    );
+                    elem.expires_at = expires_at;
+                    elem.group = group;
+                    let id = elem.id;
 
-                    HttpResponse::Ok().json(Response::<()> {
+                    queue.insert(elem).await?;
+
+                    HttpResponse::Ok().json(Response {
                         success: true,
-                        data: None,
+                        data: Some(id),
+                        msg: None,
+                    })
+                }
+                // Brackets place the entry right away and keep following it in
+                // the background, attaching TP/SL legs sized to its fills.
+                Exchange::BracketOrder {
+                    action,
+                    vault_address,
+                } => {
+                    action.validate().map_err(BadRequestError)?;
+
+                    let id =
+                        Brackets::open(&stores.brackets, chain, user, agent, action, vault_address)
+                            .await
+                            .map_err(|err| BadRequestError(err.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(id),
+                        msg: None,
+                    })
+                }
+                Exchange::CancelBracketOrder { action } => {
+                    Brackets::cancel(&stores.brackets, chain, &user, action.id)
+                        .await
+                        .map_err(|err| BadRequestError(err.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(action.id),
+                        msg: None,
+                    })
+                }
+                Exchange::ScaledOrder {
+                    action,
+                    vault_address,
+                } => {
+                    action.validate().map_err(BadRequestError)?;
+
+                    let info: hyperliquid::Info = Hyperliquid::new(chain);
+                    let market = market::market(&info, action.asset)
+                        .await
+                        .map_err(|err| BadRequestError(err.to_string()))?;
+                    let orders = scaled::ladder(&action, &market).map_err(BadRequestError)?;
+
+                    let data = exchange
+                        .place_order(agent, orders, vault_address)
+                        .await
+                        .map_err(|msg| BadRequestError(msg.to_string()))?;
+
+                    match data {
+                        response::Response::Ok(data) => HttpResponse::Ok().json(Response {
+                            success: true,
+                            data: Some(data),
+                            msg: None,
+                        }),
+                        response::Response::Err(msg) => HttpResponse::Ok().json(Response {
+                            success: false,
+                            data: None::<String>,
+                            msg: Some(msg),
+                        }),
+                    }
+                }
+                // Chases place their order once the book stream delivers the
+                // touch and keep it there in the background.
+                Exchange::ChaseOrder {
+                    action,
+                    vault_address,
+                } => {
+                    action.validate().map_err(BadRequestError)?;
+
+                    let id = Chases::open(chain, user, agent, action, vault_address)
+                        .await
+                        .map_err(|err| BadRequestError(err.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(id),
+                        msg: None,
+                    })
+                }
+                Exchange::CancelChaseOrder { action } => {
+                    let data = Chases::cancel(&user, action.id)
+                        .await
+                        .map_err(|err| BadRequestError(err.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                // Icebergs place their first clip right away and replenish it
+                // in the background as clips fill.
+                Exchange::IcebergOrder {
+                    action,
+                    vault_address,
+                } => {
+                    action.validate().map_err(BadRequestError)?;
+
+                    let id = Icebergs::open(chain, user, agent, action, vault_address)
+                        .await
+                        .map_err(|err| BadRequestError(err.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(id),
+                        msg: None,
+                    })
+                }
+                Exchange::CancelIcebergOrder { action } => {
+                    Icebergs::cancel(chain, &user, action.id)
+                        .await
+                        .map_err(|err| BadRequestError(err.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(action.id),
+                        msg: None,
+                    })
+                }
+                Exchange::GridBot {
+                    action,
+                    vault_address,
+                } => {
+                    action.validate().map_err(BadRequestError)?;
+
+                    let id =
+                        GridBots::start(&stores.grids, chain, user, agent, action, vault_address)
+                            .await
+                            .map_err(|err| BadRequestError(err.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(id),
+                        msg: None,
+                    })
+                }
+                Exchange::StopGridBot { action } => {
+                    GridBots::stop(&stores.grids, chain, &user, action.id)
+                        .await
+                        .map_err(|err| BadRequestError(err.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(action.id),
+                        msg: None,
+                    })
+                }
+                Exchange::PauseTwapOrder { action } => {
+                    let data = Twaps::pause(&user, action.id)
+                        .await
+                        .map_err(|err| BadRequestError(err.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                Exchange::ResumeTwapOrder { action } => {
+                    let data = Twaps::resume(&user, action.id)
+                        .await
+                        .map_err(|err| BadRequestError(err.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                Exchange::CancelTwapOrder { action } => {
+                    let data = Twaps::cancel(&user, action.id)
+                        .await
+                        .map_err(|err| BadRequestError(err.to_string()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(data),
+                        msg: None,
+                    })
+                }
+                Exchange::AmendCondOrder { action } => {
+                    let owned = queue
+                        .get(action.id)
+                        .await
+                        .is_some_and(|elem| elem.owner == user);
+                    if !owned {
+                        return Err(BadRequestError("Conditional order not found".into()));
+                    }
+
+                    if let Some(condition) = &action.condition {
+                        condition
+                            .validate()
+                            .map_err(|msg| BadRequestError(msg.to_string()))?;
+                    }
+                    if let Some(action) = &action.action {
+                        action.validate().map_err(BadRequestError)?;
+                    }
+
+                    let view = queue
+                        .amend(action.id, action.condition, action.action)
+                        .await?
+                        .ok_or_else(|| BadRequestError("Conditional order not found".into()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(view),
+                        msg: None,
+                    })
+                }
+                Exchange::CancelCondOrder { action } => {
+                    let owned = queue
+                        .get(action.id)
+                        .await
+                        .is_some_and(|elem| elem.owner == user);
+                    if !owned {
+                        return Err(BadRequestError("Conditional order not found".into()));
+                    }
+
+                    let elem = queue
+                        .cancel(action.id)
+                        .await
+                        .ok_or_else(|| BadRequestError("Conditional order not found".into()))?;
+
+                    HttpResponse::Ok().json(Response {
+                        success: true,
+                        data: Some(elem.id),
                         msg: None,
                     })
                 }
@@ -554,52 +933,58 @@ pub async fn hyperliquid(
                     action,
                     vault_address,
                 } => {
-                    let request = action.twap;
-
-                    // ----------------------------------------------------------------
-                    // ensure that the frequency is between 1 and 3600 seconds; 1s to 1hr
-                    if request.frequency < 1 || request.frequency > 3600 {
-                        return Ok(HttpResponse::BadRequest().json(Response {
-                            success: false,
-                            data: None::<String>,
-                            msg: Some("Frequency must be between 1 and 3600 seconds".into()),
-                        }));
-                    }
-
-                    // ensure runtime is between 2 and 86400s; 2s to 24hrs
-                    if request.runtime < 2 || request.runtime > 86400 {
-                        return Ok(HttpResponse::BadRequest().json(Response {
-                            success: false,
-                            data: None::<String>,
-                            msg: Some("Running time must be between 2 and 86400 seconds".into()),
-                        }));
-                    }
+                    submit_twap(
+                        &stores,
+                        &sender,
+                        user,
+                        agent,
+                        action.twap,
+                        Algorithm::Twap,
+                        vault_address,
+                    )
+                    .await?
+                }
+                Exchange::VwapOrder {
+                    action,
+                    vault_address,
+                } => {
+                    let request = action.vwap;
+                    let algorithm = Algorithm::Vwap {
+                        lookback_days: request.lookback_days,
+                    };
 
-                    // ----------------------------------------------------------------
+                    submit_twap(
+                        &stores,
+                        &sender,
+                        user,
+                        agent,
+                        request.twap,
+                        algorithm,
+                        vault_address,
+                    )
+                    .await?
+                }
+                Exchange::PovOrder {
+                    action,
+                    vault_address,
+                } => {
+                    let request = action.pov;
+                    let algorithm = Algorithm::Pov {
+                        participation: request.participation,
+                        min_slice_sz: request.min_slice_sz,
+                        max_slice_sz: request.max_slice_sz,
+                    };
 
-                    match sender
-                        .send(InternalRequest::TwapOrder {
-                            request,
-                            agent,
-                            vault_address,
-                        })
-                        .await
-                    {
-                        Ok(_) => HttpResponse::Created().json(Response {
-                            success: true,
-                            data: "Created".into(),
-                            msg: None,
-                        }),
-                        Err(e) => {
-                            tracing::error!("Failed to send twap order: {:#?}", e);
-
-                            HttpResponse::InternalServerError().json(Response {
-                                success: false,
-                                data: None::<String>,
-                                msg: Some("Failed to send twap order".into()),
-                            })
-                        }
-                    }
+                    submit_twap(
+                        &stores,
+                        &sender,
+                        user,
+                        agent,
+                        request.twap,
+                        algorithm,
+                        vault_address,
+                    )
+                    .await?
                 }
             }
         }
@@ -632,6 +1017,58 @@ pub async fn hyperliquid(
     })
 }
 
+/// Validate a TWAP, VWAP or POV order, register it and hand it to the TWAP
+/// worker, answering with its id. The three only differ in how `algorithm`
+/// sizes the slices.
+async fn submit_twap(
+    stores: &Stores,
+    sender: &Sender<InternalRequest>,
+    user: Address,
+    agent: Arc<LocalWallet>,
+    request: TwapOrderRequest,
+    algorithm: Algorithm,
+    vault_address: Option<Address>,
+) -> Result<HttpResponse> {
+    if let Err(msg) = request
+        .validate()
+        .and_then(|()| algorithm.validate(&request))
+    {
+        return Ok(HttpResponse::BadRequest().json(Response {
+            success: false,
+            data: None::<String>,
+            msg: Some(msg),
+        }));
+    }
+
+    let id = Twaps::submit(
+        &stores.twaps,
+        user,
+        agent,
+        request,
+        algorithm,
+        vault_address,
+    )
+    .await?;
+
+    match sender.send(InternalRequest::TwapOrder { id }).await {
+        Ok(_) => Ok(HttpResponse::Created().json(Response {
+            success: true,
+            data: Some(id),
+            msg: None,
+        })),
+        Err(e) => {
+            tracing::error!("Failed to send {} order: {:#?}", algorithm.name(), e);
+            Twaps::discard(id).await;
+
+            Ok(HttpResponse::InternalServerError().json(Response {
+                success: false,
+                data: None::<String>,
+                msg: Some(format!("Failed to send {} order", algorithm.name())),
+            }))
+        }
+    }
+}
+
 /// Filters a list of orders based on their risk value.
 ///
 /// This function evaluates each order in the given vector of `OrderRequest` objects.
@@ -21,15 +21,35 @@ pub struct Config {
     /// Secret used to sign session cookies.
     pub cookie_key: String,
 
+    /// Secret the agent keys persisted with queued orders are encrypted under.
+    /// Changing it makes previously stored entries unreadable.
+    pub agent_key_secret: String,
+
     /// CCXT service URL for v2 implementation.
     #[serde(default = "default_ccxt_service_url")]
     pub ccxt_service_url: String,
+
+    /// Maximum number of TWAPs of a single user executing at the same time.
+    #[serde(default = "default_max_twaps_per_user")]
+    pub max_twaps_per_user: usize,
+
+    /// Maximum number of TWAPs executing at the same time across all users.
+    #[serde(default = "default_max_twaps")]
+    pub max_twaps: usize,
 }
 
 fn default_ccxt_service_url() -> String {
     "http://localhost:4001".to_string()
 }
 
+fn default_max_twaps_per_user() -> usize {
+    5
+}
+
+fn default_max_twaps() -> usize {
+    100
+}
+
 impl Config {
     /// Build a configuration instance using environment variables and `.env` fallbacks. This
     /// method is used from `main` so it bubbles up detailed context errors when things go wrong.
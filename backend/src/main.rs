@@ -1,4 +1,4 @@
-use std::{env, net::TcpListener, time::Duration};
+use std::{env, net::TcpListener, sync::Arc, time::Duration};
 
 use actix::spawn;
 use actix_cors::Cors;
@@ -11,28 +11,24 @@ use actix_web::{
 use anyhow::Context;
 use backend::{
     api, log,
-    model::hyperliquid::{InternalRequest, QueueElem},
+    model::hyperliquid::{CloseReason, ClosedCondOrder, InternalRequest},
+    service::{
+        bracket::{BracketStore, Brackets},
+        cipher::RecordCipher,
+        clock::{Clock, SystemClock},
+        grid::{GridBots, GridStore},
+        queue::{CondQueue, QueueStore, Wakeups},
+        store::{self, Stores},
+        twap::{TwapLimits, TwapScheduler, TwapStore, Twaps},
+    },
     ws, Config,
 };
 
-use hyperliquid::{
-    types::{
-        exchange::{
-            request::{Limit, OrderRequest, OrderType, Tif},
-            response::Response,
-        },
-        info::response::AssetContext,
-        Chain,
-    },
-    utils::{parse_price, parse_size},
-    Exchange, Hyperliquid, Info,
-};
+use hyperliquid::{types::Chain, Exchange, Hyperliquid, Info};
 
-use tokio::sync::{mpsc, RwLock};
+use tokio::sync::mpsc;
 use tracing_actix_web::TracingLogger;
 
-const SLIPPAGE: f64 = 0.03;
-
 /// Application entry point that wires together configuration, logging, HTTP routing, and
 /// background workers. This function is intentionally linear so newcomers can follow the boot
 /// sequence from loading configuration all the way to serving requests.
@@ -67,123 +63,46 @@ async fn main() -> anyhow::Result<()> {
 
     let chain = Chain::Arbitrum;
 
-    // Dedicated worker that consumes internal TWAP requests from the mpsc channel and executes
-    // them against Hyperliquid on a cadence derived from the payload parameters.
+    // Records that sign on a user's behalf after a restart are sealed with the server secret and
+    // share one Redis connection.
+    let cipher = RecordCipher::new(&config_data.agent_key_secret);
+    let connection = store::connect(&config_data.redis_url).await?;
+
+    // Reload the TWAPs stored before the last shutdown, cancelling the slices interrupted ones
+    // left open; the ones that never started are handed to the worker again below.
+    let twap_store = TwapStore::new(connection.clone(), cipher.clone());
+    let requeued = Twaps::restore(&twap_store, chain).await?;
+
+    tracing::info!("Queued {} restored TWAPs again", requeued.len());
+
+    // Dedicated worker that consumes internal TWAP requests from the mpsc channel. Every TWAP
+    // runs as its own task, within the configured per-user and global limits; the others wait
+    // queued until a running one ends.
+    let twap_limits = TwapLimits {
+        per_user: config_data.max_twaps_per_user,
+        total: config_data.max_twaps,
+    };
     spawn(async move {
-        while let Some(request) = rx.recv().await {
-            match request {
-                InternalRequest::TwapOrder {
-                    request,
-                    agent,
-                    vault_address,
-                } => {
-                    tracing::info!("Received twap order request: {:#?}", request);
-
-                    let info: Info = Hyperliquid::new(chain);
-                    let exchange: Exchange = Hyperliquid::new(chain);
-
-                    // auto calculate order count based on minutes
-                    let order_count = request.runtime / 60 * 2 + 1;
-
-                    let sz = request.sz / order_count as f64;
-
-                    let interval = Duration::from_secs(request.frequency);
-
-                    for i in 1..=order_count {
-                        let (limit_px, sz_decimals) = {
-                            let ctxs = match info.contexts().await {
-                                Ok(ctxs) => ctxs,
-                                Err(err) => {
-                                    tracing::error!("{:#?}", err);
-                                    return;
-                                }
-                            };
-
-                            let asset_ctxs = match ctxs.get(1) {
-                                Some(AssetContext::Ctx(ctxs)) => ctxs,
-                                _ => {
-                                    tracing::error!("Failed to get asset contexts");
-                                    return;
-                                }
-                            };
-
-                            let mark_px = match asset_ctxs.get(request.asset as usize) {
-                                Some(ctx) => &ctx.mark_px,
-                                _ => {
-                                    tracing::error!("Failed to get mark price");
-                                    return;
-                                }
-                            };
-
-                            let mark_px: f64 = match mark_px.parse() {
-                                Ok(px) => px,
-                                Err(err) => {
-                                    tracing::error!("{:#?}", err);
-                                    return;
-                                }
-                            };
-
-                            let limit_px =
-                                mark_px * (1.0 + if request.is_buy { SLIPPAGE } else { -SLIPPAGE }); // 3% slippage
-
-                            let universe = match ctxs.first() {
-                                Some(AssetContext::Meta(meta)) => &meta.universe,
-                                _ => {
-                                    tracing::error!("Failed to get universe");
-                                    return;
-                                }
-                            };
-
-                            let sz_decimals = match universe.get(request.asset as usize) {
-                                Some(asset) => asset.sz_decimals as u32,
-                                _ => {
-                                    tracing::error!("Failed to get sz_decimals");
-                                    return;
-                                }
-                            };
-
-                            (limit_px, sz_decimals)
-                        };
-
-                        let order = OrderRequest {
-                            asset: request.asset,
-                            is_buy: request.is_buy,
-                            limit_px: parse_price(limit_px),
-                            sz: parse_size(sz, sz_decimals),
-                            reduce_only: request.reduce_only,
-                            order_type: OrderType::Limit(Limit { tif: Tif::Ioc }),
-                            cloid: None,
-                        };
-
-                        match exchange
-                            .place_order(agent.clone(), vec![order], vault_address)
-                            .await
-                        {
-                            Ok(order) => match order {
-                                Response::Err(err) => {
-                                    println!("{:#?}", err);
-                                    return;
-                                }
-                                Response::Ok(order) => {
-                                    println!("Order placed: {:#?}", order);
-                                    println!(
-                                        "{} order was successfully placed.\n",
-                                        if request.is_buy { "Buy" } else { "Sell" }
-                                    );
-                                }
-                            },
-                            Err(err) => {
-                                println!("{:#?}", err);
-                                return;
-                            }
-                        }
-                        if i != order_count {
-                            println!("Waiting for {} minutes", interval.as_secs() / 60);
-                            println!("{}", "-".repeat(5));
-                            tokio::time::sleep(interval).await;
-                        }
+        let mut scheduler = TwapScheduler::new(chain, twap_limits);
+        for id in requeued {
+            scheduler.submit(id);
+        }
+
+        loop {
+            scheduler.admit().await;
+
+            // Both branches only do bookkeeping that cannot be interrupted halfway; slots are
+            // handed out above, outside the race.
+            tokio::select! {
+                request = rx.recv() => match request {
+                    Some(InternalRequest::TwapOrder { id }) => {
+                        tracing::info!("Received twap order {}", id);
+
+                        scheduler.submit(id);
                     }
-                }
+                    None => break,
+                },
+                _ = scheduler.reap() => {}
             }
         }
     });
@@ -195,49 +114,87 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
-    let queue: RwLock<Vec<QueueElem>> = RwLock::new(vec![]);
+    // Reload conditional orders persisted before the last shutdown and rebuild the stream
+    // subscriptions they rely on, so restored triggers fire exactly as they would have.
+    let wakeups = Arc::new(Wakeups::default());
+    let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+    let queue_store = QueueStore::new(connection.clone(), cipher.clone());
+    let history = queue_store.clone();
+    let queue = CondQueue::restore(queue_store, wakeups.clone(), clock.clone(), chain).await?;
+
+    tracing::info!(
+        "Restored {} queued conditional orders",
+        queue.read().await.len()
+    );
+
+    // Resume the grid bots that were running before the last shutdown; fills that happened in
+    // the meantime are picked up from each bot's stored fill cursor.
+    let grid_store = GridStore::new(connection.clone(), cipher.clone());
+    let grids = GridBots::restore(grid_store.clone(), chain).await?;
+
+    tracing::info!("Resumed {} grid bots", grids);
+
+    // Resume tracking the bracket entries that were still resting, so fills that happened in the
+    // meantime get their take-profit/stop-loss legs.
+    let bracket_store = BracketStore::new(connection, cipher);
+    let brackets = Brackets::restore(bracket_store.clone(), chain).await?;
+
+    tracing::info!("Resumed {} bracket orders", brackets);
+
     let queue = web::Data::new(queue);
+    let stores = web::Data::new(Stores {
+        brackets: bracket_store,
+        grids: grid_store,
+        twaps: twap_store,
+    });
+    let stores_2 = stores.clone();
     let queue_2 = queue.clone();
 
-    // Background reconciliation loop that periodically checks queued tasks (e.g. delayed
-    // executions) and drives them to completion while pruning finished entries.
+    // Event-driven evaluation loop: sleeps until a stream that some queued condition depends
+    // on changes or the next time boundary (time condition or expiry) is reached, then
+    // re-checks only the conditions that could have flipped and executes the ones that fired.
+    let twap_sender = tx.clone();
     tokio::spawn(async move {
         let exchange: Exchange = Hyperliquid::new(chain);
+        let info: Info = Hyperliquid::new(chain);
 
         loop {
-            let queue_r = queue_2.read().await;
+            let deadline = queue_2.next_deadline().await;
 
-            let mut indices_to_remove = Vec::new();
+            let topics = match deadline {
+                Some(deadline) => {
+                    let delay = Duration::from_millis(deadline.saturating_sub(clock.now()));
 
-            for (i, elem) in queue_r.iter().enumerate() {
-                match elem.check().await {
-                    Ok(true) => {
-                        indices_to_remove.push(i);
-                    }
-                    Err(err) => {
-                        tracing::error!("{:?}", err);
+                    tokio::select! {
+                        topics = wakeups.wait() => topics,
+                        _ = tokio::time::sleep(delay) => Default::default(),
                     }
-                    _ => {}
                 }
-            }
+                None => wakeups.wait().await,
+            };
 
-            drop(queue_r);
+            let triggered = queue_2.take_triggered(&topics).await;
 
-            if !indices_to_remove.is_empty() {
-                let mut queue_w = queue_2.write().await;
+            for elem in triggered {
+                let mut closed = ClosedCondOrder::new(&elem, CloseReason::Executed, clock.now());
+
+                let outcome = elem
+                    .execute(&exchange, &info, &stores_2.twaps, &twap_sender)
+                    .await;
+                if let Some(error) = &outcome.error {
+                    tracing::error!("Conditional order {} failed: {}", closed.id, error);
+                }
+                closed.outcome = Some(outcome);
 
-                for &i in indices_to_remove.iter().rev() {
-                    let elem = queue_w.remove(i);
-                    drop(queue_w);
-                    elem.execute(&exchange).await;
-                    queue_w = queue_2.write().await;
+                if let Err(err) = history.record_closed(&closed).await {
+                    tracing::error!("{:?}", err);
                 }
             }
         }
     });
 
     // Share global application state with the Actix data registry so request handlers can access
-    // the Hyperliquid chain choice, TWAP sender, and queue storage.
+    // the Hyperliquid chain choice, TWAP sender, queue and order service storage.
     let chain = web::Data::new(chain);
     let sender = web::Data::new(tx);
 
@@ -272,6 +229,7 @@ async fn main() -> anyhow::Result<()> {
             .app_data(chain.clone())
             .app_data(sender.clone())
             .app_data(queue.clone())
+            .app_data(stores.clone())
     })
     .listen(listener)?
     .run()
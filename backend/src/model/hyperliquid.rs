@@ -8,23 +8,43 @@
 use ethers::{
     signers::LocalWallet,
     types::{Address, Signature, U256},
+    utils::hex,
 };
 use std::{collections::HashMap, sync::Arc};
+use tokio::sync::mpsc::Sender;
 use tokio::sync::oneshot;
 use tokio::sync::watch;
 
-use anyhow::anyhow;
-use async_trait::async_trait;
-use hyperliquid::types::{
-    exchange::request::{CancelRequest, OrderRequest},
-    info::request::CandleSnapshotRequest,
+use hyperliquid::{
+    types::{
+        exchange::{
+            request::{CancelRequest, Limit, OrderRequest, OrderType, Tif},
+            response::Response as ExchangeResponse,
+        },
+        info::{request::CandleSnapshotRequest, response::AssetContext},
+        API,
+    },
+    utils::{parse_price, parse_size},
 };
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::service::{
+    clock::Clock,
+    indicator::IndicatorSpec,
+    market::MIN_NOTIONAL,
+    twap::{TwapStore, Twaps},
+};
+
+mod condition;
+
+pub use condition::*;
+
 lazy_static! {
-    /// Shared cache of live websocket subscriptions keyed by symbol.
-    pub static ref CONNECTIONS: Arc<Mutex<HashMap<String, ChannelConnection>>> =
+    /// Shared cache of live websocket subscriptions keyed by topic.
+    pub static ref CONNECTIONS: Arc<Mutex<HashMap<Subscribe, ChannelConnection>>> =
         Arc::new(Mutex::new(HashMap::new()));
 }
 
@@ -84,6 +104,55 @@ pub enum Info {
     /// Query Hyperliquid spot metadata from the info endpoint.
     SpotMeta,
     Liquidity { req: LiquidityRequest },
+    /// List the conditional orders the session's user still has queued.
+    CondOrders,
+    /// Inspect a single queued conditional order of the session's user.
+    CondOrder {
+        /// Id returned when the order was queued.
+        id: Uuid,
+    },
+    /// Compute a technical indicator over a candle range, with the same
+    /// implementation used by indicator conditions.
+    Indicator {
+        /// Indicator query parameters.
+        req: IndicatorRequest,
+    },
+    /// List the session user's conditional orders that are no longer queued,
+    /// most recent first, with the reason each one left the queue.
+    CondOrderHistory,
+    /// List the session user's bracket orders whose entry is still being
+    /// tracked.
+    BracketOrders,
+    /// List the session user's iceberg orders that are still working.
+    IcebergOrders,
+    /// List the session user's chase-limit orders, most recent first.
+    ChaseOrders,
+    /// List the session user's grid bots, running and stopped, most recent
+    /// first.
+    GridBots,
+    /// List the session user's TWAPs with their progress, most recent first.
+    TwapOrders,
+    /// Inspect a single TWAP of the session's user.
+    TwapOrder {
+        /// Id returned when the TWAP was submitted.
+        id: Uuid,
+    },
+}
+
+/// Parameters of the `indicator` info request.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndicatorRequest {
+    /// Symbol whose candles feed the indicator.
+    pub coin: String,
+    /// Candle interval, e.g. `1h`.
+    pub interval: String,
+    /// Indicator and its parameters.
+    pub indicator: IndicatorSpec,
+    /// First bar close to return, in milliseconds since the Unix epoch.
+    pub start_time: u64,
+    /// End of the range, in milliseconds since the Unix epoch.
+    pub end_time: u64,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq)]
@@ -166,7 +235,7 @@ pub struct DepthCalculationResponse {
 }
 
 /// Wrapper around one or more Hyperliquid order submissions.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Order {
     /// Batch of limit/market orders to place via the exchange endpoint.
@@ -192,7 +261,7 @@ pub struct SubAccountModify {
 }
 
 /// Request payload for transferring funds to or from a sub-account.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SubAccountTransfer {
     /// Direction flag: `true` when depositing into the sub-account.
@@ -204,7 +273,7 @@ pub struct SubAccountTransfer {
 }
 
 /// Parameters for updating account-wide leverage settings.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UpdateLeverage {
     /// Asset identifier to apply leverage to.
@@ -228,7 +297,7 @@ pub struct UpdateIsolatedMargin {
 }
 
 /// TWAP execution configuration submitted by the frontend.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TwapOrderRequest {
     /// Asset identifier to trade.
@@ -246,25 +315,203 @@ pub struct TwapOrderRequest {
     /// Target size per execution slice.
     #[serde(rename = "s", alias = "sz")]
     pub sz: f64,
-    /// Whether to randomize order submission intervals.
+    /// Whether to jitter the intervals and sizes of the slices.
     #[serde(rename = "t", alias = "randomize")]
     pub randomize: bool,
     /// Frequency (in seconds) between slices.
     #[serde(rename = "f", alias = "frequency")]
     pub frequency: u64,
+    /// How slices are priced off the order book.
+    #[serde(default)]
+    pub pricing: TwapPricing,
+    /// Maximum distance of a slice's limit price from the mid price, as a
+    /// fraction (`0.03` is 3%). Slices priced beyond it are deferred.
+    #[serde(default = "default_twap_slippage_cap")]
+    pub slippage_cap: f64,
+}
+
+fn default_twap_slippage_cap() -> f64 {
+    0.03
+}
+
+/// Pricing mode of TWAP slices, relative to the live order book.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "camelCase")]
+pub enum TwapPricing {
+    /// Post-only at the best price of the TWAP's own side. Unfilled size is
+    /// cancelled before the next slice and carried over.
+    Passive,
+    /// Immediate-or-cancel at the best opposite price plus `ticks` ticks.
+    Marketable { ticks: u32 },
+    /// Immediate-or-cancel at the mid price plus the slippage cap.
+    #[default]
+    Ioc,
+}
+
+impl TwapOrderRequest {
+    /// Check the frequency, runtime and slippage bounds enforced for every
+    /// TWAP.
+    pub fn validate(&self) -> Result<(), String> {
+        // ensure that the frequency is between 1 and 3600 seconds; 1s to 1hr
+        if self.frequency < 1 || self.frequency > 3600 {
+            return Err("Frequency must be between 1 and 3600 seconds".into());
+        }
+
+        // ensure runtime is between 2 and 86400s; 2s to 24hrs
+        if self.runtime < 2 || self.runtime > 86400 {
+            return Err("Running time must be between 2 and 86400 seconds".into());
+        }
+
+        if !(self.slippage_cap > 0.0 && self.slippage_cap < 1.0) {
+            return Err("Slippage cap must be between 0 and 1".into());
+        }
+
+        Ok(())
+    }
 }
 
 /// Envelope carrying the TWAP order request.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Twap {
     /// Nested TWAP order configuration.
     pub twap: TwapOrderRequest,
 }
 
-/// Batch cancellation payload forwarded to Hyperliquid.
+/// VWAP execution configuration: a TWAP whose slices are sized by the
+/// asset's intraday volume profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VwapOrderRequest {
+    /// Asset, side, size and timing, as for a TWAP.
+    #[serde(flatten)]
+    pub twap: TwapOrderRequest,
+    /// Days of candle history the volume profile is averaged over.
+    #[serde(default = "default_vwap_lookback_days")]
+    pub lookback_days: u32,
+}
+
+fn default_vwap_lookback_days() -> u32 {
+    7
+}
+
+/// Envelope carrying the VWAP order request.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Vwap {
+    /// Nested VWAP order configuration.
+    pub vwap: VwapOrderRequest,
+}
+
+/// Percentage-of-volume (POV) execution configuration: child orders follow a
+/// share of the volume traded on the asset instead of a fixed schedule.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PovOrderRequest {
+    /// Asset, side and size as for a TWAP. `runtime` is the deadline and
+    /// `frequency` the shortest interval between child orders.
+    #[serde(flatten)]
+    pub twap: TwapOrderRequest,
+    /// Share of the traded volume to target, as a fraction (`0.1` is 10%).
+    pub participation: f64,
+    /// Smallest child order; less waits for more volume.
+    #[serde(default)]
+    pub min_slice_sz: f64,
+    /// Largest child order.
+    #[serde(default)]
+    pub max_slice_sz: Option<f64>,
+}
+
+/// Envelope carrying the POV order request.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Pov {
+    /// Nested POV order configuration.
+    pub pov: PovOrderRequest,
+}
+
+/// How the slices of a TWAP job are sized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Algorithm {
+    /// Evenly, before jitter.
+    #[default]
+    Twap,
+    /// By the volume expected while each slice is out.
+    #[serde(rename_all = "camelCase")]
+    Vwap { lookback_days: u32 },
+    /// By the volume traded since the previous slice.
+    #[serde(rename_all = "camelCase")]
+    Pov {
+        participation: f64,
+        min_slice_sz: f64,
+        max_slice_sz: Option<f64>,
+    },
+}
+
+impl Algorithm {
+    /// Check the parameters of the algorithm for `request`, whose TWAP
+    /// bounds are checked by [`TwapOrderRequest::validate`].
+    pub fn validate(&self, request: &TwapOrderRequest) -> Result<(), String> {
+        match *self {
+            Algorithm::Twap => {}
+            Algorithm::Vwap { lookback_days } => {
+                // ensure lookback is between 1 and 30 days
+                if !(1..=30).contains(&lookback_days) {
+                    return Err("Lookback must be between 1 and 30 days".into());
+                }
+            }
+            Algorithm::Pov {
+                participation,
+                min_slice_sz,
+                max_slice_sz,
+            } => {
+                if request.randomize {
+                    return Err("POV orders cannot be randomized".into());
+                }
+
+                // ensure participation is above 0 and at most 50%
+                if !(participation > 0.0 && participation <= 0.5) {
+                    return Err("Participation must be above 0 and at most 0.5".into());
+                }
+
+                if min_slice_sz < 0.0 {
+                    return Err("Minimum slice size cannot be negative".into());
+                }
+                if let Some(max_slice_sz) = max_slice_sz {
+                    if max_slice_sz <= 0.0 || max_slice_sz < min_slice_sz {
+                        return Err(
+                            "Maximum slice size must be positive and at least the minimum".into(),
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lowercase name of the algorithm, for messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Algorithm::Twap => "twap",
+            Algorithm::Vwap { .. } => "vwap",
+            Algorithm::Pov { .. } => "pov",
+        }
+    }
+}
+
+/// Identifies a TWAP to pause, resume or cancel.
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct TwapOrderId {
+    /// Id returned when the TWAP was submitted.
+    pub id: Uuid,
+}
+
+/// Batch cancellation payload forwarded to Hyperliquid.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Cancel {
     /// Batch of cancel requests to forward to Hyperliquid.
     pub cancels: Vec<CancelRequest>,
@@ -372,6 +619,41 @@ pub enum Exchange {
         vault_address: Option<Address>,
     },
 
+    /// Submit a volume-weighted average price (VWAP) execution, run by the
+    /// TWAP worker.
+    #[serde(rename_all = "camelCase")]
+    VwapOrder {
+        /// VWAP configuration payload.
+        action: Vwap,
+        /// Vault executing the VWAP orders.
+        vault_address: Option<Address>,
+    },
+
+    /// Submit a percentage-of-volume (POV) execution, run by the TWAP worker.
+    #[serde(rename_all = "camelCase")]
+    PovOrder {
+        /// POV configuration payload.
+        action: Pov,
+        /// Vault executing the POV orders.
+        vault_address: Option<Address>,
+    },
+
+    /// Stop sending slices of a TWAP until it is resumed.
+    PauseTwapOrder {
+        /// Id of the TWAP.
+        action: TwapOrderId,
+    },
+    /// Continue a paused TWAP.
+    ResumeTwapOrder {
+        /// Id of the TWAP.
+        action: TwapOrderId,
+    },
+    /// Stop a TWAP for good.
+    CancelTwapOrder {
+        /// Id of the TWAP.
+        action: TwapOrderId,
+    },
+
     /// Conditional order executed when a supplied `Condition` evaluates true.
     #[serde(rename_all = "camelCase")]
     CondOrder {
@@ -383,22 +665,576 @@ pub enum Exchange {
         source: Source,
         /// Vault executing the resulting action.
         vault_address: Option<Address>,
+        /// Good-till-time expiry in milliseconds since the Unix epoch. The
+        /// order is dropped unexecuted once this passes.
+        expires_at: Option<u64>,
+        /// One-cancels-other group. When any order of the user's group
+        /// executes, the other members are cancelled.
+        group: Option<String>,
+    },
+    /// Queue a trailing stop that closes (part of) a position once the book
+    /// retraces by the trail.
+    #[serde(rename_all = "camelCase")]
+    TrailingStopOrder {
+        /// Trailing stop parameters.
+        action: TrailingStopOrder,
+        /// Vault holding the position, if any.
+        vault_address: Option<Address>,
+        /// Good-till-time expiry in milliseconds since the Unix epoch.
+        expires_at: Option<u64>,
+        /// One-cancels-other group, e.g. shared with a take-profit.
+        group: Option<String>,
+    },
+    /// Place an entry order whose take-profit/stop-loss legs are attached by
+    /// the server, sized to the entry's fills.
+    #[serde(rename_all = "camelCase")]
+    BracketOrder {
+        /// Entry and legs of the bracket.
+        action: BracketOrder,
+        /// Vault executing the orders, if any.
+        vault_address: Option<Address>,
+    },
+    /// Cancel a bracket's entry together with its legs.
+    CancelBracketOrder {
+        /// Cancellation payload.
+        action: CancelBracketOrder,
+    },
+    /// Place a ladder of limit orders spread over a price range.
+    #[serde(rename_all = "camelCase")]
+    ScaledOrder {
+        /// Price range, order count and size distribution of the ladder.
+        action: ScaledOrder,
+        /// Vault executing the orders, if any.
+        vault_address: Option<Address>,
+    },
+    /// Rest a post-only order at the touch and reprice it as the touch moves
+    /// away until it fills.
+    #[serde(rename_all = "camelCase")]
+    ChaseOrder {
+        /// Size and chase bounds of the order.
+        action: ChaseOrder,
+        /// Vault executing the order, if any.
+        vault_address: Option<Address>,
+    },
+    /// Cancel a chase-limit order.
+    CancelChaseOrder {
+        /// Cancellation payload.
+        action: CancelChaseOrder,
+    },
+    /// Work a large limit order showing only a display quantity at a time,
+    /// replenished by the server as each clip fills.
+    #[serde(rename_all = "camelCase")]
+    IcebergOrder {
+        /// Size, price and display quantity of the iceberg.
+        action: IcebergOrder,
+        /// Vault executing the clips, if any.
+        vault_address: Option<Address>,
+    },
+    /// Cancel an iceberg's visible clip and stop replenishing it.
+    CancelIcebergOrder {
+        /// Cancellation payload.
+        action: CancelIcebergOrder,
+    },
+    /// Start a grid bot trading a price range with a ladder of buys and
+    /// sells, replacing each filled order with the opposite one a level
+    /// away.
+    #[serde(rename_all = "camelCase")]
+    GridBot {
+        /// Price range, levels and investment of the grid.
+        action: GridBotConfig,
+        /// Vault executing the orders, if any.
+        vault_address: Option<Address>,
+    },
+    /// Cancel a grid bot's resting orders and stop it.
+    StopGridBot {
+        /// Stop payload.
+        action: StopGridBot,
+    },
+    /// Replace the condition and/or action of a queued conditional order.
+    AmendCondOrder {
+        /// Amendment payload.
+        action: AmendCondOrder,
+    },
+    /// Withdraw a queued conditional order before it fires.
+    CancelCondOrder {
+        /// Cancellation payload.
+        action: CancelCondOrder,
     },
 }
 
-/// Actions executed when a [`Condition`] evaluates to `true`.
+/// Trailing stop on a perp position, queued as a conditional order with a
+/// [`Condition::TrailingStop`] condition and a [`CondAction::ClosePosition`]
+/// action.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrailingStopOrder {
+    /// Symbol whose book is followed.
+    pub symbol: String,
+    /// Asset identifier of the position.
+    pub asset: u32,
+    /// Side of the position being protected.
+    pub side: PositionSide,
+    /// Distance of the stop from the best price seen.
+    pub trail: Trail,
+    /// Size to close; the whole position when omitted.
+    pub size: Option<f64>,
+    /// Maximum slippage of the closing order as a fraction.
+    #[serde(default = "default_close_slippage")]
+    pub slippage: f64,
+}
+
+impl TrailingStopOrder {
+    /// Split the request into the condition and action of the queued order.
+    pub fn into_parts(self) -> (Condition, CondAction) {
+        let condition = Condition::TrailingStop(TrailingStop {
+            symbol: self.symbol,
+            side: self.side,
+            trail: self.trail,
+            extreme: None,
+        });
+        let action = CondAction::ClosePosition(ClosePosition {
+            asset: self.asset,
+            size: self.size,
+            slippage: self.slippage,
+        });
+
+        (condition, action)
+    }
+}
+
+/// Entry order with take-profit/stop-loss legs placed once the entry fills.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BracketOrder {
+    /// Order opening the position. Its `cloid` is assigned by the server.
+    pub entry: OrderRequest,
+    /// Trigger price of the take-profit leg.
+    pub take_profit: Option<f64>,
+    /// Trigger price of the stop-loss leg.
+    pub stop_loss: Option<f64>,
+    /// Maximum slippage of the legs' market orders, as a fraction.
+    #[serde(default = "default_close_slippage")]
+    pub slippage: f64,
+}
+
+impl BracketOrder {
+    /// Reject brackets whose legs could not protect the entry.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.entry.reduce_only {
+            return Err("Bracket entry cannot be reduce-only".into());
+        }
+        if self.take_profit.is_none() && self.stop_loss.is_none() {
+            return Err("Bracket needs a take-profit or a stop-loss".into());
+        }
+        if [self.take_profit, self.stop_loss]
+            .into_iter()
+            .flatten()
+            .any(|px| !px.is_finite() || px <= 0.0)
+        {
+            return Err("Bracket trigger prices must be positive".into());
+        }
+        if let (Some(take_profit), Some(stop_loss)) = (self.take_profit, self.stop_loss) {
+            // A long takes profit above its stop, a short below it.
+            if (take_profit > stop_loss) != self.entry.is_buy {
+                return Err("Take-profit and stop-loss are on the wrong sides".into());
+            }
+        }
+        if !(0.0..1.0).contains(&self.slippage) {
+            return Err("Slippage must be between 0 and 1".into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Identifies a bracket order to tear down.
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct CancelBracketOrder {
+    /// Id returned when the bracket was placed.
+    pub id: Uuid,
+}
+
+/// Ladder of `count` limit orders evenly spaced from `start_px` to `end_px`,
+/// splitting `sz` according to `distribution`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ScaledOrder {
+    /// Asset identifier to trade.
+    pub asset: u32,
+    /// Side of the orders.
+    pub is_buy: bool,
+    /// Price of the first order.
+    pub start_px: f64,
+    /// Price of the last order.
+    pub end_px: f64,
+    /// Total size over all orders.
+    pub sz: f64,
+    /// Number of orders.
+    pub count: u32,
+    /// How `sz` is split over the orders.
+    #[serde(default)]
+    pub distribution: Distribution,
+    /// Whether the orders only reduce the position.
+    #[serde(default)]
+    pub reduce_only: bool,
+    /// Whether the orders are post-only (add liquidity only).
+    #[serde(default)]
+    pub post_only: bool,
+}
+
+/// Size distribution over the orders of a ladder.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Distribution {
+    /// Same size for every order.
+    #[default]
+    Flat,
+    /// Sizes growing linearly toward one end: `1, 2, ..., count` parts.
+    Linear { toward: LadderEnd },
+    /// Sizes growing by `ratio` from one order to the next toward one end.
+    Exponential { toward: LadderEnd, ratio: f64 },
+}
+
+/// End of a ladder.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LadderEnd {
+    /// The order at `start_px`.
+    Start,
+    /// The order at `end_px`.
+    End,
+}
+
+impl ScaledOrder {
+    /// Reject ladders that cannot be built.
+    pub fn validate(&self) -> Result<(), String> {
+        if [self.start_px, self.end_px]
+            .iter()
+            .any(|px| !px.is_finite() || *px <= 0.0)
+        {
+            return Err("Ladder prices must be positive".into());
+        }
+        if self.start_px == self.end_px {
+            return Err("Ladder start and end prices must differ".into());
+        }
+        if !self.sz.is_finite() || self.sz <= 0.0 {
+            return Err("Ladder size must be positive".into());
+        }
+
+        // ensure count is between 2 and 50 orders
+        if self.count < 2 || self.count > 50 {
+            return Err("Order count must be between 2 and 50".into());
+        }
+
+        if let Distribution::Exponential { ratio, .. } = self.distribution {
+            if !ratio.is_finite() || ratio <= 1.0 {
+                return Err("Exponential ratio must be above 1".into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Post-only limit order kept at the best price of its side until it fills,
+/// it would move more than `max_chase` from where it started or `timeout`
+/// expires.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ChaseOrder {
+    /// Asset identifier to trade.
+    pub asset: u32,
+    /// Side of the order.
+    pub is_buy: bool,
+    /// Size to fill.
+    pub sz: f64,
+    /// Whether the order only reduces the position.
+    #[serde(default)]
+    pub reduce_only: bool,
+    /// Furthest the order may be repriced from the touch it started at.
+    pub max_chase: Trail,
+    /// Seconds after which the order is cancelled.
+    pub timeout: u64,
+}
+
+impl ChaseOrder {
+    /// Check the size, chase distance and timeout bounds.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.sz.is_finite() || self.sz <= 0.0 {
+            return Err("Chase size must be positive".into());
+        }
+        self.max_chase.validate().map_err(|err| err.to_string())?;
+
+        // ensure timeout is between 1 and 86400s; 1s to 24hrs
+        if self.timeout < 1 || self.timeout > 86400 {
+            return Err("Timeout must be between 1 and 86400 seconds".into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Identifies a chase-limit order to cancel.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelChaseOrder {
+    /// Id returned when the chase order was placed.
+    pub id: Uuid,
+}
+
+/// Limit order worked in clips of `display_sz`, so only a clip rests on the
+/// book at a time.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IcebergOrder {
+    /// Asset identifier to trade.
+    pub asset: u32,
+    /// Side of the order.
+    pub is_buy: bool,
+    /// Limit price of the clips; with [`Reprice::Touch`], the furthest price a
+    /// clip is placed at.
+    pub limit_px: f64,
+    /// Total size to fill.
+    pub sz: f64,
+    /// Size shown on the book at a time.
+    pub display_sz: f64,
+    /// Whether the clips only reduce the position.
+    #[serde(default)]
+    pub reduce_only: bool,
+    /// Whether the clips are post-only (add liquidity only).
+    #[serde(default)]
+    pub post_only: bool,
+    /// Price of each new clip.
+    #[serde(default)]
+    pub reprice: Reprice,
+}
+
+/// Price an iceberg places its next clip at.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Reprice {
+    /// The limit price.
+    #[default]
+    Same,
+    /// The best price of the iceberg's own side, capped at the limit price.
+    Touch,
+}
+
+impl IcebergOrder {
+    /// Reject icebergs whose clips could not be placed.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.limit_px.is_finite() || self.limit_px <= 0.0 {
+            return Err("Iceberg limit price must be positive".into());
+        }
+        if !(self.sz.is_finite() && self.sz > 0.0 && self.display_sz > 0.0) {
+            return Err("Iceberg sizes must be positive".into());
+        }
+        if self.display_sz > self.sz {
+            return Err("Display size cannot exceed the total size".into());
+        }
+        if self.display_sz * self.limit_px < MIN_NOTIONAL {
+            return Err(format!(
+                "Display size is below the ${} minimum order value",
+                MIN_NOTIONAL
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Identifies an iceberg order to cancel.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelIcebergOrder {
+    /// Id returned when the iceberg was placed.
+    pub id: Uuid,
+}
+
+/// Grid of `levels` prices evenly spaced from `lower_px` to `upper_px`, with
+/// `investment` split over its orders.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GridBotConfig {
+    /// Asset identifier to trade.
+    pub asset: u32,
+    /// Lowest grid price.
+    pub lower_px: f64,
+    /// Highest grid price.
+    pub upper_px: f64,
+    /// Number of grid prices, both ends included.
+    pub levels: u32,
+    /// Order value over the whole grid, in USD.
+    pub investment: f64,
+    /// Whether the orders are post-only (add liquidity only).
+    #[serde(default)]
+    pub post_only: bool,
+}
+
+impl GridBotConfig {
+    /// Reject grids that cannot be laid out.
+    pub fn validate(&self) -> Result<(), String> {
+        if [self.lower_px, self.upper_px]
+            .iter()
+            .any(|px| !px.is_finite() || *px <= 0.0)
+        {
+            return Err("Grid prices must be positive".into());
+        }
+        if self.lower_px >= self.upper_px {
+            return Err("Grid lower price must be below the upper price".into());
+        }
+        if !self.investment.is_finite() || self.investment <= 0.0 {
+            return Err("Grid investment must be positive".into());
+        }
+
+        // ensure levels is between 3 and 100 prices
+        if self.levels < 3 || self.levels > 100 {
+            return Err("Grid levels must be between 3 and 100".into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Identifies a grid bot to stop.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StopGridBot {
+    /// Id returned when the grid bot was started.
+    pub id: Uuid,
+}
+
+/// Amendment applied to a queued conditional order. Omitted fields are kept.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AmendCondOrder {
+    /// Id of the queued order to amend.
+    pub id: Uuid,
+    /// New trigger predicate, e.g. the same condition with another threshold.
+    pub condition: Option<Condition>,
+    /// New action to run once the condition is met.
+    pub action: Option<CondAction>,
+}
+
+/// Identifies a queued conditional order to withdraw.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CancelCondOrder {
+    /// Id of the queued order to cancel.
+    pub id: Uuid,
+}
+
+/// Actions executed when a [`Condition`] evaluates to `true`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub enum CondAction {
     /// Submit a Hyperliquid order when the condition triggers.
     HLOrder(Order),
+    /// Cancel resting orders.
+    Cancel(Cancel),
+    /// Change the leverage of an asset.
+    UpdateLeverage(UpdateLeverage),
+    /// Move funds between the account and a sub-account.
+    SubAccountTransfer(SubAccountTransfer),
+    /// Flatten the position in an asset with a reduce-only IOC order.
+    ClosePosition(ClosePosition),
+    /// Start a TWAP on the TWAP worker.
+    TwapOrder(Twap),
+}
+
+impl CondAction {
+    /// Reject actions that would be refused when they fire.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            CondAction::TwapOrder(twap) => twap.twap.validate(),
+            CondAction::ClosePosition(close) if !(0.0..1.0).contains(&close.slippage) => {
+                Err("Slippage must be between 0 and 1".into())
+            }
+            CondAction::ClosePosition(ClosePosition {
+                size: Some(size), ..
+            }) if *size <= 0.0 => Err("Size must be positive".into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Flatten the position held in one perp asset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClosePosition {
+    /// Asset identifier of the position.
+    pub asset: u32,
+    /// Size to reduce the position by, capped at the position size. The
+    /// whole position is closed when omitted.
+    #[serde(default)]
+    pub size: Option<f64>,
+    /// Maximum distance from the mark price accepted for the IOC order, as a
+    /// fraction (`0.03` is 3%).
+    #[serde(default = "default_close_slippage")]
+    pub slippage: f64,
+}
+
+fn default_close_slippage() -> f64 {
+    0.03
+}
+
+/// Result of running a [`CondAction`], kept in the order's history record.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActionOutcome {
+    /// Whether the action was accepted.
+    pub success: bool,
+    /// Response returned by Hyperliquid, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<serde_json::Value>,
+    /// Why the action failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ActionOutcome {
+    fn failed(error: impl ToString) -> Self {
+        Self {
+            success: false,
+            response: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+impl<E: std::fmt::Display> From<Result<ExchangeResponse, E>> for ActionOutcome {
+    fn from(result: Result<ExchangeResponse, E>) -> Self {
+        match result {
+            Ok(ExchangeResponse::Ok(data)) => Self {
+                success: true,
+                response: serde_json::to_value(data).ok(),
+                error: None,
+            },
+            Ok(ExchangeResponse::Err(msg)) => Self::failed(msg),
+            Err(err) => Self::failed(err),
+        }
+    }
 }
 
 /// Queued conditional order awaiting execution by the background worker.
+///
+/// Entries are persisted as JSON by [`crate::service::queue::QueueStore`], so
+/// every field (including the agent key) must survive a serde round trip.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct QueueElem {
+    /// Stable id handed back to the user and used as the persistence key.
+    pub id: Uuid,
+    /// Hyperliquid user (the session's `Agent.user`) that queued the order.
+    pub owner: Address,
     /// Origin of the condition data (currently only Hyperliquid).
     pub source: Source,
     /// Wallet authorised to execute the resulting action.
+    #[serde(
+        serialize_with = "serialize_wallet",
+        deserialize_with = "deserialize_wallet"
+    )]
     pub agent: Arc<LocalWallet>,
     /// Action to perform once `condition` evaluates to true.
     pub action: CondAction,
@@ -406,169 +1242,489 @@ pub struct QueueElem {
     pub condition: Condition,
     /// Vault routing for the executed order, if set.
     pub vault_address: Option<Address>,
+    /// Good-till-time expiry in milliseconds since the Unix epoch.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// One-cancels-other group, scoped to `owner`.
+    #[serde(default)]
+    pub group: Option<String>,
 }
 
-/// Trait implemented by condition types to determine whether they have been
-/// satisfied.
-#[async_trait]
-pub trait Check {
-    /// Evaluate the condition, returning `true` when it has been satisfied.
-    async fn check(&self) -> anyhow::Result<bool>;
-}
-
-impl PairPrice {
-    /// Retrieve the price from the cached websocket book for a symbol and
-    /// level index.
-    async fn get_price_from_book(
-        &self,
-        symbol: &str,
-        level_index: usize,
-    ) -> anyhow::Result<Option<f32>> {
-        let connection = CONNECTIONS.lock().await;
-        let receiver = connection
-            .get(symbol)
-            .ok_or_else(|| anyhow!("There are no connections for symbol {}", symbol))?;
-
-        let ref_book = receiver.receiver.borrow();
-        let ws_response = match ref_book.as_ref() {
-            Some(response) => response,
-            None => return Ok(None),
-        };
+impl QueueElem {
+    /// Build a new queue entry with a freshly generated id.
+    pub fn new(
+        owner: Address,
+        source: Source,
+        agent: Arc<LocalWallet>,
+        action: CondAction,
+        condition: Condition,
+        vault_address: Option<Address>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            owner,
+            source,
+            agent,
+            action,
+            condition,
+            vault_address,
+            expires_at: None,
+            group: None,
+        }
+    }
 
-        if let WSResponse::L2Book(book) = ws_response {
-            let levels = book
-                .levels
-                .get(level_index)
-                .ok_or_else(|| anyhow!("Book doesn't contain level {}", level_index))?
-                .iter()
-                .filter_map(|l| Some((l.px.parse::<f32>().ok()?, l.sz.parse::<f32>().ok()?)))
-                .collect::<Vec<_>>();
-
-            let price = if level_index == 0 {
-                levels
-                    .iter()
-                    .max_by(|l1, l2| l1.0.total_cmp(&l2.0))
-                    .ok_or_else(|| anyhow!("Level doesn't have any items"))?
-                    .0
-            } else {
-                levels
-                    .iter()
-                    .min_by(|l1, l2| l1.0.total_cmp(&l2.0))
-                    .ok_or_else(|| anyhow!("Level doesn't have any items"))?
-                    .0
-            };
+    /// Whether `other` belongs to the same one-cancels-other group.
+    pub fn is_sibling(&self, other: &QueueElem) -> bool {
+        self.id != other.id
+            && self.owner == other.owner
+            && self.group.is_some()
+            && self.group == other.group
+    }
 
-            Ok(Some(price))
-        } else {
-            Err(anyhow!("Book is not L2Book"))
-        }
+    /// Evaluate the stored condition against the latest websocket data;
+    /// `None` while some of that data has not arrived yet.
+    pub async fn check(
+        &mut self,
+        feeds: &Feeds,
+        clock: &dyn Clock,
+    ) -> anyhow::Result<Option<bool>> {
+        self.condition.check(feeds, clock).await
     }
-}
 
-#[async_trait]
-impl Check for PairPrice {
-    /// Compare the cached book prices for both legs to determine whether the
-    /// configured ratio threshold has been met.
-    async fn check(&self) -> anyhow::Result<bool> {
-        let left_price = self.get_price_from_book(&self.left_symbol, 0).await?;
-        let right_price = self.get_price_from_book(&self.right_symbol, 1).await?;
-        if left_price.is_none() || right_price.is_none() {
-            return Ok(false);
+    /// Whether the good-till-time expiry has passed at `now`.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    /// Whether the entry has a time leaf or an expiry, i.e. needs looking at
+    /// without any market update.
+    pub fn has_deadline(&self) -> bool {
+        self.expires_at.is_some() || self.condition.is_timed()
+    }
+
+    /// Next instant after `now` at which the worker has to look at this entry
+    /// without any market update: a time condition boundary or the expiry.
+    pub fn next_deadline(&self, now: u64) -> Option<u64> {
+        let expiry = self.expires_at.filter(|&expires_at| expires_at > now);
+
+        match (self.condition.next_deadline(now), expiry) {
+            (Some(deadline), Some(expiry)) => Some(deadline.min(expiry)),
+            (deadline, expiry) => deadline.or(expiry),
         }
-        let left_price = left_price.unwrap();
-        let right_price = right_price.unwrap();
+    }
 
-        let comparison_result = if self.is_less {
-            left_price / right_price < self.price
-        } else {
-            left_price / right_price > self.price
-        };
+    /// Take a reference on every stream the condition depends on. Used
+    /// both when an order is queued and when it is restored after a restart.
+    pub async fn subscribe(&self) -> anyhow::Result<()> {
+        self.condition.subscribe().await
+    }
 
-        Ok(comparison_result)
+    /// Drop the references taken by [`QueueElem::subscribe`], closing streams
+    /// nobody else is listening to.
+    pub async fn unsubscribe(&self) {
+        self.condition.unsubscribe().await
     }
-}
 
-impl QueueElem {
-    /// Evaluate the stored condition against the latest websocket data.
-    pub async fn check(&self) -> anyhow::Result<bool> {
-        match &self.condition {
-            Condition::PairPrice(pair_price) => pair_price.check().await,
-            _ => Err(anyhow!("There is no such Condition")),
+    /// Public view of the entry, leaving out the agent key.
+    pub fn view(&self) -> QueueElemView {
+        QueueElemView {
+            id: self.id,
+            owner: self.owner,
+            source: self.source.clone(),
+            action: self.action.clone(),
+            condition: self.condition.clone(),
+            vault_address: self.vault_address,
+            expires_at: self.expires_at,
+            group: self.group.clone(),
+            trailing: self.condition.trailing_levels(),
         }
     }
 
-    /// Execute the queued action if the condition is satisfied, cleaning up
-    /// websocket subscriptions when no longer needed.
-    pub async fn execute(self, exchange: &hyperliquid::Exchange) {
+    /// Execute the queued action once the condition is satisfied and report
+    /// how it went. Websocket subscriptions are released by the queue when
+    /// the entry is removed.
+    pub async fn execute(
+        self,
+        exchange: &hyperliquid::Exchange,
+        info: &hyperliquid::Info,
+        twaps: &TwapStore,
+        sender: &Sender<InternalRequest>,
+    ) -> ActionOutcome {
+        let agent = self.agent;
+        let vault_address = self.vault_address;
+
         match self.action {
-            CondAction::HLOrder(order) => {
-                let result = exchange
-                    .place_order(self.agent.clone(), order.orders, self.vault_address)
-                    .await;
+            CondAction::HLOrder(order) => exchange
+                .place_order(agent, order.orders, vault_address)
+                .await
+                .into(),
+            CondAction::Cancel(cancel) => exchange
+                .cancel_order(agent, cancel.cancels, vault_address)
+                .await
+                .into(),
+            CondAction::UpdateLeverage(action) => exchange
+                .update_leverage(agent, action.leverage, action.asset, action.is_cross)
+                .await
+                .into(),
+            CondAction::SubAccountTransfer(action) => exchange
+                .sub_account_transfer(
+                    agent,
+                    action.is_deposit,
+                    action.sub_account_user,
+                    action.usd,
+                )
+                .await
+                .into(),
+            CondAction::ClosePosition(action) => {
+                let user = vault_address.unwrap_or(self.owner);
 
-                if let Err(err) = result {
-                    tracing::error!("Failed to place order: {}", err);
+                match close_order(info, user, &action).await {
+                    Ok(order) => exchange
+                        .place_order(agent, vec![order], vault_address)
+                        .await
+                        .into(),
+                    Err(err) => ActionOutcome::failed(err),
                 }
+            }
+            CondAction::TwapOrder(action) => {
+                let id = match Twaps::submit(
+                    twaps,
+                    self.owner,
+                    agent,
+                    action.twap,
+                    Algorithm::Twap,
+                    vault_address,
+                )
+                .await
+                {
+                    Ok(id) => id,
+                    Err(err) => return ActionOutcome::failed(err),
+                };
 
-                match self.condition {
-                    Condition::PairPrice(pair_price) => {
-                        let mut connection = CONNECTIONS.lock().await;
-                        let left_connection = connection.get_mut(&pair_price.left_symbol).unwrap();
-                        left_connection.count -= 1;
-                        if left_connection.count == 0 {
-                            let con = connection.remove(&pair_price.left_symbol).unwrap();
-                            con.stop_sender.send(());
-                        }
-
-                        let right_connection =
-                            connection.get_mut(&pair_price.right_symbol).unwrap();
-                        right_connection.count -= 1;
-                        if right_connection.count == 0 {
-                            let con = connection.remove(&pair_price.right_symbol).unwrap();
-                            con.stop_sender.send(());
-                        }
+                match sender.send(InternalRequest::TwapOrder { id }).await {
+                    Ok(()) => ActionOutcome {
+                        success: true,
+                        response: Some(serde_json::json!({ "twapId": id })),
+                        error: None,
+                    },
+                    Err(_) => {
+                        Twaps::discard(id).await;
+                        ActionOutcome::failed("Failed to send twap order")
                     }
                 }
             }
-            _ => tracing::error!("There is no such action!"),
         }
     }
 }
 
-/// Sources that can power a conditional order.
-#[derive(Debug, Deserialize, Default)]
+/// Build the reduce-only IOC order flattening `user`'s position in
+/// `action.asset`, priced at the mark price plus the allowed slippage.
+async fn close_order(
+    info: &hyperliquid::Info,
+    user: Address,
+    action: &ClosePosition,
+) -> anyhow::Result<OrderRequest> {
+    let ctxs = info
+        .contexts()
+        .await
+        .map_err(|err| anyhow::anyhow!("Failed to fetch asset contexts: {}", err))?;
+
+    let (Some(AssetContext::Meta(meta)), Some(AssetContext::Ctx(asset_ctxs))) =
+        (ctxs.first(), ctxs.get(1))
+    else {
+        return Err(anyhow::anyhow!("Failed to get asset contexts"));
+    };
+
+    let asset = meta
+        .universe
+        .get(action.asset as usize)
+        .ok_or_else(|| anyhow::anyhow!("Unknown asset {}", action.asset))?;
+    let mark_px: f64 = asset_ctxs
+        .get(action.asset as usize)
+        .ok_or_else(|| anyhow::anyhow!("Failed to get mark price"))?
+        .mark_px
+        .parse()?;
+
+    let state: ClearinghouseState = info
+        .client
+        .post(
+            &API::Info,
+            &UserStateRequest {
+                type_: "clearinghouseState".into(),
+                user,
+            },
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("Failed to fetch positions: {}", err))?;
+
+    let position = state
+        .asset_positions
+        .iter()
+        .find(|position| position.position.coin == asset.name)
+        .map(|position| position.position.szi)
+        .filter(|&size| size != 0.0)
+        .ok_or_else(|| anyhow::anyhow!("No open {} position", asset.name))?;
+
+    Ok(close_request(
+        action,
+        position,
+        mark_px,
+        asset.sz_decimals as u32,
+    ))
+}
+
+/// Reduce-only IOC order taking `action.size`, capped at the position, or the
+/// whole signed `position` off, priced `action.slippage` through `mark_px`.
+fn close_request(
+    action: &ClosePosition,
+    position: f64,
+    mark_px: f64,
+    sz_decimals: u32,
+) -> OrderRequest {
+    let is_buy = position < 0.0;
+    let size = action
+        .size
+        .map_or(position.abs(), |reduce| reduce.min(position.abs()));
+    let limit_px = mark_px
+        * (1.0
+            + if is_buy {
+                action.slippage
+            } else {
+                -action.slippage
+            });
+
+    OrderRequest {
+        asset: action.asset,
+        is_buy,
+        limit_px: parse_price(limit_px),
+        sz: parse_size(size, sz_decimals),
+        reduce_only: true,
+        order_type: OrderType::Limit(Limit { tif: Tif::Ioc }),
+        cloid: None,
+    }
+}
+
+/// `clearinghouseState` info request.
+#[derive(Debug, Serialize)]
+struct UserStateRequest {
+    #[serde(rename = "type")]
+    type_: String,
+    user: Address,
+}
+
+/// Subset of the `clearinghouseState` response used to read positions.
+#[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub enum Source {
-    #[default]
-    /// Hyperliquid websocket feeds maintained by the backend.
-    Hyperliquid,
+struct ClearinghouseState {
+    asset_positions: Vec<AssetPosition>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AssetPosition {
+    position: Position,
 }
 
-/// Available trigger predicates for conditional orders.
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize)]
+struct Position {
+    coin: String,
+    /// Signed position size, negative for shorts.
+    #[serde(deserialize_with = "parse")]
+    szi: f64,
+}
+
+/// `orderStatus` info request, by oid or client order id.
+#[derive(Debug, Serialize)]
+pub struct OrderStatusRequest {
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// Account the order was placed for.
+    pub user: Address,
+    /// Order id, or client order id as a 0x-prefixed hex string.
+    pub oid: String,
+}
+
+/// `orderStatus` info response.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum OrderStatusResponse {
+    /// The order is known to Hyperliquid.
+    Order {
+        /// Order and its current status.
+        order: OrderStatus,
+    },
+    /// No order with the requested id exists.
+    UnknownOid,
+}
+
+/// Order and status returned by `orderStatus`.
+#[derive(Debug, Deserialize)]
+pub struct OrderStatus {
+    /// Order details.
+    pub order: StatusOrder,
+    /// `open`, `filled`, `canceled`, `rejected`, ...
+    pub status: String,
+}
+
+/// Subset of the order returned by `orderStatus`.
+#[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub enum Condition {
-    /// Trigger based on the price ratio of two symbols.
-    PairPrice(PairPrice),
+pub struct StatusOrder {
+    /// Exchange-assigned order id.
+    pub oid: u64,
+    /// Size still resting.
+    #[serde(deserialize_with = "parse")]
+    pub sz: f64,
+    /// Size the order was placed with.
+    #[serde(deserialize_with = "parse")]
+    pub orig_sz: f64,
 }
 
-/// Ratio-based condition comparing two book prices.
-#[derive(Debug, Deserialize, Clone)]
-pub struct PairPrice {
-    /// Whether the trigger compares `left/right` with `< price` or `> price`.
-    pub is_less: bool,
-    /// Ratio threshold to compare against.
-    pub price: f32,
-    /// Symbol providing the numerator side of the comparison.
-    pub left_symbol: String,
-    /// Symbol providing the denominator side of the comparison.
-    pub right_symbol: String,
+impl OrderStatus {
+    /// Size filled so far.
+    pub fn filled(&self) -> f64 {
+        self.order.orig_sz - self.order.sz
+    }
+}
+
+/// `userFillsByTime` info request.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserFillsRequest {
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// Account whose fills are listed.
+    pub user: Address,
+    /// Earliest fill time in milliseconds since the Unix epoch.
+    pub start_time: u64,
+}
+
+/// Subset of a fill returned by `userFillsByTime`.
+#[derive(Debug, Deserialize)]
+pub struct UserFill {
+    /// Id of the filled order.
+    pub oid: u64,
+    /// Trade id, unique per fill.
+    pub tid: u64,
+    /// Fill time in milliseconds since the Unix epoch.
+    pub time: u64,
+    /// Fill price.
+    #[serde(deserialize_with = "parse")]
+    pub px: f64,
+    /// Filled size.
+    #[serde(deserialize_with = "parse")]
+    pub sz: f64,
+}
+
+/// Queue entry as returned by the conditional-order info endpoints.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueElemView {
+    /// Stable id of the queued order.
+    pub id: Uuid,
+    /// User that queued the order.
+    pub owner: Address,
+    /// Origin of the condition data.
+    pub source: Source,
+    /// Action performed once the condition is met.
+    pub action: CondAction,
+    /// Condition tracked by the background worker.
+    pub condition: Condition,
+    /// Vault routing for the executed order, if set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vault_address: Option<Address>,
+    /// Good-till-time expiry, if set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// One-cancels-other group, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Current levels of the trailing stops in the condition.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub trailing: Vec<TrailingLevel>,
+}
+
+/// Why a conditional order left the queue.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum CloseReason {
+    /// The condition was met and the action was run.
+    Executed,
+    /// The owner cancelled the order.
+    Cancelled,
+    /// The good-till-time expiry passed before the condition was met.
+    Expired,
+    /// Another order of the same one-cancels-other group executed.
+    GroupCancelled,
+}
+
+/// Record kept for a conditional order after it left the queue.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClosedCondOrder {
+    /// Id of the closed order.
+    pub id: Uuid,
+    /// User that queued the order.
+    pub owner: Address,
+    /// Why the order left the queue.
+    pub reason: CloseReason,
+    /// When the order left the queue, in milliseconds since the Unix epoch.
+    pub closed_at: u64,
+    /// The order as it was queued, in the same shape as [`QueueElemView`].
+    pub order: serde_json::Value,
+    /// Result of the action, for executed orders.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<ActionOutcome>,
+}
+
+impl ClosedCondOrder {
+    /// Snapshot `elem` as closed for `reason` at `closed_at`.
+    pub fn new(elem: &QueueElem, reason: CloseReason, closed_at: u64) -> Self {
+        Self {
+            id: elem.id,
+            owner: elem.owner,
+            reason,
+            closed_at,
+            order: serde_json::to_value(elem.view()).unwrap_or_default(),
+            outcome: None,
+        }
+    }
+}
+
+/// Serialize an agent wallet as its hex-encoded private key so queued orders
+/// can be signed again after being reloaded from storage.
+pub(crate) fn serialize_wallet<S>(wallet: &Arc<LocalWallet>, se: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    se.serialize_str(&hex::encode(wallet.signer().to_bytes()))
+}
+
+/// Counterpart of [`serialize_wallet`] rebuilding the wallet from its key.
+pub(crate) fn deserialize_wallet<'de, D>(de: D) -> Result<Arc<LocalWallet>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    String::deserialize(de)?
+        .parse::<LocalWallet>()
+        .map(Arc::new)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Sources that can power a conditional order.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum Source {
+    #[default]
+    /// Hyperliquid websocket feeds maintained by the backend.
+    Hyperliquid,
 }
 
 /// Live websocket connection metadata stored in [`CONNECTIONS`].
 pub struct ChannelConnection {
     /// Broadcast channel that delivers the latest websocket payload.
     pub receiver: watch::Receiver<Option<WSResponse>>,
+    /// Volume tally, only kept for `trades` streams.
+    pub volume: watch::Receiver<TradeVolume>,
     /// Signal used to request a graceful shutdown of the background task.
     pub stop_sender: oneshot::Sender<()>,
     /// Number of consumers currently relying on this connection.
@@ -578,15 +1734,10 @@ pub struct ChannelConnection {
 /// Internal requests handled by the websocket/worker infrastructure.
 #[derive(Debug)]
 pub enum InternalRequest {
-    /// Queue entry representing a TWAP action to execute on the background
-    /// worker.
+    /// TWAP registered in [`Twaps`] that the background worker should run.
     TwapOrder {
-        /// TWAP configuration forwarded to the worker.
-        request: TwapOrderRequest,
-        /// Wallet executing the TWAP leg.
-        agent: Arc<LocalWallet>,
-        /// Optional vault routing for fills.
-        vault_address: Option<Address>,
+        /// Id of the registered TWAP.
+        id: Uuid,
     },
 }
 /// Envelope for REST and websocket requests coming from the frontend.
@@ -628,13 +1779,29 @@ impl Subscription {
 }
 
 /// Individual stream subscriptions understood by Hyperliquid.
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "type", rename_all = "camelCase")]
 pub enum Subscribe {
     /// Stream candle updates for a given coin/interval.
     Candle { coin: String, interval: String },
     /// Stream level-2 book updates for a coin.
     L2Book { coin: String },
+    /// Stream public trades for a coin.
+    Trades { coin: String },
+    /// Stream perp asset context (mark, funding, open interest) for a coin.
+    ActiveAssetCtx { coin: String },
+}
+
+impl Subscribe {
+    /// Coin the subscription streams data for.
+    pub fn coin(&self) -> &str {
+        match self {
+            Subscribe::Candle { coin, .. }
+            | Subscribe::L2Book { coin }
+            | Subscribe::Trades { coin }
+            | Subscribe::ActiveAssetCtx { coin } => coin,
+        }
+    }
 }
 
 /// Payloads emitted by Hyperliquid websocket streams.
@@ -647,6 +1814,10 @@ pub enum WSResponse {
     Candle(Candle),
     /// Level-2 order book snapshot/update.
     L2Book(L2Book),
+    /// Batch of public trades.
+    Trades(Vec<Trade>),
+    /// Perp asset context update.
+    ActiveAssetCtx(ActiveAssetCtx),
 }
 
 /// Individual book levels returned by Hyperliquid.
@@ -672,6 +1843,121 @@ pub struct L2Book {
     pub time: u64,
 }
 
+impl L2Book {
+    /// Highest bid price in the book, if any.
+    pub fn best_bid(&self) -> Option<f64> {
+        self.levels
+            .first()?
+            .iter()
+            .filter_map(|l| l.px.parse::<f64>().ok())
+            .max_by(|px1, px2| px1.total_cmp(px2))
+    }
+
+    /// Lowest ask price in the book, if any.
+    pub fn best_ask(&self) -> Option<f64> {
+        self.levels
+            .get(1)?
+            .iter()
+            .filter_map(|l| l.px.parse::<f64>().ok())
+            .min_by(|px1, px2| px1.total_cmp(px2))
+    }
+
+    /// Midpoint between the best bid and the best ask.
+    pub fn mid(&self) -> Option<f64> {
+        Some((self.best_bid()? + self.best_ask()?) / 2.)
+    }
+}
+
+/// Public trade delivered by the `trades` stream.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Trade {
+    /// Symbol the trade executed on.
+    pub coin: String,
+    /// Aggressor side, `B` for buys and `A` for sells.
+    pub side: String,
+    /// Execution price.
+    #[serde(deserialize_with = "parse")]
+    pub px: f64,
+    /// Executed size.
+    #[serde(deserialize_with = "parse")]
+    pub sz: f64,
+    /// Millisecond timestamp of the trade.
+    pub time: u64,
+    /// Trade id.
+    pub tid: u64,
+}
+
+/// Running tally of the size traded on a `trades` stream.
+///
+/// The stream's watch channel only keeps the latest batch, so batches
+/// arriving faster than a consumer wakes up are lost to it; the tally is
+/// kept by the forwarder instead and consumers read its change.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TradeVolume {
+    /// Size traded since the stream was opened.
+    pub total: f64,
+    /// Time and id of the newest trade counted.
+    pub latest: (u64, u64),
+}
+
+impl TradeVolume {
+    /// Tally starting with the trades after `time`, in milliseconds since
+    /// the Unix epoch, leaving out the snapshot sent on subscription.
+    pub fn since(time: u64) -> Self {
+        Self {
+            total: 0.0,
+            latest: (time, 0),
+        }
+    }
+
+    /// Count the trades of `batch` newer than the ones already counted,
+    /// returning whether any were.
+    pub fn add(&mut self, batch: &[Trade]) -> bool {
+        let mut counted = false;
+        let mut newest = self.latest;
+        for trade in batch {
+            if (trade.time, trade.tid) > self.latest {
+                self.total += trade.sz;
+                newest = newest.max((trade.time, trade.tid));
+                counted = true;
+            }
+        }
+        self.latest = newest;
+        counted
+    }
+}
+
+/// Payload of the `activeAssetCtx` stream.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ActiveAssetCtx {
+    /// Symbol the context belongs to.
+    pub coin: String,
+    /// Latest perp context for the symbol.
+    pub ctx: AssetCtx,
+}
+
+/// Per-asset perp context shared by the `activeAssetCtx` stream and the
+/// `metaAndAssetCtxs` info request.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetCtx {
+    /// Current hourly funding rate.
+    #[serde(deserialize_with = "parse")]
+    pub funding: f64,
+    /// Open interest in units of the asset.
+    #[serde(deserialize_with = "parse")]
+    pub open_interest: f64,
+    /// Oracle price.
+    #[serde(deserialize_with = "parse")]
+    pub oracle_px: f64,
+    /// Mark price.
+    #[serde(deserialize_with = "parse")]
+    pub mark_px: f64,
+    /// Premium of the mark over the oracle price, when available.
+    #[serde(default, deserialize_with = "parse_option")]
+    pub premium: Option<f64>,
+}
+
 /// Candle update emitted by Hyperliquid streams.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Candle {
@@ -738,3 +2024,91 @@ where
         .parse()
         .map_err(serde::de::Error::custom)
 }
+
+/// Like [`parse`], for fields Hyperliquid may send as `null`.
+fn parse_option<'de, T, D>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr,
+    <T as std::str::FromStr>::Err: std::fmt::Display,
+{
+    Option::<String>::deserialize(de)?
+        .map(|value| value.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trade(time: u64, tid: u64, sz: f64) -> Trade {
+        Trade {
+            coin: "ETH".to_string(),
+            side: "B".to_string(),
+            px: 2000.0,
+            sz,
+            time,
+            tid,
+        }
+    }
+
+    /// Side, limit price and size of the close order for `position`.
+    fn close(size: Option<f64>, position: f64) -> (bool, f64, f64) {
+        let action = ClosePosition {
+            asset: 1,
+            size,
+            slippage: 0.03,
+        };
+        let order = close_request(&action, position, 2000.0, 4);
+
+        assert!(order.reduce_only);
+        assert!(matches!(
+            order.order_type,
+            OrderType::Limit(Limit { tif: Tif::Ioc })
+        ));
+        (
+            order.is_buy,
+            order.limit_px.parse().unwrap(),
+            order.sz.parse().unwrap(),
+        )
+    }
+
+    #[test]
+    fn close_order_sells_a_long_below_the_mark() {
+        let (is_buy, limit_px, sz) = close(None, 2.5);
+
+        assert!(!is_buy);
+        assert!((limit_px - 1940.0).abs() < 1e-6);
+        assert_eq!(sz, 2.5);
+    }
+
+    #[test]
+    fn close_order_buys_a_short_back_above_the_mark() {
+        let (is_buy, limit_px, sz) = close(None, -1.5);
+
+        assert!(is_buy);
+        assert!((limit_px - 2060.0).abs() < 1e-6);
+        assert_eq!(sz, 1.5);
+    }
+
+    #[test]
+    fn close_order_size_is_capped_at_the_position() {
+        assert_eq!(close(Some(0.5), 2.0).2, 0.5);
+        assert_eq!(close(Some(5.0), 2.0).2, 2.0);
+        assert_eq!(close(Some(5.0), -2.0).2, 2.0);
+    }
+
+    #[test]
+    fn trade_volume_counts_every_trade_once() {
+        let mut volume = TradeVolume::since(100);
+
+        // The snapshot sent on subscription predates the tally.
+        assert!(!volume.add(&[trade(90, 1, 5.0)]));
+        assert!(volume.add(&[trade(110, 2, 1.0), trade(120, 3, 2.0)]));
+        assert!(volume.add(&[trade(120, 3, 2.0), trade(130, 4, 0.5)]));
+        assert!(!volume.add(&[trade(130, 4, 0.5)]));
+
+        assert_eq!(volume.total, 3.5);
+        assert_eq!(volume.latest, (130, 4));
+    }
+}
@@ -0,0 +1,1751 @@
+//! Trigger predicates evaluated by the conditional-order worker.
+//!
+//! Each condition lists the Hyperliquid streams it reads through
+//! [`Condition::topics`]. The queue keeps those streams alive in
+//! [`CONNECTIONS`](super::CONNECTIONS), holds a receiver for each of them in
+//! [`Feeds`] and re-evaluates the condition through [`Check`] whenever one of
+//! them delivers an update.
+//!
+//! Time conditions ([`At`], [`After`], [`Window`]) read no stream. They report
+//! the next instant their outcome can change through
+//! [`Condition::next_deadline`] so the worker can sleep until then, and they
+//! read the time from the injected [`Clock`].
+//!
+//! Indicator conditions also need candle history, which the queue loads
+//! through [`Condition::seed`] before the condition is first evaluated.
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+use super::{Subscribe, WSResponse};
+use hyperliquid::Info;
+use tokio::sync::watch;
+
+use crate::{
+    service::{
+        clock::Clock,
+        indicator::{history, interval_millis, is_interval, Indicator, IndicatorSpec, Ohlc},
+    },
+    ws::hyperliquid::book_price::{BookPrice, Feed},
+};
+
+/// Receivers of the streams conditions read, keyed by topic.
+///
+/// The queue holds one receiver per watched topic and hands them to
+/// [`Check::check`], so evaluating a condition only reads the latest cached
+/// payloads and never locks [`CONNECTIONS`](super::CONNECTIONS).
+pub type Feeds = HashMap<Subscribe, watch::Receiver<Option<WSResponse>>>;
+
+/// Trait implemented by condition types to determine whether they have been
+/// satisfied.
+#[async_trait]
+pub trait Check {
+    /// Evaluate the condition against the latest payloads in `feeds`,
+    /// returning `Some(true)` when it has been satisfied, `Some(false)` when
+    /// it has not, and `None` while the data needed to decide is missing
+    /// (e.g. a stream that has not delivered yet). The queue only fires on
+    /// `Some(true)`. Conditions may update internal state, e.g. the last
+    /// value seen by a crossing trigger. Time-based conditions read the
+    /// current time from `clock`.
+    async fn check(&mut self, feeds: &Feeds, clock: &dyn Clock) -> anyhow::Result<Option<bool>>;
+}
+
+/// Available trigger predicates for conditional orders.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum Condition {
+    /// Trigger based on the price ratio of two symbols.
+    PairPrice(PairPrice),
+    /// Trigger based on a single reference price of one symbol.
+    Price(Price),
+    /// Trigger based on a perp context metric such as funding or open
+    /// interest.
+    AssetCtx(AssetCtxMetric),
+    /// Trigger based on the closing price of a candle, ignoring moves inside
+    /// the bar.
+    CandleClose(CandleClose),
+    /// Trigger based on a technical indicator such as an EMA cross or RSI.
+    Indicator(Box<IndicatorCondition>),
+    /// Trigger based on the z-score of a two-symbol spread.
+    Spread(Box<Spread>),
+    /// Fire when the book retraces from its best level by a trail.
+    TrailingStop(TrailingStop),
+    /// Fire once a timestamp has been reached.
+    At(At),
+    /// Fire once a delay has elapsed since the order was queued.
+    After(After),
+    /// Hold only while the current time is inside a window.
+    Window(Window),
+    /// Fire when every child condition holds.
+    And(Vec<Condition>),
+    /// Fire when at least one child condition holds.
+    Or(Vec<Condition>),
+    /// Fire when the child condition does not hold.
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    /// Streams that must be live for the condition to be evaluated, collected
+    /// from every leaf of composite conditions without duplicates.
+    pub fn topics(&self) -> Vec<Subscribe> {
+        let mut topics = Vec::new();
+        self.collect_topics(&mut topics);
+        topics
+    }
+
+    fn collect_topics(&self, topics: &mut Vec<Subscribe>) {
+        let leaf_topics = match self {
+            Condition::PairPrice(pair_price) => vec![
+                BookPrice::topic(&pair_price.left_symbol),
+                BookPrice::topic(&pair_price.right_symbol),
+            ],
+            Condition::Price(price) => vec![price.topic()],
+            Condition::AssetCtx(metric) => vec![metric.topic()],
+            Condition::CandleClose(candle_close) => candle_close.topics(),
+            Condition::Indicator(indicator) => vec![indicator.topic()],
+            Condition::Spread(spread) => vec![
+                BookPrice::topic(&spread.left_symbol),
+                BookPrice::topic(&spread.right_symbol),
+            ],
+            Condition::TrailingStop(trailing) => vec![BookPrice::topic(&trailing.symbol)],
+            Condition::At(_) | Condition::After(_) | Condition::Window(_) => return,
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                for condition in conditions {
+                    condition.collect_topics(topics);
+                }
+                return;
+            }
+            Condition::Not(condition) => return condition.collect_topics(topics),
+        };
+
+        for topic in leaf_topics {
+            if !topics.contains(&topic) {
+                topics.push(topic);
+            }
+        }
+    }
+
+    /// Reject conditions that could never be evaluated meaningfully.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                if conditions.is_empty() {
+                    return Err(anyhow!("Composite conditions need at least one child"));
+                }
+                conditions.iter().try_for_each(Condition::validate)
+            }
+            Condition::Not(condition) => condition.validate(),
+            Condition::Indicator(indicator) => indicator.validate(),
+            Condition::Spread(spread) => spread.validate(),
+            Condition::TrailingStop(trailing) => trailing.trail.validate(),
+            Condition::CandleClose(candle_close) if !is_interval(&candle_close.interval) => Err(
+                anyhow!("Unsupported candle interval {}", candle_close.interval),
+            ),
+            Condition::Window(window) if window.start >= window.end => {
+                Err(anyhow!("Time window must start before it ends"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether any leaf of the condition depends on the clock.
+    pub fn is_timed(&self) -> bool {
+        match self {
+            Condition::At(_) | Condition::After(_) | Condition::Window(_) => true,
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                conditions.iter().any(Condition::is_timed)
+            }
+            Condition::Not(condition) => condition.is_timed(),
+            Condition::PairPrice(_)
+            | Condition::Price(_)
+            | Condition::AssetCtx(_)
+            | Condition::CandleClose(_)
+            | Condition::Indicator(_)
+            | Condition::Spread(_)
+            | Condition::TrailingStop(_) => false,
+        }
+    }
+
+    /// Anchor relative time conditions to `now`, the moment the order is
+    /// queued. Conditions that were already anchored (e.g. restored from
+    /// Redis) keep their original start.
+    pub fn arm(&mut self, now: u64) {
+        match self {
+            Condition::After(after) => {
+                after.since.get_or_insert(now);
+            }
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                for condition in conditions {
+                    condition.arm(now);
+                }
+            }
+            Condition::Not(condition) => condition.arm(now),
+            _ => {}
+        }
+    }
+
+    /// Seed history-based leaves (indicators, spreads) from candles ending
+    /// at `now`. Failures are logged and leave the leaf to warm up from the
+    /// live stream.
+    pub async fn seed(&mut self, info: &Info, now: u64) {
+        let mut leaves = Vec::new();
+        self.collect_seeded(&mut leaves);
+
+        for leaf in leaves {
+            if let Err(err) = leaf.seed(info, now).await {
+                tracing::warn!("Failed to seed condition from history: {:?}", err);
+            }
+        }
+    }
+
+    fn collect_seeded<'a>(&'a mut self, leaves: &mut Vec<&'a mut (dyn Seed + Send)>) {
+        match self {
+            Condition::Indicator(indicator) => leaves.push(&mut **indicator),
+            Condition::Spread(spread) => leaves.push(&mut **spread),
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                for condition in conditions {
+                    condition.collect_seeded(leaves);
+                }
+            }
+            Condition::Not(condition) => condition.collect_seeded(leaves),
+            _ => {}
+        }
+    }
+
+    /// Number of relative asset-context leaves still waiting for the first
+    /// update to pin their baseline. The queue persists the entry as soon as
+    /// this drops, so a restart compares against the same reference.
+    pub fn unpinned_baselines(&self) -> usize {
+        match self {
+            Condition::AssetCtx(metric) => {
+                usize::from(metric.relative && metric.baseline.is_none())
+            }
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                conditions.iter().map(Condition::unpinned_baselines).sum()
+            }
+            Condition::Not(condition) => condition.unpinned_baselines(),
+            _ => 0,
+        }
+    }
+
+    /// Whether any leaf is a trailing stop.
+    pub fn has_trailing_stop(&self) -> bool {
+        match self {
+            Condition::TrailingStop(_) => true,
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                conditions.iter().any(Condition::has_trailing_stop)
+            }
+            Condition::Not(condition) => condition.has_trailing_stop(),
+            _ => false,
+        }
+    }
+
+    /// Current levels of every trailing-stop leaf, for display and
+    /// streaming.
+    pub fn trailing_levels(&self) -> Vec<TrailingLevel> {
+        let mut levels = Vec::new();
+        self.collect_trailing_levels(&mut levels);
+        levels
+    }
+
+    fn collect_trailing_levels(&self, levels: &mut Vec<TrailingLevel>) {
+        match self {
+            Condition::TrailingStop(trailing) => levels.extend(trailing.level()),
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                for condition in conditions {
+                    condition.collect_trailing_levels(levels);
+                }
+            }
+            Condition::Not(condition) => condition.collect_trailing_levels(levels),
+            _ => {}
+        }
+    }
+
+    /// Earliest instant after `now` at which a time leaf of the condition can
+    /// change its outcome, or `None` if no clock boundary is ahead.
+    pub fn next_deadline(&self, now: u64) -> Option<u64> {
+        match self {
+            Condition::At(at) => Some(at.time).filter(|&time| time > now),
+            Condition::After(after) => after.deadline().filter(|&time| time > now),
+            Condition::Window(window) => [window.start, window.end]
+                .into_iter()
+                .find(|&time| time > now),
+            Condition::And(conditions) | Condition::Or(conditions) => conditions
+                .iter()
+                .filter_map(|condition| condition.next_deadline(now))
+                .min(),
+            Condition::Not(condition) => condition.next_deadline(now),
+            Condition::PairPrice(_)
+            | Condition::Price(_)
+            | Condition::AssetCtx(_)
+            | Condition::CandleClose(_)
+            | Condition::Indicator(_)
+            | Condition::Spread(_)
+            | Condition::TrailingStop(_) => None,
+        }
+    }
+
+    /// Take a reference on every stream listed by [`Condition::topics`].
+    /// References already taken are released again if a later one fails.
+    pub async fn subscribe(&self) -> anyhow::Result<()> {
+        let topics = self.topics();
+
+        for (i, topic) in topics.iter().enumerate() {
+            if let Err(err) = Feed::subscribe(topic).await {
+                for topic in &topics[..i] {
+                    Feed::unsubscribe(topic).await;
+                }
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Release the references taken by [`Condition::subscribe`].
+    pub async fn unsubscribe(&self) {
+        for topic in self.topics() {
+            Feed::unsubscribe(&topic).await;
+        }
+    }
+}
+
+#[async_trait]
+impl Check for Condition {
+    /// Dispatch to the concrete condition. Composite conditions evaluate
+    /// every child before combining the outcomes, so stateful children (such
+    /// as crossing triggers and trailing extremes) observe every update even
+    /// when an earlier child is unknown or fails. Unknown children make the
+    /// composite unknown unless another child already decides it, and `not`
+    /// of an unknown condition stays unknown.
+    async fn check(&mut self, feeds: &Feeds, clock: &dyn Clock) -> anyhow::Result<Option<bool>> {
+        match self {
+            Condition::PairPrice(pair_price) => pair_price.check(feeds, clock).await,
+            Condition::Price(price) => price.check(feeds, clock).await,
+            Condition::AssetCtx(metric) => metric.check(feeds, clock).await,
+            Condition::CandleClose(candle_close) => candle_close.check(feeds, clock).await,
+            Condition::Indicator(indicator) => indicator.check(feeds, clock).await,
+            Condition::Spread(spread) => spread.check(feeds, clock).await,
+            Condition::TrailingStop(trailing) => trailing.check(feeds, clock).await,
+            Condition::At(at) => at.check(feeds, clock).await,
+            Condition::After(after) => after.check(feeds, clock).await,
+            Condition::Window(window) => window.check(feeds, clock).await,
+            Condition::And(conditions) => {
+                let outcomes = check_all(conditions, feeds, clock).await?;
+
+                Ok(if outcomes.contains(&Some(false)) {
+                    Some(false)
+                } else if outcomes.contains(&None) {
+                    None
+                } else {
+                    Some(true)
+                })
+            }
+            Condition::Or(conditions) => {
+                let outcomes = check_all(conditions, feeds, clock).await?;
+
+                Ok(if outcomes.contains(&Some(true)) {
+                    Some(true)
+                } else if outcomes.contains(&None) {
+                    None
+                } else {
+                    Some(false)
+                })
+            }
+            Condition::Not(condition) => Ok(condition.check(feeds, clock).await?.map(|held| !held)),
+        }
+    }
+}
+
+/// Evaluate every condition of a composite, then return the first error if
+/// any child failed.
+async fn check_all(
+    conditions: &mut [Condition],
+    feeds: &Feeds,
+    clock: &dyn Clock,
+) -> anyhow::Result<Vec<Option<bool>>> {
+    let mut outcomes = Vec::with_capacity(conditions.len());
+    for condition in conditions.iter_mut() {
+        outcomes.push(condition.check(feeds, clock).await);
+    }
+
+    outcomes.into_iter().collect()
+}
+
+/// Conditions whose state starts from candle history rather than empty.
+#[async_trait]
+trait Seed {
+    /// Load the history ending at `now` the condition is evaluated against.
+    async fn seed(&mut self, info: &Info, now: u64) -> anyhow::Result<()>;
+}
+
+/// Read the latest payload cached for `topic` and extract a value from it.
+/// Returns `None` until the stream has delivered a matching update.
+fn read_feed<T>(
+    feeds: &Feeds,
+    topic: &Subscribe,
+    extract: impl FnOnce(&WSResponse) -> Option<T>,
+) -> anyhow::Result<Option<T>> {
+    let receiver = feeds
+        .get(topic)
+        .ok_or_else(|| anyhow!("There are no connections for {:?}", topic))?;
+
+    let latest = receiver.borrow();
+
+    Ok(latest.as_ref().and_then(extract))
+}
+
+/// How a value is compared against a threshold.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Trigger {
+    /// Fire while the value is below the threshold.
+    Below,
+    /// Fire while the value is above the threshold.
+    Above,
+    /// Fire when the value moves from below the threshold to at or above it.
+    CrossesUp,
+    /// Fire when the value moves from above the threshold to at or below it.
+    CrossesDown,
+}
+
+impl Trigger {
+    /// Whether moving from `previous` to `current` satisfies the trigger.
+    /// Crossing triggers need a previous observation, so they never fire on
+    /// the first value seen (e.g. right after queueing or a restart).
+    pub fn fired(self, previous: Option<f64>, current: f64, threshold: f64) -> bool {
+        match self {
+            Trigger::Below => current < threshold,
+            Trigger::Above => current > threshold,
+            Trigger::CrossesUp => previous.is_some_and(|p| p < threshold) && current >= threshold,
+            Trigger::CrossesDown => previous.is_some_and(|p| p > threshold) && current <= threshold,
+        }
+    }
+}
+
+/// Ratio-based condition comparing two book prices.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PairPrice {
+    /// Whether the trigger compares `left/right` with `< price` or `> price`.
+    pub is_less: bool,
+    /// Ratio threshold to compare against.
+    pub price: f32,
+    /// Symbol providing the numerator side of the comparison.
+    pub left_symbol: String,
+    /// Symbol providing the denominator side of the comparison.
+    pub right_symbol: String,
+}
+
+#[async_trait]
+impl Check for PairPrice {
+    /// Compare the cached book prices for both legs to determine whether the
+    /// configured ratio threshold has been met.
+    async fn check(&mut self, feeds: &Feeds, _clock: &dyn Clock) -> anyhow::Result<Option<bool>> {
+        let left_price =
+            read_feed(
+                feeds,
+                &BookPrice::topic(&self.left_symbol),
+                |response| match response {
+                    WSResponse::L2Book(book) => book.best_bid(),
+                    _ => None,
+                },
+            )?;
+        let right_price =
+            read_feed(
+                feeds,
+                &BookPrice::topic(&self.right_symbol),
+                |response| match response {
+                    WSResponse::L2Book(book) => book.best_ask(),
+                    _ => None,
+                },
+            )?;
+
+        let (Some(left_price), Some(right_price)) = (left_price, right_price) else {
+            return Ok(None);
+        };
+
+        let ratio = (left_price / right_price) as f32;
+
+        let comparison_result = if self.is_less {
+            ratio < self.price
+        } else {
+            ratio > self.price
+        };
+
+        Ok(Some(comparison_result))
+    }
+}
+
+/// Reference price of a single instrument.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PriceReference {
+    /// Best bid of the L2 book.
+    Bid,
+    /// Best ask of the L2 book.
+    Ask,
+    /// Midpoint between best bid and best ask.
+    Mid,
+    /// Mark price from the asset context stream.
+    Mark,
+    /// Price of the most recent public trade.
+    Last,
+}
+
+/// Condition watching one reference price of a single symbol.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Price {
+    /// Symbol to watch.
+    pub symbol: String,
+    /// Which price of the symbol to compare.
+    pub reference: PriceReference,
+    /// Comparison applied against `price`.
+    pub trigger: Trigger,
+    /// Price threshold.
+    pub price: f64,
+    /// Last reference price observed, used by crossing triggers. Not
+    /// persisted: crossings re-arm on the first update after a restart.
+    #[serde(skip)]
+    pub last_price: Option<f64>,
+}
+
+impl Price {
+    /// Stream carrying the selected reference price.
+    pub fn topic(&self) -> Subscribe {
+        let coin = self.symbol.clone();
+
+        match self.reference {
+            PriceReference::Bid | PriceReference::Ask | PriceReference::Mid => {
+                Subscribe::L2Book { coin }
+            }
+            PriceReference::Mark => Subscribe::ActiveAssetCtx { coin },
+            PriceReference::Last => Subscribe::Trades { coin },
+        }
+    }
+
+    /// Current value of the selected reference price.
+    fn current(&self, feeds: &Feeds) -> anyhow::Result<Option<f64>> {
+        let reference = self.reference;
+
+        read_feed(feeds, &self.topic(), move |response| {
+            match (reference, response) {
+                (PriceReference::Bid, WSResponse::L2Book(book)) => book.best_bid(),
+                (PriceReference::Ask, WSResponse::L2Book(book)) => book.best_ask(),
+                (PriceReference::Mid, WSResponse::L2Book(book)) => book.mid(),
+                (PriceReference::Mark, WSResponse::ActiveAssetCtx(ctx)) => Some(ctx.ctx.mark_px),
+                (PriceReference::Last, WSResponse::Trades(trades)) => trades
+                    .iter()
+                    .max_by_key(|trade| (trade.time, trade.tid))
+                    .map(|trade| trade.px),
+                _ => None,
+            }
+        })
+    }
+}
+
+#[async_trait]
+impl Check for Price {
+    /// Compare the latest reference price against the threshold.
+    async fn check(&mut self, feeds: &Feeds, _clock: &dyn Clock) -> anyhow::Result<Option<bool>> {
+        let Some(current) = self.current(feeds)? else {
+            return Ok(None);
+        };
+
+        let fired = self.trigger.fired(self.last_price, current, self.price);
+        self.last_price = Some(current);
+
+        Ok(Some(fired))
+    }
+}
+
+/// Field of the perp asset context a condition watches.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AssetMetric {
+    /// Current hourly funding rate.
+    Funding,
+    /// Open interest in units of the asset.
+    OpenInterest,
+    /// Premium of the mark over the oracle price.
+    Premium,
+    /// Oracle price.
+    Oracle,
+}
+
+/// Condition watching one field of a symbol's perp asset context.
+///
+/// With `relative` set, `value` is a percentage change from the first value
+/// observed after queueing, e.g. `below` `-10` fires once open interest has
+/// dropped by more than 10%.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetCtxMetric {
+    /// Perp symbol to watch.
+    pub symbol: String,
+    /// Which context field to compare.
+    pub metric: AssetMetric,
+    /// Comparison applied against `value`.
+    pub trigger: Trigger,
+    /// Threshold, either an absolute value or a percentage change.
+    pub value: f64,
+    /// Compare the percentage change from `baseline` instead of the raw value.
+    #[serde(default)]
+    pub relative: bool,
+    /// Reference for relative comparisons. Taken from the first update when
+    /// omitted and persisted with the order from then on, so restarts keep
+    /// comparing against it.
+    #[serde(default)]
+    pub baseline: Option<f64>,
+    /// Last compared value, used by crossing triggers.
+    #[serde(skip)]
+    pub last_value: Option<f64>,
+}
+
+impl AssetCtxMetric {
+    /// Stream carrying the asset context of the symbol.
+    pub fn topic(&self) -> Subscribe {
+        Subscribe::ActiveAssetCtx {
+            coin: self.symbol.clone(),
+        }
+    }
+
+    /// Current value of the selected context field.
+    fn current(&self, feeds: &Feeds) -> anyhow::Result<Option<f64>> {
+        let metric = self.metric;
+
+        read_feed(feeds, &self.topic(), move |response| match response {
+            WSResponse::ActiveAssetCtx(active) => match metric {
+                AssetMetric::Funding => Some(active.ctx.funding),
+                AssetMetric::OpenInterest => Some(active.ctx.open_interest),
+                AssetMetric::Premium => active.ctx.premium,
+                AssetMetric::Oracle => Some(active.ctx.oracle_px),
+            },
+            _ => None,
+        })
+    }
+}
+
+#[async_trait]
+impl Check for AssetCtxMetric {
+    /// Compare the latest context value, or its change from the baseline,
+    /// against the threshold.
+    async fn check(&mut self, feeds: &Feeds, _clock: &dyn Clock) -> anyhow::Result<Option<bool>> {
+        let Some(current) = self.current(feeds)? else {
+            return Ok(None);
+        };
+
+        let compared = if self.relative {
+            let baseline = *self.baseline.get_or_insert(current);
+            if baseline == 0.0 {
+                return Ok(None);
+            }
+            (current - baseline) / baseline.abs() * 100.0
+        } else {
+            current
+        };
+
+        let fired = self.trigger.fired(self.last_value, compared, self.value);
+        self.last_value = Some(compared);
+
+        Ok(Some(fired))
+    }
+}
+
+/// Condition evaluated only when a candle closes. The close of `symbol`, or
+/// of the synthetic `symbol / right_symbol` ratio, is compared against
+/// `price`; crossing triggers compare consecutive closes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CandleClose {
+    /// Symbol whose candles are watched, or the numerator of a pair.
+    pub symbol: String,
+    /// Denominator of a synthetic pair ratio, if any.
+    pub right_symbol: Option<String>,
+    /// Candle interval, e.g. `15m` or `1h`.
+    pub interval: String,
+    /// Comparison applied against `price`.
+    pub trigger: Trigger,
+    /// Level the close is compared with.
+    pub price: f64,
+    /// Bar tracking, rebuilt from the stream after a restart.
+    #[serde(skip)]
+    state: Box<CandleCloseState>,
+}
+
+/// Per-leg bar tracking used to detect candle closes.
+#[derive(Debug, Clone, Default)]
+struct CandleCloseState {
+    left: BarTracker,
+    right: BarTracker,
+    /// Close time of the last bar that was evaluated.
+    evaluated: Option<u64>,
+    /// Value of the last evaluated close, used by crossing triggers.
+    last_close: Option<f64>,
+}
+
+/// Latest open bar of one leg and the last bar seen closing.
+#[derive(Debug, Clone, Copy, Default)]
+struct BarTracker {
+    open: Option<Ohlc>,
+    closed: Option<Ohlc>,
+}
+
+impl BarTracker {
+    /// Record the latest update of the open bar. An update with a later
+    /// `close_time` means the previously open bar has closed.
+    fn update(&mut self, bar: Ohlc) {
+        if let Some(open) = self.open {
+            if bar.close_time > open.close_time {
+                self.closed = Some(open);
+            }
+        }
+        self.open = Some(bar);
+    }
+}
+
+/// Latest update of the open `interval` candle of `coin`.
+fn latest_candle(feeds: &Feeds, coin: &str, interval: &str) -> anyhow::Result<Option<Ohlc>> {
+    let topic = Subscribe::Candle {
+        coin: coin.to_string(),
+        interval: interval.to_string(),
+    };
+
+    read_feed(feeds, &topic, |response| match response {
+        WSResponse::Candle(candle) => Some(Ohlc::from(candle)),
+        _ => None,
+    })
+}
+
+impl CandleClose {
+    /// Candle streams of every leg.
+    pub fn topics(&self) -> Vec<Subscribe> {
+        std::iter::once(&self.symbol)
+            .chain(self.right_symbol.as_ref())
+            .map(|coin| Subscribe::Candle {
+                coin: coin.clone(),
+                interval: self.interval.clone(),
+            })
+            .collect()
+    }
+
+    /// Close time and value of the most recently closed bar, once every leg
+    /// has closed the same bar.
+    fn closed_value(&self) -> Option<(u64, f64)> {
+        let left = self.state.left.closed?;
+
+        if self.right_symbol.is_none() {
+            return Some((left.close_time, left.close));
+        }
+
+        let right = self.state.right.closed?;
+        (left.close_time == right.close_time && right.close != 0.0)
+            .then(|| (left.close_time, left.close / right.close))
+    }
+}
+
+#[async_trait]
+impl Check for CandleClose {
+    /// Track the open bar of each leg and compare the close once a new bar
+    /// starts. Updates inside a bar leave the condition undecided, so it
+    /// cannot fire between closes, not even negated.
+    async fn check(&mut self, feeds: &Feeds, _clock: &dyn Clock) -> anyhow::Result<Option<bool>> {
+        if let Some(bar) = latest_candle(feeds, &self.symbol, &self.interval)? {
+            self.state.left.update(bar);
+        }
+        if let Some(right_symbol) = &self.right_symbol {
+            if let Some(bar) = latest_candle(feeds, right_symbol, &self.interval)? {
+                self.state.right.update(bar);
+            }
+        }
+
+        let Some((close_time, close)) = self.closed_value() else {
+            return Ok(None);
+        };
+        if self
+            .state
+            .evaluated
+            .is_some_and(|evaluated| evaluated >= close_time)
+        {
+            return Ok(None);
+        }
+
+        let fired = self.trigger.fired(self.state.last_close, close, self.price);
+        self.state.evaluated = Some(close_time);
+        self.state.last_close = Some(close);
+
+        Ok(Some(fired))
+    }
+}
+
+/// What an indicator is compared against.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Operand {
+    /// A fixed level, e.g. `30` for RSI.
+    Value(f64),
+    /// A second indicator on the same candles, e.g. a slower EMA.
+    Indicator(IndicatorSpec),
+}
+
+/// Condition comparing a technical indicator of `symbol` against a level or
+/// another indicator, evaluated once per closed `interval` candle.
+///
+/// The indicators are seeded from candle history when the order is queued or
+/// restored (see [`Condition::seed`]) and then fed closed bars from the
+/// candle stream.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IndicatorCondition {
+    /// Symbol whose candles feed the indicators.
+    pub symbol: String,
+    /// Candle interval, e.g. `1h`.
+    pub interval: String,
+    /// Indicator compared by `trigger`.
+    pub indicator: IndicatorSpec,
+    /// Comparison applied against `against`.
+    pub trigger: Trigger,
+    /// Level or indicator the first indicator is compared with.
+    pub against: Operand,
+    /// Indicator state, rebuilt from history after a restart.
+    #[serde(skip)]
+    state: IndicatorState,
+}
+
+#[derive(Debug, Clone, Default)]
+struct IndicatorState {
+    left: Option<Indicator>,
+    right: Option<Indicator>,
+    bars: BarTracker,
+    /// Close time of the last bar fed to the indicators.
+    fed_through: Option<u64>,
+    /// Last `indicator - against` difference, used by crossing triggers.
+    last_diff: Option<f64>,
+}
+
+impl IndicatorCondition {
+    /// Candle stream feeding the indicators.
+    pub fn topic(&self) -> Subscribe {
+        Subscribe::Candle {
+            coin: self.symbol.clone(),
+            interval: self.interval.clone(),
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        interval_millis(&self.interval)
+            .ok_or_else(|| anyhow!("Unsupported candle interval {}", self.interval))?;
+        self.indicator.validate()?;
+        if let Operand::Indicator(spec) = &self.against {
+            spec.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// `indicator - against`, once every indicator has a value.
+    fn diff(&self) -> Option<f64> {
+        let left = self.state.left.as_ref()?.value()?;
+        let right = match self.against {
+            Operand::Value(value) => value,
+            Operand::Indicator(_) => self.state.right.as_ref()?.value()?,
+        };
+
+        Some(left - right)
+    }
+}
+
+#[async_trait]
+impl Seed for IndicatorCondition {
+    /// Replay enough closed candles before `now` to warm both indicators up.
+    async fn seed(&mut self, info: &Info, now: u64) -> anyhow::Result<()> {
+        let warmup = match &self.against {
+            Operand::Indicator(spec) => spec.warmup().max(self.indicator.warmup()),
+            Operand::Value(_) => self.indicator.warmup(),
+        };
+        let bars = history(info, &self.symbol, &self.interval, warmup + 1, now).await?;
+
+        self.state.left = Some(Indicator::seeded(self.indicator, &bars));
+        if let Operand::Indicator(spec) = self.against {
+            self.state.right = Some(Indicator::seeded(spec, &bars));
+        }
+        self.state.fed_through = bars.last().map(|bar| bar.close_time);
+        self.state.last_diff = self.diff();
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Check for IndicatorCondition {
+    /// Feed the indicators each newly closed bar and compare the result.
+    /// Conditions that could not be seeded warm up from the stream instead.
+    async fn check(&mut self, feeds: &Feeds, _clock: &dyn Clock) -> anyhow::Result<Option<bool>> {
+        if let Some(bar) = latest_candle(feeds, &self.symbol, &self.interval)? {
+            self.state.bars.update(bar);
+        }
+
+        let Some(bar) = self.state.bars.closed else {
+            return Ok(None);
+        };
+        if self
+            .state
+            .fed_through
+            .is_some_and(|fed_through| fed_through >= bar.close_time)
+        {
+            return Ok(Some(false));
+        }
+
+        let state = &mut self.state;
+        state
+            .left
+            .get_or_insert_with(|| Indicator::new(self.indicator))
+            .update(&bar);
+        if let Operand::Indicator(spec) = self.against {
+            state
+                .right
+                .get_or_insert_with(|| Indicator::new(spec))
+                .update(&bar);
+        }
+        state.fed_through = Some(bar.close_time);
+
+        let Some(diff) = self.diff() else {
+            return Ok(None);
+        };
+
+        let fired = self.trigger.fired(self.state.last_diff, diff, 0.0);
+        self.state.last_diff = Some(diff);
+
+        Ok(Some(fired))
+    }
+}
+
+/// How the spread between two symbols is measured.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum SpreadMode {
+    /// `left / right`.
+    Ratio,
+    /// `ln(left) - hedge_ratio * ln(right)`.
+    #[serde(rename_all = "camelCase")]
+    LogSpread { hedge_ratio: f64 },
+}
+
+impl SpreadMode {
+    /// Spread of two prices, `None` if it is undefined for them.
+    fn spread(self, left: f64, right: f64) -> Option<f64> {
+        if left <= 0.0 || right <= 0.0 {
+            return None;
+        }
+
+        Some(match self {
+            SpreadMode::Ratio => left / right,
+            SpreadMode::LogSpread { hedge_ratio } => left.ln() - hedge_ratio * right.ln(),
+        })
+    }
+}
+
+/// Mean-reversion condition on the z-score of the spread between two
+/// symbols.
+///
+/// The rolling window holds one spread sample per `interval`, the last
+/// `lookback` of them. It is seeded from the candle closes of both legs and
+/// then extended with the last live spread of each elapsed interval. The
+/// live spread uses the book mids and is scored against the window.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Spread {
+    /// Numerator (or long leg) of the spread.
+    pub left_symbol: String,
+    /// Denominator (or hedge leg) of the spread.
+    pub right_symbol: String,
+    /// Sampling interval of the window, e.g. `1h`.
+    pub interval: String,
+    /// Number of samples in the rolling window.
+    pub lookback: usize,
+    /// How the spread is measured.
+    pub mode: SpreadMode,
+    /// Comparison applied against `z_score`.
+    pub trigger: Trigger,
+    /// Z-score threshold, e.g. `2.0` or `-2.0`.
+    pub z_score: f64,
+    /// Rolling window, rebuilt from history after a restart.
+    #[serde(skip)]
+    state: SpreadState,
+}
+
+#[derive(Debug, Clone, Default)]
+struct SpreadState {
+    window: VecDeque<f64>,
+    /// Start of the interval the live spread is currently sampled in.
+    bucket: Option<u64>,
+    /// Latest live spread, pushed to the window when its interval ends.
+    latest: Option<f64>,
+    /// Last z-score, used by crossing triggers.
+    last_z: Option<f64>,
+}
+
+impl Spread {
+    fn validate(&self) -> anyhow::Result<()> {
+        interval_millis(&self.interval)
+            .ok_or_else(|| anyhow!("Unsupported candle interval {}", self.interval))?;
+        if self.lookback < 2 {
+            return Err(anyhow!("Spread lookback needs at least two samples"));
+        }
+        if let SpreadMode::LogSpread { hedge_ratio } = self.mode {
+            if !hedge_ratio.is_finite() {
+                return Err(anyhow!("Hedge ratio must be a finite number"));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn push(&mut self, spread: f64) {
+        if self.state.window.len() == self.lookback {
+            self.state.window.pop_front();
+        }
+        self.state.window.push_back(spread);
+    }
+
+    /// Z-score of `spread` against the window, once it is full.
+    fn z_score(&self, spread: f64) -> Option<f64> {
+        let window = &self.state.window;
+        if window.len() < self.lookback {
+            return None;
+        }
+
+        let n = window.len() as f64;
+        let mean = window.iter().sum::<f64>() / n;
+        let variance = window.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+        let std_dev = variance.sqrt();
+
+        (std_dev > 0.0).then(|| (spread - mean) / std_dev)
+    }
+
+    /// Refill the window with the spread of the closes `left` and `right`
+    /// have in common, oldest first.
+    fn fill(&mut self, left: &[Ohlc], right: &[Ohlc]) {
+        let right_closes = right
+            .iter()
+            .map(|bar| (bar.close_time, bar.close))
+            .collect::<HashMap<_, _>>();
+
+        self.state.window.clear();
+        for bar in left {
+            let spread = right_closes
+                .get(&bar.close_time)
+                .and_then(|&right| self.mode.spread(bar.close, right));
+            if let Some(spread) = spread {
+                self.push(spread);
+            }
+        }
+    }
+
+    /// Live spread from the book mids of both legs.
+    fn live(&self, feeds: &Feeds) -> anyhow::Result<Option<f64>> {
+        let mid = |response: &WSResponse| match response {
+            WSResponse::L2Book(book) => book.mid(),
+            _ => None,
+        };
+
+        let left = read_feed(feeds, &BookPrice::topic(&self.left_symbol), mid)?;
+        let right = read_feed(feeds, &BookPrice::topic(&self.right_symbol), mid)?;
+
+        Ok(left
+            .zip(right)
+            .and_then(|(left, right)| self.mode.spread(left, right)))
+    }
+}
+
+#[async_trait]
+impl Seed for Spread {
+    /// Fill the window with the spread of the last `lookback` candle closes
+    /// both legs have in common.
+    async fn seed(&mut self, info: &Info, now: u64) -> anyhow::Result<()> {
+        let bars = self.lookback + 1;
+        let left = history(info, &self.left_symbol, &self.interval, bars, now).await?;
+        let right = history(info, &self.right_symbol, &self.interval, bars, now).await?;
+        self.fill(&left, &right);
+
+        let length = interval_millis(&self.interval).unwrap_or(1);
+        self.state.bucket = Some(now / length * length);
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Check for Spread {
+    /// Roll the window forward on interval boundaries and compare the z-score
+    /// of the live spread against the threshold.
+    async fn check(&mut self, feeds: &Feeds, clock: &dyn Clock) -> anyhow::Result<Option<bool>> {
+        let Some(spread) = self.live(feeds)? else {
+            return Ok(None);
+        };
+
+        let length = interval_millis(&self.interval).unwrap_or(1);
+        let bucket = clock.now() / length * length;
+        if self.state.bucket.is_some_and(|current| bucket > current) {
+            if let Some(latest) = self.state.latest {
+                self.push(latest);
+            }
+        }
+        self.state.bucket = Some(bucket);
+        self.state.latest = Some(spread);
+
+        let Some(z) = self.z_score(spread) else {
+            return Ok(None);
+        };
+
+        let fired = self.trigger.fired(self.state.last_z, z, self.z_score);
+        self.state.last_z = Some(z);
+
+        Ok(Some(fired))
+    }
+}
+
+/// Side of the position a trailing stop protects.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PositionSide {
+    /// Trail below the highest best bid.
+    Long,
+    /// Trail above the lowest best ask.
+    Short,
+}
+
+/// Distance between the best price seen and the stop level.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum Trail {
+    /// Fixed price distance.
+    Absolute(f64),
+    /// Percentage of the best price, e.g. `2.5` for 2.5%.
+    Percent(f64),
+}
+
+impl Trail {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match *self {
+            Trail::Absolute(amount) if amount.is_finite() && amount > 0.0 => Ok(()),
+            Trail::Percent(percent) if percent > 0.0 && percent < 100.0 => Ok(()),
+            _ => Err(anyhow!(
+                "Trail must be a positive amount or a percentage below 100"
+            )),
+        }
+    }
+
+    /// Price distance of the trail from `price`.
+    pub fn distance(self, price: f64) -> f64 {
+        match self {
+            Trail::Absolute(amount) => amount,
+            Trail::Percent(percent) => price * percent / 100.0,
+        }
+    }
+}
+
+/// Trailing stop driven by the L2 book of `symbol`.
+///
+/// A long stop follows the highest best bid since arming and fires once the
+/// bid falls back to `extreme - trail`; a short stop mirrors this on the best
+/// ask.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TrailingStop {
+    /// Symbol whose book is followed.
+    pub symbol: String,
+    /// Side of the protected position.
+    pub side: PositionSide,
+    /// Distance of the stop from the best price seen.
+    pub trail: Trail,
+    /// Best price seen since arming. The queue persists it a few seconds
+    /// after it moves, so restarts keep the stop (nearly) where it was.
+    #[serde(default)]
+    pub extreme: Option<f64>,
+}
+
+/// Current state of a trailing stop.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TrailingLevel {
+    /// Symbol whose book is followed.
+    pub symbol: String,
+    /// Side of the protected position.
+    pub side: PositionSide,
+    /// Best price seen since arming.
+    pub extreme: f64,
+    /// Price at which the stop fires.
+    pub stop: f64,
+}
+
+impl TrailingStop {
+    /// Current level, once a price was seen.
+    pub fn level(&self) -> Option<TrailingLevel> {
+        let extreme = self.extreme?;
+        let distance = self.trail.distance(extreme);
+        let stop = match self.side {
+            PositionSide::Long => extreme - distance,
+            PositionSide::Short => extreme + distance,
+        };
+
+        Some(TrailingLevel {
+            symbol: self.symbol.clone(),
+            side: self.side,
+            extreme,
+            stop,
+        })
+    }
+}
+
+#[async_trait]
+impl Check for TrailingStop {
+    /// Move the extreme with the book, then fire if the price retraced to the
+    /// stop.
+    async fn check(&mut self, feeds: &Feeds, _clock: &dyn Clock) -> anyhow::Result<Option<bool>> {
+        let side = self.side;
+        let price = read_feed(
+            feeds,
+            &BookPrice::topic(&self.symbol),
+            move |response| match (side, response) {
+                (PositionSide::Long, WSResponse::L2Book(book)) => book.best_bid(),
+                (PositionSide::Short, WSResponse::L2Book(book)) => book.best_ask(),
+                _ => None,
+            },
+        )?;
+
+        let Some(price) = price else {
+            return Ok(None);
+        };
+
+        let extreme = match (side, self.extreme) {
+            (PositionSide::Long, Some(extreme)) => extreme.max(price),
+            (PositionSide::Short, Some(extreme)) => extreme.min(price),
+            (_, None) => price,
+        };
+        self.extreme = Some(extreme);
+
+        let Some(level) = self.level() else {
+            return Ok(None);
+        };
+
+        Ok(Some(match side {
+            PositionSide::Long => price <= level.stop,
+            PositionSide::Short => price >= level.stop,
+        }))
+    }
+}
+
+/// Condition satisfied once the clock reaches a timestamp.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct At {
+    /// Timestamp in milliseconds since the Unix epoch.
+    pub time: u64,
+}
+
+#[async_trait]
+impl Check for At {
+    async fn check(&mut self, _feeds: &Feeds, clock: &dyn Clock) -> anyhow::Result<Option<bool>> {
+        Ok(Some(clock.now() >= self.time))
+    }
+}
+
+/// Condition satisfied once `delay` milliseconds have passed since the order
+/// was queued.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct After {
+    /// Delay in milliseconds.
+    pub delay: u64,
+    /// When the delay started counting, set by [`Condition::arm`] when the
+    /// order is queued and persisted so restarts keep the original schedule.
+    #[serde(default)]
+    pub since: Option<u64>,
+}
+
+impl After {
+    /// Timestamp at which the delay elapses, once armed.
+    fn deadline(&self) -> Option<u64> {
+        self.since.map(|since| since.saturating_add(self.delay))
+    }
+}
+
+#[async_trait]
+impl Check for After {
+    async fn check(&mut self, _feeds: &Feeds, clock: &dyn Clock) -> anyhow::Result<Option<bool>> {
+        Ok(Some(
+            self.deadline()
+                .is_some_and(|deadline| clock.now() >= deadline),
+        ))
+    }
+}
+
+/// Condition holding while the clock is inside `[start, end)`. Usually
+/// combined with other conditions through `and` to restrict when they may
+/// fire.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Window {
+    /// Window start in milliseconds since the Unix epoch, inclusive.
+    pub start: u64,
+    /// Window end in milliseconds since the Unix epoch, exclusive.
+    pub end: u64,
+}
+
+#[async_trait]
+impl Check for Window {
+    async fn check(&mut self, _feeds: &Feeds, clock: &dyn Clock) -> anyhow::Result<Option<bool>> {
+        let now = clock.now();
+
+        Ok(Some(self.start <= now && now < self.end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::hyperliquid::{ActiveAssetCtx, AssetCtx, Candle, L2Book, Level, Trade},
+        service::clock::ManualClock,
+    };
+
+    fn level(px: f64) -> Level {
+        Level {
+            px: px.to_string(),
+            sz: "1".into(),
+            n: 1,
+        }
+    }
+
+    /// Feeds with one book stream per `(symbol, best bid and ask)`; `None`
+    /// stands for a stream that has not delivered yet.
+    fn feeds(books: &[(&str, Option<(f64, f64)>)]) -> Feeds {
+        books
+            .iter()
+            .map(|&(symbol, touch)| {
+                let payload = touch.map(|(bid, ask)| {
+                    WSResponse::L2Book(L2Book {
+                        coin: symbol.into(),
+                        levels: vec![vec![level(bid)], vec![level(ask)]],
+                        time: 0,
+                    })
+                });
+
+                (BookPrice::topic(symbol), watch::channel(payload).1)
+            })
+            .collect()
+    }
+
+    fn bid_below(symbol: &str, price: f64) -> Condition {
+        Condition::Price(Price {
+            symbol: symbol.into(),
+            reference: PriceReference::Bid,
+            trigger: Trigger::Below,
+            price,
+            last_price: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn not_stays_unknown_until_data_arrives() {
+        let mut condition = Condition::Not(Box::new(bid_below("BTC", 100.0)));
+
+        let outcome = condition
+            .check(&feeds(&[("BTC", None)]), &ManualClock::at(0))
+            .await
+            .unwrap();
+        assert_eq!(outcome, None);
+
+        let outcome = condition
+            .check(
+                &feeds(&[("BTC", Some((110.0, 111.0)))]),
+                &ManualClock::at(0),
+            )
+            .await
+            .unwrap();
+        assert_eq!(outcome, Some(true));
+    }
+
+    #[tokio::test]
+    async fn composites_evaluate_children_after_an_unknown_one() {
+        let mut condition = Condition::Or(vec![
+            bid_below("BTC", 100.0),
+            Condition::TrailingStop(TrailingStop {
+                symbol: "ETH".into(),
+                side: PositionSide::Long,
+                trail: Trail::Absolute(5.0),
+                extreme: None,
+            }),
+        ]);
+
+        let feeds = feeds(&[("BTC", None), ("ETH", Some((50.0, 51.0)))]);
+        let outcome = condition.check(&feeds, &ManualClock::at(0)).await.unwrap();
+
+        assert_eq!(outcome, None);
+        assert_eq!(condition.trailing_levels()[0].extreme, 50.0);
+    }
+
+    #[tokio::test]
+    async fn composites_are_decided_by_a_known_child() {
+        let feeds = feeds(&[("BTC", None)]);
+
+        let mut or = Condition::Or(vec![
+            bid_below("BTC", 100.0),
+            Condition::At(At { time: 10 }),
+        ]);
+        assert_eq!(
+            or.check(&feeds, &ManualClock::at(10)).await.unwrap(),
+            Some(true)
+        );
+
+        let mut and = Condition::And(vec![
+            bid_below("BTC", 100.0),
+            Condition::At(At { time: 10 }),
+        ]);
+        assert_eq!(
+            and.check(&feeds, &ManualClock::at(5)).await.unwrap(),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn crossings_need_a_previous_sample_on_the_other_side() {
+        assert!(!Trigger::CrossesUp.fired(None, 105.0, 100.0));
+        assert!(Trigger::CrossesUp.fired(Some(95.0), 100.0, 100.0));
+        assert!(Trigger::CrossesUp.fired(Some(95.0), 105.0, 100.0));
+        assert!(!Trigger::CrossesUp.fired(Some(101.0), 105.0, 100.0));
+        assert!(!Trigger::CrossesUp.fired(Some(105.0), 95.0, 100.0));
+
+        assert!(!Trigger::CrossesDown.fired(None, 95.0, 100.0));
+        assert!(Trigger::CrossesDown.fired(Some(105.0), 100.0, 100.0));
+        assert!(Trigger::CrossesDown.fired(Some(105.0), 95.0, 100.0));
+        assert!(!Trigger::CrossesDown.fired(Some(99.0), 95.0, 100.0));
+        assert!(!Trigger::CrossesDown.fired(Some(95.0), 105.0, 100.0));
+    }
+
+    #[tokio::test]
+    async fn price_crossing_fires_once_the_touch_moves_through() {
+        let clock = ManualClock::at(0);
+        let mut condition = Condition::Price(Price {
+            symbol: "BTC".into(),
+            reference: PriceReference::Bid,
+            trigger: Trigger::CrossesUp,
+            price: 100.0,
+            last_price: None,
+        });
+
+        // Already above on the first sample: nothing was crossed.
+        for (bid, fired) in [(105.0, false), (95.0, false), (100.0, true), (110.0, false)] {
+            let outcome = condition
+                .check(&feeds(&[("BTC", Some((bid, bid + 1.0)))]), &clock)
+                .await
+                .unwrap();
+            assert_eq!(outcome, Some(fired), "bid {bid}");
+        }
+    }
+
+    #[test]
+    fn price_reads_each_reference_from_its_stream() {
+        let mut feeds = feeds(&[("BTC", Some((99.0, 101.0)))]);
+        feeds.insert(
+            Subscribe::ActiveAssetCtx { coin: "BTC".into() },
+            watch::channel(Some(WSResponse::ActiveAssetCtx(ActiveAssetCtx {
+                coin: "BTC".into(),
+                ctx: AssetCtx {
+                    funding: 0.0,
+                    open_interest: 0.0,
+                    oracle_px: 100.0,
+                    mark_px: 100.5,
+                    premium: None,
+                },
+            })))
+            .1,
+        );
+        let trade = |px: f64, time: u64, tid: u64| Trade {
+            coin: "BTC".into(),
+            side: "B".into(),
+            px,
+            sz: 1.0,
+            time,
+            tid,
+        };
+        feeds.insert(
+            Subscribe::Trades { coin: "BTC".into() },
+            watch::channel(Some(WSResponse::Trades(vec![
+                trade(102.0, 2, 1),
+                trade(98.0, 1, 2),
+            ])))
+            .1,
+        );
+
+        for (reference, expected) in [
+            (PriceReference::Bid, 99.0),
+            (PriceReference::Ask, 101.0),
+            (PriceReference::Mid, 100.0),
+            (PriceReference::Mark, 100.5),
+            (PriceReference::Last, 102.0),
+        ] {
+            let price = Price {
+                symbol: "BTC".into(),
+                reference,
+                trigger: Trigger::Above,
+                price: 0.0,
+                last_price: None,
+            };
+            assert_eq!(
+                price.current(&feeds).unwrap(),
+                Some(expected),
+                "{reference:?}"
+            );
+        }
+    }
+
+    /// Feeds with one 1m candle stream per `(symbol, close time, close)`.
+    fn candles(bars: &[(&str, i64, f64)]) -> Feeds {
+        bars.iter()
+            .map(|&(symbol, close_time, close)| {
+                let candle = Candle {
+                    open_time: close_time - 60_000,
+                    close_time,
+                    symbol: symbol.into(),
+                    interval: "1m".into(),
+                    open_price: close,
+                    close_price: close,
+                    high_price: close,
+                    low_price: close,
+                    volume: 1.0,
+                    num_trade: 1,
+                };
+                let topic = Subscribe::Candle {
+                    coin: symbol.into(),
+                    interval: "1m".into(),
+                };
+
+                (topic, watch::channel(Some(WSResponse::Candle(candle))).1)
+            })
+            .collect()
+    }
+
+    fn close_above(symbol: &str, right_symbol: Option<&str>, price: f64) -> Condition {
+        Condition::CandleClose(CandleClose {
+            symbol: symbol.into(),
+            right_symbol: right_symbol.map(Into::into),
+            interval: "1m".into(),
+            trigger: Trigger::Above,
+            price,
+            state: Default::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn candle_close_is_decided_once_per_closed_bar() {
+        let clock = ManualClock::at(0);
+        let mut condition = close_above("BTC", None, 100.0);
+
+        // Updates of the first bar, then of the next one (closing the first
+        // at 110), then the next one again, then the bar after that (closing
+        // the second at 95).
+        for (close_time, close, outcome) in [
+            (60_000, 105.0, None),
+            (60_000, 110.0, None),
+            (120_000, 90.0, Some(true)),
+            (120_000, 95.0, None),
+            (180_000, 120.0, Some(false)),
+        ] {
+            let feeds = candles(&[("BTC", close_time, close)]);
+            assert_eq!(
+                condition.check(&feeds, &clock).await.unwrap(),
+                outcome,
+                "bar {close_time} at {close}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn candle_close_ratio_waits_for_both_legs_to_close() {
+        let clock = ManualClock::at(0);
+        let mut condition = close_above("ETH", Some("BTC"), 0.05);
+
+        for (eth, btc, outcome) in [
+            ((60_000, 3_000.0), (60_000, 50_000.0), None),
+            ((120_000, 3_000.0), (60_000, 50_000.0), None),
+            ((120_000, 3_000.0), (120_000, 60_000.0), Some(true)),
+            ((180_000, 3_000.0), (180_000, 60_000.0), Some(false)),
+        ] {
+            let feeds = candles(&[("ETH", eth.0, eth.1), ("BTC", btc.0, btc.1)]);
+            assert_eq!(condition.check(&feeds, &clock).await.unwrap(), outcome);
+        }
+    }
+
+    #[tokio::test]
+    async fn negated_candle_close_does_not_fire_between_closes() {
+        let clock = ManualClock::at(0);
+        let mut condition = Condition::Not(Box::new(close_above("BTC", None, 100.0)));
+
+        for (close_time, close, outcome) in [
+            (60_000, 90.0, None),
+            (120_000, 90.0, Some(true)),
+            (120_000, 90.0, None),
+            (180_000, 110.0, Some(true)),
+        ] {
+            let feeds = candles(&[("BTC", close_time, close)]);
+            assert_eq!(condition.check(&feeds, &clock).await.unwrap(), outcome);
+        }
+    }
+
+    fn spread(mode: SpreadMode, lookback: usize, z_score: f64) -> Spread {
+        Spread {
+            left_symbol: "ETH".into(),
+            right_symbol: "BTC".into(),
+            interval: "1m".into(),
+            lookback,
+            mode,
+            trigger: Trigger::Above,
+            z_score,
+            state: Default::default(),
+        }
+    }
+
+    /// Bars of one leg closing at `close_time` minutes with `close`.
+    fn closes_at(bars: &[(u64, f64)]) -> Vec<Ohlc> {
+        bars.iter()
+            .map(|&(minute, close)| Ohlc {
+                open_time: (minute - 1) * 60_000,
+                close_time: minute * 60_000,
+                open: close,
+                high: close,
+                low: close,
+                close,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn spread_seeds_from_the_closes_both_legs_have() {
+        let mut spread = spread(SpreadMode::Ratio, 3, 1.0);
+        let left = closes_at(&[(1, 10.0), (2, 4.0), (3, 6.0), (4, 9.0), (5, 8.0)]);
+        // No close at minute 4 for the right leg.
+        let right = closes_at(&[(1, 5.0), (2, 2.0), (3, 2.0), (5, 4.0)]);
+
+        spread.fill(&left, &right);
+        assert_eq!(spread.state.window, [2.0, 3.0, 2.0]);
+    }
+
+    #[test]
+    fn spread_scores_against_the_window_mean_and_deviation() {
+        let mut spread = spread(SpreadMode::Ratio, 3, 1.0);
+        assert_eq!(spread.z_score(3.0), None);
+
+        // Mean 2, population deviation sqrt(2/3).
+        spread.fill(
+            &closes_at(&[(1, 1.0), (2, 2.0), (3, 3.0)]),
+            &closes_at(&[(1, 1.0), (2, 1.0), (3, 1.0)]),
+        );
+        let z = spread.z_score(3.0).unwrap();
+        assert!((z - 1.0 / (2.0f64 / 3.0).sqrt()).abs() < 1e-9);
+        assert_eq!(spread.z_score(2.0), Some(0.0));
+    }
+
+    #[test]
+    fn log_spread_applies_the_hedge_ratio() {
+        let mode = SpreadMode::LogSpread { hedge_ratio: 0.5 };
+        let e = std::f64::consts::E;
+
+        assert!((mode.spread(e.powi(2), e.powi(2)).unwrap() - 1.0).abs() < 1e-9);
+        assert!((mode.spread(e, e.powi(4)).unwrap() + 1.0).abs() < 1e-9);
+        assert_eq!(mode.spread(0.0, 1.0), None);
+    }
+
+    #[tokio::test]
+    async fn spread_rolls_its_window_once_per_interval() {
+        let clock = ManualClock::at(3 * 60_000);
+        let mut spread = spread(SpreadMode::Ratio, 3, 1.0);
+        spread.fill(
+            &closes_at(&[(1, 1.0), (2, 2.0), (3, 3.0)]),
+            &closes_at(&[(1, 1.0), (2, 1.0), (3, 1.0)]),
+        );
+        spread.state.bucket = Some(clock.now());
+        let mut condition = Condition::Spread(Box::new(spread));
+
+        // ETH mid 3, BTC mid 1: z = 1 / sqrt(2/3) > 1.
+        let feeds = feeds(&[("ETH", Some((2.5, 3.5))), ("BTC", Some((0.5, 1.5)))]);
+        assert_eq!(condition.check(&feeds, &clock).await.unwrap(), Some(true));
+
+        // A minute later the last live spread joins the window: mean 8/3, so
+        // the same spread of 3 scores below the threshold.
+        clock.set(4 * 60_000);
+        assert_eq!(condition.check(&feeds, &clock).await.unwrap(), Some(false));
+        let Condition::Spread(spread) = &condition else {
+            unreachable!()
+        };
+        assert_eq!(spread.state.window, [2.0, 3.0, 3.0]);
+    }
+
+    async fn evaluate(condition: &mut Condition, clock: &ManualClock) -> Option<bool> {
+        condition.check(&Feeds::new(), clock).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn at_fires_once_its_time_is_reached() {
+        let clock = ManualClock::at(900);
+        let mut condition = Condition::At(At { time: 1_000 });
+
+        assert_eq!(condition.next_deadline(clock.now()), Some(1_000));
+        assert_eq!(evaluate(&mut condition, &clock).await, Some(false));
+
+        clock.set(1_000);
+        assert_eq!(evaluate(&mut condition, &clock).await, Some(true));
+        assert_eq!(condition.next_deadline(clock.now()), None);
+    }
+
+    #[tokio::test]
+    async fn after_counts_from_arming() {
+        let clock = ManualClock::at(5_000);
+        let mut condition = Condition::After(After {
+            delay: 1_000,
+            since: None,
+        });
+        condition.arm(clock.now());
+
+        clock.set(5_999);
+        assert_eq!(evaluate(&mut condition, &clock).await, Some(false));
+        assert_eq!(condition.next_deadline(clock.now()), Some(6_000));
+
+        clock.set(6_000);
+        assert_eq!(evaluate(&mut condition, &clock).await, Some(true));
+    }
+
+    #[tokio::test]
+    async fn window_holds_between_its_boundaries() {
+        let clock = ManualClock::at(0);
+        let mut condition = Condition::Not(Box::new(Condition::Window(Window {
+            start: 100,
+            end: 200,
+        })));
+
+        assert_eq!(evaluate(&mut condition, &clock).await, Some(true));
+        assert_eq!(condition.next_deadline(clock.now()), Some(100));
+
+        clock.set(150);
+        assert_eq!(evaluate(&mut condition, &clock).await, Some(false));
+        assert_eq!(condition.next_deadline(clock.now()), Some(200));
+
+        clock.set(200);
+        assert_eq!(evaluate(&mut condition, &clock).await, Some(true));
+        assert_eq!(condition.next_deadline(clock.now()), None);
+    }
+
+    #[tokio::test]
+    async fn passed_boundaries_still_decide_the_outcome() {
+        // The order was restored after its time had passed: no boundary is
+        // ahead any more, but evaluating it fires right away.
+        let clock = ManualClock::at(10_000);
+        let mut condition = Condition::After(After {
+            delay: 1_000,
+            since: Some(2_000),
+        });
+
+        assert_eq!(condition.next_deadline(clock.now()), None);
+        assert_eq!(evaluate(&mut condition, &clock).await, Some(true));
+    }
+}
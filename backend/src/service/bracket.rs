@@ -0,0 +1,467 @@
+//! Bracket orders: an entry order whose take-profit/stop-loss legs are placed
+//! by the backend as the entry fills.
+//!
+//! Each bracket is followed by its own task polling the entry's
+//! `orderStatus` by client order id. Whenever the filled size grows, the legs
+//! are replaced by a pair sized to the whole filled quantity through
+//! `normal_tpsl`, so partial fills are protected as they happen. Tracking
+//! stops once the entry leaves the book; the legs then rest as ordinary
+//! reduce-only triggers.
+//!
+//! Brackets still being tracked are stored, sealed with their agent key, in a
+//! Redis hash after every change of their legs; `main.rs` reloads them on
+//! boot, and fills that happened while the backend was down get their legs on
+//! the first poll.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use ethers::{signers::LocalWallet, types::Address};
+use hyperliquid::{
+    types::{
+        exchange::{
+            request::{CancelRequest, OrderRequest, OrderType, TpSl, Trigger},
+            response::Response as ExchangeResponse,
+        },
+        Chain,
+    },
+    utils::{parse_price, parse_size},
+    Exchange, Hyperliquid, Info,
+};
+use lazy_static::lazy_static;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    model::hyperliquid::{deserialize_wallet, serialize_wallet, BracketOrder, OrderStatus},
+    service::{
+        cipher::RecordCipher,
+        hyperliquid::{
+            exchange::{place_statuses, PlaceStatus},
+            info,
+        },
+        market,
+        registry::Registry,
+        store::RecordStore,
+    },
+};
+
+/// Redis hash holding the brackets still being tracked.
+const BRACKET_KEY: &str = "bracket_orders";
+
+/// How often the entry's fills are polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+lazy_static! {
+    /// Brackets whose entry is still being tracked, keyed by bracket id.
+    static ref BRACKETS: Registry<Entry> = Registry::default();
+}
+
+/// Registry entry of a bracket.
+struct Entry {
+    bracket: Mutex<Bracket>,
+    /// Wallet signing the entry, leg and cancel requests.
+    agent: Arc<LocalWallet>,
+}
+
+/// Tracked state of one bracket order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Bracket {
+    /// Id handed back to the user and used as the persistence key.
+    pub id: Uuid,
+    /// User that placed the bracket.
+    pub owner: Address,
+    /// Vault executing the orders, if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vault_address: Option<Address>,
+    /// Entry and legs as requested.
+    pub order: BracketOrder,
+    /// Client order id of the entry.
+    pub entry: Uuid,
+    /// Filled size of the entry covered by the current legs.
+    pub filled: f64,
+    /// Client order ids of the current legs.
+    pub legs: Vec<Uuid>,
+    sz_decimals: u32,
+    /// Set once the bracket was torn down, stopping its task.
+    #[serde(skip)]
+    closed: bool,
+}
+
+/// Stored copy of a bracket, with the agent key needed to attach its legs
+/// after a restart.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    #[serde(
+        serialize_with = "serialize_wallet",
+        deserialize_with = "deserialize_wallet"
+    )]
+    agent: Arc<LocalWallet>,
+    #[serde(flatten)]
+    bracket: Bracket,
+}
+
+/// Handle to the Redis hash backing the brackets.
+#[derive(Clone)]
+pub struct BracketStore {
+    records: RecordStore,
+}
+
+impl BracketStore {
+    /// Store the brackets over `connection`, sealed with `cipher`.
+    pub fn new(connection: ConnectionManager, cipher: RecordCipher) -> Self {
+        Self {
+            records: RecordStore::new(connection, cipher, BRACKET_KEY),
+        }
+    }
+
+    /// Insert or overwrite the stored copy of `bracket`.
+    async fn save(&self, bracket: &Bracket, agent: &Arc<LocalWallet>) -> anyhow::Result<()> {
+        let record = Record {
+            agent: agent.clone(),
+            bracket: bracket.clone(),
+        };
+
+        self.records.save(bracket.id, &record).await
+    }
+
+    /// Delete the stored copy of the bracket `id`.
+    async fn remove(&self, id: Uuid) -> anyhow::Result<()> {
+        self.records.remove(id).await
+    }
+
+    /// Load every stored bracket.
+    async fn load(&self) -> anyhow::Result<Vec<Record>> {
+        self.records.load().await
+    }
+}
+
+/// Registry of the bracket orders being tracked.
+pub struct Brackets;
+
+impl Brackets {
+    /// Place the entry of `order` and start attaching its legs to the fills.
+    /// Returns the id of the bracket.
+    pub async fn open(
+        store: &BracketStore,
+        chain: Chain,
+        owner: Address,
+        agent: Arc<LocalWallet>,
+        mut order: BracketOrder,
+        vault_address: Option<Address>,
+    ) -> anyhow::Result<Uuid> {
+        let info: Info = Hyperliquid::new(chain);
+        let exchange: Exchange = Hyperliquid::new(chain);
+
+        let sz_decimals = market::market(&info, order.entry.asset).await?.sz_decimals;
+
+        let entry = Uuid::new_v4();
+        order.entry.cloid = Some(entry);
+
+        let response = exchange
+            .place_order(agent.clone(), vec![order.entry.clone()], vault_address)
+            .await
+            .map_err(|err| anyhow!("Failed to place the entry: {}", err))?;
+        if let ExchangeResponse::Err(err) = response {
+            return Err(anyhow!("Entry rejected: {}", err));
+        }
+
+        let id = Uuid::new_v4();
+        let bracket = Bracket {
+            id,
+            owner,
+            vault_address,
+            order,
+            entry,
+            filled: 0.0,
+            legs: Vec::new(),
+            sz_decimals,
+            closed: false,
+        };
+        // The entry is live either way; without a stored copy it is only
+        // followed until the next restart.
+        if let Err(err) = store.save(&bracket, &agent).await {
+            tracing::error!("Bracket {}: {:?}", id, err);
+        }
+
+        let entry = Arc::new(Entry {
+            bracket: Mutex::new(bracket),
+            agent,
+        });
+        BRACKETS.insert(id, entry.clone()).await;
+        tokio::spawn(track(info, exchange, store.clone(), entry));
+
+        Ok(id)
+    }
+
+    /// Reload the brackets stored before the last shutdown and resume
+    /// tracking their entries. Returns the number of brackets resumed.
+    pub async fn restore(store: BracketStore, chain: Chain) -> anyhow::Result<usize> {
+        let records = store.load().await?;
+        let resumed = records.len();
+
+        for Record { agent, bracket } in records {
+            let id = bracket.id;
+            let entry = Arc::new(Entry {
+                bracket: Mutex::new(bracket),
+                agent,
+            });
+            BRACKETS.insert(id, entry.clone()).await;
+
+            let info: Info = Hyperliquid::new(chain);
+            let exchange: Exchange = Hyperliquid::new(chain);
+            tokio::spawn(track(info, exchange, store.clone(), entry));
+        }
+
+        Ok(resumed)
+    }
+
+    /// Brackets of `owner` whose entry is still being tracked.
+    pub async fn list(owner: &Address) -> Vec<Bracket> {
+        let entries = BRACKETS.entries().await;
+
+        let mut owned = Vec::new();
+        for entry in entries {
+            let bracket = entry.bracket.lock().await;
+            if bracket.owner == *owner && !bracket.closed {
+                owned.push(bracket.clone());
+            }
+        }
+        owned
+    }
+
+    /// Cancel the entry and the legs of `owner`'s bracket `id`.
+    pub async fn cancel(
+        store: &BracketStore,
+        chain: Chain,
+        owner: &Address,
+        id: Uuid,
+    ) -> anyhow::Result<()> {
+        let entry = BRACKETS
+            .get(id)
+            .await
+            .ok_or_else(|| anyhow!("Bracket order not found"))?;
+
+        let mut bracket = entry.bracket.lock().await;
+        if bracket.owner != *owner || bracket.closed {
+            return Err(anyhow!("Bracket order not found"));
+        }
+
+        let info: Info = Hyperliquid::new(chain);
+        let exchange: Exchange = Hyperliquid::new(chain);
+
+        let mut orders = bracket.legs.clone();
+        orders.push(bracket.entry);
+        bracket
+            .cancel_orders(&info, &exchange, &entry.agent, &orders)
+            .await?;
+
+        bracket.closed = true;
+        BRACKETS.remove(id).await;
+        if let Err(err) = store.remove(id).await {
+            // Dropped on the first poll after a restart, the entry being
+            // cancelled.
+            tracing::error!("Bracket {}: {:?}", id, err);
+        }
+
+        Ok(())
+    }
+}
+
+impl Bracket {
+    /// Account the orders are placed for.
+    fn user(&self) -> Address {
+        self.vault_address.unwrap_or(self.owner)
+    }
+
+    /// Resize the legs to the entry's fills given its `status`. Returns
+    /// whether the entry is still resting.
+    async fn sync(
+        &mut self,
+        info: &Info,
+        exchange: &Exchange,
+        agent: &Arc<LocalWallet>,
+        status: Option<OrderStatus>,
+    ) -> anyhow::Result<bool> {
+        let Some(status) = status else {
+            // Hyperliquid never accepted the entry.
+            return Ok(false);
+        };
+
+        let filled = status.filled();
+        if filled > self.filled {
+            self.attach(info, exchange, agent, filled).await?;
+        }
+
+        Ok(status.status == "open")
+    }
+
+    /// Replace the legs by a pair covering `filled`. The new legs are placed
+    /// before the old ones are cancelled so the position is never left
+    /// unprotected. Unless every new leg rests, the old pair is kept and
+    /// whichever new leg made it to the book is cancelled.
+    async fn attach(
+        &mut self,
+        info: &Info,
+        exchange: &Exchange,
+        agent: &Arc<LocalWallet>,
+        filled: f64,
+    ) -> anyhow::Result<()> {
+        let mut legs = Vec::new();
+        if let Some(take_profit) = self.order.take_profit {
+            legs.push(self.leg(take_profit, TpSl::Tp, filled));
+        }
+        if let Some(stop_loss) = self.order.stop_loss {
+            legs.push(self.leg(stop_loss, TpSl::Sl, filled));
+        }
+        let cloids: Vec<_> = legs.iter().filter_map(|leg| leg.cloid).collect();
+
+        let response = exchange
+            .normal_tpsl(agent.clone(), legs, self.vault_address)
+            .await
+            .map_err(|err| anyhow!("Failed to place the legs: {}", err))?;
+
+        let failure = match place_statuses(response) {
+            Ok(statuses) if statuses.len() < cloids.len() => {
+                Some(anyhow!("Missing leg statuses: {:?}", statuses))
+            }
+            Ok(statuses) => statuses.into_iter().find_map(|status| match status {
+                PlaceStatus::Resting { .. } => None,
+                PlaceStatus::Rejected(reason) => Some(anyhow!("Leg rejected: {}", reason)),
+                PlaceStatus::Filled { .. } => Some(anyhow!("Leg filled when placed")),
+            }),
+            Err(err) => Some(err),
+        };
+        if let Some(err) = failure {
+            if let Err(cancel) = self.cancel_orders(info, exchange, agent, &cloids).await {
+                tracing::error!("Bracket {}: {:?}", self.id, cancel);
+            }
+            return Err(err);
+        }
+
+        let stale = std::mem::replace(&mut self.legs, cloids);
+        self.filled = filled;
+
+        self.cancel_orders(info, exchange, agent, &stale).await
+    }
+
+    /// Reduce-only market trigger closing `size` of the entry at `trigger_px`.
+    fn leg(&self, trigger_px: f64, tpsl: TpSl, size: f64) -> OrderRequest {
+        // Legs close the entry, so they trade the opposite side.
+        let is_buy = !self.order.entry.is_buy;
+        let limit_px = trigger_px
+            * (1.0
+                + if is_buy {
+                    self.order.slippage
+                } else {
+                    -self.order.slippage
+                });
+
+        OrderRequest {
+            asset: self.order.entry.asset,
+            is_buy,
+            limit_px: parse_price(limit_px),
+            sz: parse_size(size, self.sz_decimals),
+            reduce_only: true,
+            order_type: OrderType::Trigger(Trigger {
+                is_market: true,
+                trigger_px: parse_price(trigger_px),
+                tpsl,
+            }),
+            cloid: Some(Uuid::new_v4()),
+        }
+    }
+
+    /// Cancel the orders among `cloids` that are still resting.
+    async fn cancel_orders(
+        &self,
+        info: &Info,
+        exchange: &Exchange,
+        agent: &Arc<LocalWallet>,
+        cloids: &[Uuid],
+    ) -> anyhow::Result<()> {
+        let mut cancels = Vec::new();
+        for &cloid in cloids {
+            if let Some(status) = info::order_status(info, self.user(), cloid).await? {
+                if status.status == "open" {
+                    cancels.push(CancelRequest {
+                        asset: self.order.entry.asset,
+                        oid: status.order.oid,
+                    });
+                }
+            }
+        }
+
+        if cancels.is_empty() {
+            return Ok(());
+        }
+
+        let response = exchange
+            .cancel_order(agent.clone(), cancels, self.vault_address)
+            .await
+            .map_err(|err| anyhow!("Failed to cancel bracket orders: {}", err))?;
+        if let ExchangeResponse::Err(err) = response {
+            return Err(anyhow!("Cancel rejected: {}", err));
+        }
+
+        Ok(())
+    }
+}
+
+/// Follow the entry of a bracket until it leaves the book or the bracket is
+/// cancelled.
+///
+/// The entry's status is read without holding the bracket, so a cancel only
+/// waits behind the poll when the legs are being replaced.
+async fn track(info: Info, exchange: Exchange, store: BracketStore, entry: Arc<Entry>) {
+    loop {
+        let (id, user, cloid) = {
+            let bracket = entry.bracket.lock().await;
+            if bracket.closed {
+                return;
+            }
+            (bracket.id, bracket.user(), bracket.entry)
+        };
+
+        match info::order_status(&info, user, cloid).await {
+            Ok(status) => {
+                let mut bracket = entry.bracket.lock().await;
+                if bracket.closed {
+                    return;
+                }
+
+                let filled = bracket.filled;
+                let resting = bracket.sync(&info, &exchange, &entry.agent, status).await;
+                if bracket.filled != filled {
+                    if let Err(err) = store.save(&bracket, &entry.agent).await {
+                        tracing::error!("Bracket {}: {:?}", id, err);
+                    }
+                }
+
+                match resting {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        tracing::info!(
+                            "Bracket {} entry closed with {} filled",
+                            id,
+                            bracket.filled
+                        );
+                        bracket.closed = true;
+                        BRACKETS.remove(id).await;
+                        if let Err(err) = store.remove(id).await {
+                            tracing::error!("Bracket {}: {:?}", id, err);
+                        }
+                        return;
+                    }
+                    Err(err) => tracing::error!("Bracket {}: {:?}", id, err),
+                }
+            }
+            Err(err) => tracing::error!("Bracket {}: {:?}", id, err),
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,633 @@
+//! Chase-limit orders: a post-only order kept at the top of its side of the
+//! book until it fills.
+//!
+//! Each chase runs as its own task following the asset's [`BookPrice`]
+//! stream. Whenever the touch moves away from the order, the order is
+//! modified to the new touch, until it fills, the touch moves further than
+//! `max_chase` from where the chase started or the timeout expires; in the
+//! latter two cases the order is cancelled. An order rejected for crossing
+//! the book is placed again at the next touch. Fills are read from the
+//! order's `orderStatus` by client order id.
+//!
+//! The state of every chase is published on a watch channel, which backs
+//! both the `chaseOrders` info request and the `chase` websocket stream.
+
+use std::{cmp::Reverse, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use ethers::{signers::LocalWallet, types::Address};
+use hyperliquid::{
+    types::{
+        exchange::{
+            request::{CancelRequest, Limit, ModifyRequest, OrderRequest, OrderType, Tif},
+            response::Response as ExchangeResponse,
+        },
+        Chain,
+    },
+    utils::{parse_price, parse_size},
+    Exchange, Hyperliquid, Info,
+};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tokio::{sync::watch, time::Instant};
+use uuid::Uuid;
+
+use crate::{
+    model::hyperliquid::{ChaseOrder, Subscribe, WSResponse},
+    service::{
+        clock::{Clock, SystemClock},
+        hyperliquid::{
+            exchange::{place_statuses, PlaceStatus},
+            info,
+        },
+        market::{self, Market},
+        registry::{Finished, Registry},
+    },
+    ws::hyperliquid::book_price::{BookPrice, Feed},
+};
+
+/// How often the order's fills are polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a cancel request waits for the chase to wind down.
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Failures in a row after which a chase gives up.
+const MAX_CONSECUTIVE_ERRORS: usize = 3;
+
+/// Start of the reason Hyperliquid rejects a post-only order that would have
+/// crossed the book with.
+const CROSSED: &str = "Post only order would have immediately matched";
+
+lazy_static! {
+    /// Chases by id, kept for [`FINISHED_TTL`] once finished.
+    ///
+    /// [`FINISHED_TTL`]: crate::service::registry::FINISHED_TTL
+    static ref CHASES: Registry<Entry> = Registry::default();
+}
+
+/// Lifecycle state of a chase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChaseStatus {
+    /// The order is being kept at the touch.
+    Working,
+    /// The whole size filled.
+    Filled,
+    /// The touch moved past the maximum chase distance.
+    ChaseLimit,
+    /// The timeout expired.
+    Expired,
+    /// The owner cancelled the chase.
+    Cancelled,
+    /// The chase gave up after errors.
+    Failed,
+}
+
+impl ChaseStatus {
+    fn is_finished(self) -> bool {
+        self != ChaseStatus::Working
+    }
+}
+
+/// State of one chase-limit order.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Chase {
+    /// Id handed back to the user.
+    pub id: Uuid,
+    /// User that placed the chase.
+    pub owner: Address,
+    /// Vault executing the order, if set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vault_address: Option<Address>,
+    /// Chase as requested.
+    pub order: ChaseOrder,
+    /// Current lifecycle state.
+    pub status: ChaseStatus,
+    /// Touch the order was first placed at.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_px: Option<f64>,
+    /// Furthest price the order may be repriced to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit_px: Option<f64>,
+    /// Current price of the order, while it rests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub px: Option<f64>,
+    /// Client order id of the order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloid: Option<Uuid>,
+    /// Size filled so far.
+    pub filled: f64,
+    /// Number of times the order was moved to a new touch.
+    pub reprices: u64,
+    /// Most recent error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When the chase was placed, in milliseconds since the Unix epoch.
+    pub created_at: u64,
+    /// When the chase finished.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+}
+
+/// Registry entry of one chase.
+struct Entry {
+    state: watch::Sender<Chase>,
+    agent: Arc<LocalWallet>,
+    /// Set by the owner to stop the chase.
+    cancel: watch::Sender<bool>,
+}
+
+impl Entry {
+    fn chase(&self) -> Chase {
+        self.state.borrow().clone()
+    }
+}
+
+impl Finished for Entry {
+    fn finished_at(&self) -> Option<u64> {
+        self.state.borrow().finished_at
+    }
+}
+
+/// Registry of the chase-limit orders.
+pub struct Chases;
+
+impl Chases {
+    /// Start chasing the touch with `order`. Returns the id of the chase.
+    pub async fn open(
+        chain: Chain,
+        owner: Address,
+        agent: Arc<LocalWallet>,
+        order: ChaseOrder,
+        vault_address: Option<Address>,
+    ) -> anyhow::Result<Uuid> {
+        let info: Info = Hyperliquid::new(chain);
+        let market = market::market(&info, order.asset).await?;
+        if market.round_down(order.sz) <= 0.0 {
+            return Err(anyhow!("Size is below the lot size of the asset"));
+        }
+
+        let now = SystemClock.now();
+        let chase = Chase {
+            id: Uuid::new_v4(),
+            owner,
+            vault_address,
+            order,
+            status: ChaseStatus::Working,
+            start_px: None,
+            limit_px: None,
+            px: None,
+            cloid: None,
+            filled: 0.0,
+            reprices: 0,
+            error: None,
+            created_at: now,
+            finished_at: None,
+        };
+        let id = chase.id;
+
+        let entry = Arc::new(Entry {
+            state: watch::Sender::new(chase),
+            agent,
+            cancel: watch::Sender::new(false),
+        });
+
+        CHASES.prune(now).await;
+        CHASES.insert(id, entry.clone()).await;
+
+        tokio::spawn(run(chain, market, entry));
+
+        Ok(id)
+    }
+
+    /// Chases of `owner`, most recent first.
+    pub async fn list(owner: &Address) -> Vec<Chase> {
+        let mut chases: Vec<_> = CHASES
+            .entries()
+            .await
+            .iter()
+            .map(|entry| entry.chase())
+            .filter(|chase| chase.owner == *owner)
+            .collect();
+        chases.sort_by_key(|chase| Reverse(chase.created_at));
+        chases
+    }
+
+    /// Watch the state of chase `id`.
+    pub async fn receiver(id: Uuid) -> Option<watch::Receiver<Chase>> {
+        CHASES.get(id).await.map(|entry| entry.state.subscribe())
+    }
+
+    /// Stop `owner`'s chase `id`, cancelling its order. Returns the state it
+    /// ended in.
+    pub async fn cancel(owner: &Address, id: Uuid) -> anyhow::Result<Chase> {
+        let entry = CHASES
+            .get(id)
+            .await
+            .filter(|entry| entry.state.borrow().owner == *owner)
+            .ok_or_else(|| anyhow!("Chase order not found"))?;
+
+        if entry.state.borrow().status.is_finished() {
+            return Err(anyhow!("Chase order already finished"));
+        }
+        entry.cancel.send_replace(true);
+
+        // The order may still fill while it is being cancelled, so report
+        // the state the chase actually ended in.
+        let mut receiver = entry.state.subscribe();
+        let _ = tokio::time::timeout(
+            CANCEL_TIMEOUT,
+            receiver.wait_for(|chase| chase.status.is_finished()),
+        )
+        .await;
+
+        Ok(entry.chase())
+    }
+}
+
+/// Follow the book until the chase of `entry` ends.
+async fn run(chain: Chain, market: Market, entry: Arc<Entry>) {
+    let id = entry.chase().id;
+
+    let book = BookPrice::topic(&market.coin);
+    if let Err(err) = Feed::subscribe(&book).await {
+        tracing::error!("Chase {} could not stream the book: {:?}", id, err);
+        entry.state.send_modify(|chase| {
+            chase.status = ChaseStatus::Failed;
+            chase.error = Some(err.to_string());
+            chase.finished_at = Some(SystemClock.now());
+        });
+        return;
+    }
+
+    let chase = entry.chase();
+    let mut chaser = Chaser {
+        info: Hyperliquid::new(chain),
+        exchange: Hyperliquid::new(chain),
+        user: chase.vault_address.unwrap_or(chase.owner),
+        order: chase.order,
+        entry,
+        market,
+        book: book.clone(),
+        cloid: None,
+        px: 0.0,
+        limit_px: None,
+        settled: 0.0,
+    };
+
+    let (status, error) = match chaser.chase().await {
+        Ok(status) => (status, None),
+        Err(err) => {
+            tracing::error!("Chase {} failed: {:?}", id, err);
+            (ChaseStatus::Failed, Some(err.to_string()))
+        }
+    };
+    chaser.close(status, error).await;
+    tracing::info!("Chase {} finished", id);
+
+    Feed::unsubscribe(&book).await;
+}
+
+/// What a chase woke up for.
+enum Event {
+    /// The book changed.
+    Book,
+    /// The fills are due to be polled.
+    Poll,
+    /// The timeout expired.
+    Expired,
+    /// The owner cancelled the chase.
+    Cancelled,
+    /// The book stream went away.
+    Closed,
+}
+
+/// Works the order of one chase.
+struct Chaser {
+    info: Info,
+    exchange: Exchange,
+    entry: Arc<Entry>,
+    /// Account holding the position, the vault if set.
+    user: Address,
+    order: ChaseOrder,
+    market: Market,
+    /// L2 book stream the order follows.
+    book: Subscribe,
+    /// Client order id of the order, once placed.
+    cloid: Option<Uuid>,
+    /// Current price of the order.
+    px: f64,
+    /// Furthest price the order may be moved to, set by the first placement.
+    limit_px: Option<f64>,
+    /// Size filled before the order was last modified.
+    settled: f64,
+}
+
+impl Chaser {
+    /// Keep the order at the touch. Returns why the chase ended.
+    async fn chase(&mut self) -> anyhow::Result<ChaseStatus> {
+        let mut book = Feed::receiver(&self.book)
+            .await
+            .ok_or_else(|| anyhow!("Order book stream is closed"))?;
+        let mut cancel = self.entry.cancel.subscribe();
+
+        let deadline = Instant::now() + Duration::from_secs(self.order.timeout);
+        // The first tick is immediate and places the order if the stream
+        // already holds a book.
+        let mut poll = tokio::time::interval(POLL_INTERVAL);
+        let mut consecutive_errors = 0;
+
+        loop {
+            let event = tokio::select! {
+                changed = book.changed() => match changed {
+                    Ok(()) => Event::Book,
+                    Err(_) => Event::Closed,
+                },
+                _ = poll.tick() => Event::Poll,
+                _ = tokio::time::sleep_until(deadline) => Event::Expired,
+                _ = cancel.wait_for(|cancel| *cancel) => Event::Cancelled,
+            };
+
+            let touch = best_price(&book.borrow_and_update(), self.order.is_buy);
+            let result = match event {
+                Event::Book => self.follow(touch).await,
+                Event::Poll if self.cloid.is_none() => self.follow(touch).await,
+                Event::Poll => self.poll().await,
+                Event::Expired => return Ok(ChaseStatus::Expired),
+                Event::Cancelled => return Ok(ChaseStatus::Cancelled),
+                Event::Closed => return Err(anyhow!("Order book stream is closed")),
+            };
+
+            match result {
+                Ok(Some(status)) => return Ok(status),
+                Ok(None) => consecutive_errors = 0,
+                Err(err) => {
+                    tracing::warn!("Chase {}: {:?}", self.entry.chase().id, err);
+                    consecutive_errors += 1;
+                    if consecutive_errors == MAX_CONSECUTIVE_ERRORS {
+                        return Err(err);
+                    }
+                    self.entry
+                        .state
+                        .send_modify(|chase| chase.error = Some(err.to_string()));
+                }
+            }
+        }
+    }
+
+    /// Place the order at `touch`, or move it there if the touch moved away
+    /// from it. Returns a status once the chase has to end.
+    async fn follow(&mut self, touch: Option<f64>) -> anyhow::Result<Option<ChaseStatus>> {
+        let Some(touch) = touch else {
+            return Ok(None);
+        };
+
+        let side = if self.order.is_buy { 1.0 } else { -1.0 };
+        if let Some(limit_px) = self.limit_px {
+            if side * (touch - limit_px) > 0.0 {
+                return Ok(Some(ChaseStatus::ChaseLimit));
+            }
+        }
+
+        match self.cloid {
+            None => self.place(touch).await?,
+            Some(_) if side * (touch - self.px) > 0.0 => self.reprice(touch).await?,
+            Some(_) => {}
+        }
+
+        Ok(None)
+    }
+
+    /// Place a new post-only order at `touch` for what is left to fill.
+    async fn place(&mut self, touch: f64) -> anyhow::Result<()> {
+        let cloid = Uuid::new_v4();
+        let sz = self.market.round_down(self.order.sz - self.settled);
+
+        let response = self
+            .exchange
+            .place_order(
+                self.entry.agent.clone(),
+                vec![self.request(touch, sz, cloid)],
+                self.entry.chase().vault_address,
+            )
+            .await
+            .map_err(|err| anyhow!("Failed to place order: {}", err))?;
+        match place_statuses(response)?.into_iter().next() {
+            Some(PlaceStatus::Resting { .. } | PlaceStatus::Filled { .. }) => {}
+            // The touch moved into the order; it is placed again at the next
+            // touch.
+            Some(PlaceStatus::Rejected(reason)) if reason.starts_with(CROSSED) => return Ok(()),
+            Some(PlaceStatus::Rejected(reason)) => {
+                return Err(anyhow!("Order rejected: {}", reason));
+            }
+            None => return Err(anyhow!("No status for order {}", cloid)),
+        }
+
+        let side = if self.order.is_buy { 1.0 } else { -1.0 };
+        let limit_px = *self
+            .limit_px
+            .get_or_insert_with(|| touch + side * self.order.max_chase.distance(touch));
+        self.cloid = Some(cloid);
+        self.px = touch;
+
+        self.entry.state.send_modify(|chase| {
+            chase.start_px.get_or_insert(touch);
+            chase.limit_px = Some(limit_px);
+            chase.px = Some(touch);
+            chase.cloid = Some(cloid);
+        });
+
+        Ok(())
+    }
+
+    /// Move the resting order to `touch`.
+    async fn reprice(&mut self, touch: f64) -> anyhow::Result<()> {
+        let Some(cloid) = self.cloid else {
+            return Ok(());
+        };
+        let status = info::order_status(&self.info, self.user, cloid)
+            .await?
+            .ok_or_else(|| anyhow!("Order is unknown"))?;
+        if status.status != "open" {
+            // The next poll settles the order.
+            return Ok(());
+        }
+
+        // What filled so far stays with the order being replaced.
+        let settled = self.settled + status.filled();
+        let sz = self.market.round_down(self.order.sz - settled);
+        if sz <= 0.0 {
+            return Ok(());
+        }
+
+        let modify = ModifyRequest {
+            oid: status.order.oid,
+            order: self.request(touch, sz, cloid),
+        };
+        let response = self
+            .exchange
+            .modify_order(
+                self.entry.agent.clone(),
+                modify,
+                self.entry.chase().vault_address,
+            )
+            .await
+            .map_err(|err| anyhow!("Failed to modify order: {}", err))?;
+        match place_statuses(response)?.into_iter().next() {
+            Some(PlaceStatus::Resting { .. } | PlaceStatus::Filled { .. }) => {}
+            Some(PlaceStatus::Rejected(reason)) if reason.starts_with(CROSSED) => {
+                return self.settle_rejected(cloid).await;
+            }
+            Some(PlaceStatus::Rejected(reason)) => {
+                return Err(anyhow!("Modify rejected: {}", reason));
+            }
+            None => return Err(anyhow!("No status for order {}", cloid)),
+        }
+
+        self.settled = settled;
+        self.px = touch;
+        self.entry.state.send_modify(|chase| {
+            chase.px = Some(touch);
+            chase.filled = settled;
+            chase.reprices += 1;
+        });
+
+        Ok(())
+    }
+
+    /// Handle a reprice of order `cloid` rejected for crossing the book. If
+    /// the rejection took the order off the book, what it filled is kept and
+    /// a new order is placed at the next touch; otherwise it stays where it
+    /// was until the touch moves again.
+    async fn settle_rejected(&mut self, cloid: Uuid) -> anyhow::Result<()> {
+        let status = info::order_status(&self.info, self.user, cloid)
+            .await?
+            .ok_or_else(|| anyhow!("Order is unknown"))?;
+        if status.status == "open" || status.status == "filled" {
+            // The next poll settles a filled order.
+            return Ok(());
+        }
+
+        let settled = self.settled + status.filled();
+        self.settled = settled;
+        self.cloid = None;
+        self.entry.state.send_modify(|chase| {
+            chase.filled = settled;
+            chase.px = None;
+            chase.cloid = None;
+        });
+
+        Ok(())
+    }
+
+    /// Read the order's fills. Returns a status once the chase has to end.
+    async fn poll(&mut self) -> anyhow::Result<Option<ChaseStatus>> {
+        let Some(cloid) = self.cloid else {
+            return Ok(None);
+        };
+        let status = info::order_status(&self.info, self.user, cloid)
+            .await?
+            .ok_or_else(|| anyhow!("Order is unknown"))?;
+
+        let filled = self.settled + status.filled();
+        self.entry.state.send_modify(|chase| chase.filled = filled);
+
+        match status.status.as_str() {
+            "open" => Ok(None),
+            "filled" => Ok(Some(ChaseStatus::Filled)),
+            // A post-only order that would have crossed the book is placed
+            // again at the next touch.
+            rejected if rejected.ends_with("Rejected") && status.filled() <= 0.0 => {
+                self.cloid = None;
+                Ok(None)
+            }
+            other => Err(anyhow!("Order left the book: {}", other)),
+        }
+    }
+
+    /// Cancel the order if it still rests and record how the chase ended.
+    async fn close(&mut self, mut status: ChaseStatus, mut error: Option<String>) {
+        let result = async {
+            let Some(cloid) = self.cloid else {
+                return Ok(None);
+            };
+
+            if let Some(order) = info::order_status(&self.info, self.user, cloid).await? {
+                if order.status == "open" {
+                    let cancel = CancelRequest {
+                        asset: self.order.asset,
+                        oid: order.order.oid,
+                    };
+                    let response = self
+                        .exchange
+                        .cancel_order(
+                            self.entry.agent.clone(),
+                            vec![cancel],
+                            self.entry.chase().vault_address,
+                        )
+                        .await
+                        .map_err(|err| anyhow!("Failed to cancel order: {}", err))?;
+                    if let ExchangeResponse::Err(err) = response {
+                        return Err(anyhow!("Cancel rejected: {}", err));
+                    }
+                }
+            }
+
+            // Read the status again, the order may have filled until the
+            // cancel went through.
+            info::order_status(&self.info, self.user, cloid).await
+        }
+        .await;
+
+        let mut filled = None;
+        match result {
+            Ok(Some(order)) => {
+                filled = Some(self.settled + order.filled());
+                if order.status == "filled" {
+                    status = ChaseStatus::Filled;
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                tracing::error!("Chase order could not be closed: {:?}", err);
+                error = Some(err.to_string());
+            }
+        }
+
+        self.entry.state.send_modify(|chase| {
+            chase.status = status;
+            if let Some(filled) = filled {
+                chase.filled = filled;
+            }
+            if error.is_some() {
+                chase.error = error;
+            }
+            chase.px = None;
+            chase.finished_at = Some(SystemClock.now());
+        });
+    }
+
+    /// Post-only order for `sz` at `px`.
+    fn request(&self, px: f64, sz: f64, cloid: Uuid) -> OrderRequest {
+        OrderRequest {
+            asset: self.order.asset,
+            is_buy: self.order.is_buy,
+            limit_px: parse_price(px),
+            sz: parse_size(sz, self.market.sz_decimals),
+            reduce_only: self.order.reduce_only,
+            order_type: OrderType::Limit(Limit { tif: Tif::Alo }),
+            cloid: Some(cloid),
+        }
+    }
+}
+
+/// Best price of the order's own side in the latest book.
+fn best_price(book: &Option<WSResponse>, is_buy: bool) -> Option<f64> {
+    match book {
+        Some(WSResponse::L2Book(book)) if is_buy => book.best_bid(),
+        Some(WSResponse::L2Book(book)) => book.best_ask(),
+        _ => None,
+    }
+}
@@ -0,0 +1,89 @@
+//! Encryption at rest for persisted records holding agent keys.
+//!
+//! Queued conditional orders (and anything else that has to sign on a user's
+//! behalf after a restart) keep the session's agent wallet in Redis. Those
+//! records are sealed with AES-256-GCM under a key derived from the server's
+//! `AGENT_KEY_SECRET`, so a Redis dump or replica alone does not expose the
+//! signing keys.
+
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Key, Nonce,
+};
+use anyhow::{anyhow, Context};
+use ethers::{
+    core::rand::{thread_rng, RngCore},
+    utils::{hex, keccak256},
+};
+
+/// Length in bytes of the random nonce prepended to every sealed record.
+const NONCE_LEN: usize = 12;
+
+/// Seals and opens persisted records with the server-side secret.
+#[derive(Clone)]
+pub struct RecordCipher {
+    cipher: Aes256Gcm,
+}
+
+impl RecordCipher {
+    /// Derive the record key from the configured secret.
+    pub fn new(secret: &str) -> Self {
+        let key = keccak256(secret.as_bytes());
+
+        Self {
+            cipher: Aes256Gcm::new(&Key::<Aes256Gcm>::from(key)),
+        }
+    }
+
+    /// Encrypt `plaintext` under a fresh nonce, returning hex of
+    /// `nonce || ciphertext`.
+    pub fn seal(&self, plaintext: &str) -> anyhow::Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        thread_rng().fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .cipher
+            .encrypt(&Nonce::from(nonce), plaintext.as_bytes())
+            .map_err(|_| anyhow!("Failed to encrypt record"))?;
+
+        Ok(hex::encode([&nonce[..], &ciphertext].concat()))
+    }
+
+    /// Decrypt a record produced by [`RecordCipher::seal`].
+    pub fn open(&self, sealed: &str) -> anyhow::Result<String> {
+        let bytes = hex::decode(sealed).context("Sealed record is not hex")?;
+        if bytes.len() < NONCE_LEN {
+            return Err(anyhow!("Sealed record is too short"));
+        }
+
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let nonce: [u8; NONCE_LEN] = nonce.try_into()?;
+        let plaintext = self
+            .cipher
+            .decrypt(&Nonce::from(nonce), ciphertext)
+            .map_err(|_| anyhow!("Failed to decrypt record, wrong AGENT_KEY_SECRET?"))?;
+
+        String::from_utf8(plaintext).context("Decrypted record is not UTF-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_under_the_same_secret() {
+        let cipher = RecordCipher::new("secret");
+        let sealed = cipher.seal(r#"{"agent":"0xabc"}"#).unwrap();
+
+        assert!(!sealed.contains("0xabc"));
+        assert_eq!(cipher.open(&sealed).unwrap(), r#"{"agent":"0xabc"}"#);
+    }
+
+    #[test]
+    fn rejects_records_sealed_under_another_secret() {
+        let sealed = RecordCipher::new("secret").seal("payload").unwrap();
+
+        assert!(RecordCipher::new("other").open(&sealed).is_err());
+    }
+}
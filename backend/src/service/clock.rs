@@ -0,0 +1,55 @@
+//! Wall-clock abstraction used by time-based conditional-order logic.
+//!
+//! Time conditions and order expiry read the current time through [`Clock`]
+//! instead of calling `SystemTime` directly, so a fixed or manually advanced
+//! clock can be injected to exercise them offline.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Source of the current time in milliseconds since the Unix epoch, matching
+/// the timestamps Hyperliquid uses.
+pub trait Clock: Send + Sync {
+    /// Current time in milliseconds since the Unix epoch.
+    fn now(&self) -> u64;
+}
+
+/// [`Clock`] backed by the system time.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default()
+    }
+}
+
+/// [`Clock`] standing still until moved by the test driving it.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct ManualClock(AtomicU64);
+
+#[cfg(test)]
+impl ManualClock {
+    /// Clock reading `now`.
+    pub fn at(now: u64) -> Self {
+        Self(AtomicU64::new(now))
+    }
+
+    /// Move the clock to `now`.
+    pub fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,836 @@
+//! Grid bots: a ladder of buys below the market and sells above it over a
+//! price range, where every filled order is replaced by the opposite order
+//! one level away.
+//!
+//! A buy filled at one grid price is replaced by a sell at the next price up,
+//! and a sell by a buy at the next price down, so the bot keeps buying dips
+//! and selling rallies inside the range. A replacement order that fills
+//! closes a round trip, whose profit (the level spacing times the order size,
+//! before fees) is added to the bot's realized profit.
+//!
+//! Each running bot is followed by its own task polling `userFillsByTime`
+//! from a cursor, so a single request per poll covers every level. Bots, with
+//! their agent key, levels, resting orders and fill cursor, are stored sealed
+//! in a Redis hash (see [`crate::service::store`]) after every change and
+//! every placement; `main.rs` reloads them on boot and fills that happened
+//! while the backend was down are picked up from the cursor. Stopped bots are
+//! dropped [`FINISHED_TTL`] after they stopped. Orders rejected by
+//! Hyperliquid or cancelled outside the backend leave their level empty.
+//!
+//! [`FINISHED_TTL`]: crate::service::registry::FINISHED_TTL
+
+use std::{
+    cmp::Reverse,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use ethers::{signers::LocalWallet, types::Address};
+use hyperliquid::{
+    types::{
+        exchange::{
+            request::{CancelRequest, Limit, OrderRequest, OrderType, Tif},
+            response::Response as ExchangeResponse,
+        },
+        Chain,
+    },
+    utils::{parse_price, parse_size},
+    Exchange, Hyperliquid, Info,
+};
+use lazy_static::lazy_static;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    model::hyperliquid::{deserialize_wallet, serialize_wallet, GridBotConfig},
+    service::{
+        cipher::RecordCipher,
+        clock::{Clock, SystemClock},
+        hyperliquid::{
+            exchange::{place_statuses, PlaceStatus},
+            info,
+        },
+        market::{self, Market, MIN_NOTIONAL},
+        registry::{Finished, Registry},
+        store::RecordStore,
+    },
+};
+
+/// Redis hash holding the grid bots.
+const GRID_KEY: &str = "grid_bots";
+
+/// How often a running bot polls its fills.
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+lazy_static! {
+    /// Grid bots loaded in this process, running or stopped, keyed by id.
+    /// Stopped bots are kept for [`FINISHED_TTL`].
+    ///
+    /// [`FINISHED_TTL`]: crate::service::registry::FINISHED_TTL
+    static ref GRIDS: Registry<Entry> = Registry::default();
+}
+
+/// Registry entry of a grid bot.
+struct Entry {
+    bot: Mutex<GridBot>,
+    /// Wallet signing the bot's orders and cancels.
+    agent: Arc<LocalWallet>,
+    /// When the bot was stopped, readable while the bot is locked.
+    stopped_at: OnceLock<u64>,
+}
+
+impl Entry {
+    fn new(bot: GridBot, agent: Arc<LocalWallet>) -> Self {
+        let stopped_at = OnceLock::new();
+        if let Some(at) = bot.stopped_at {
+            let _ = stopped_at.set(at);
+        }
+
+        Self {
+            bot: Mutex::new(bot),
+            agent,
+            stopped_at,
+        }
+    }
+}
+
+impl Finished for Entry {
+    fn finished_at(&self) -> Option<u64> {
+        self.stopped_at.get().copied()
+    }
+}
+
+/// Whether a grid bot is still trading.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum GridStatus {
+    /// Orders rest on the book and fills are replaced.
+    Running,
+    /// Stopped by the user; its orders were cancelled.
+    Stopped,
+}
+
+/// State of one grid bot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GridBot {
+    /// Id handed back to the user and used as the persistence key.
+    pub id: Uuid,
+    /// User that started the bot.
+    pub owner: Address,
+    /// Vault executing the orders, if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vault_address: Option<Address>,
+    /// Grid as requested.
+    pub config: GridBotConfig,
+    /// Whether the bot is still trading.
+    pub status: GridStatus,
+    /// Grid prices, lowest first.
+    pub prices: Vec<f64>,
+    /// Size of every order.
+    pub sz: f64,
+    /// Orders of the grid, resting or waiting for their order id.
+    pub orders: Vec<GridOrder>,
+    /// Profit of the completed round trips, before fees.
+    pub realized_profit: f64,
+    /// Number of completed round trips.
+    pub round_trips: u64,
+    /// Start time in milliseconds since the Unix epoch.
+    pub created_at: u64,
+    /// Stop time in milliseconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stopped_at: Option<u64>,
+    /// Latest error met while following the grid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Orders still to be placed, retried on every poll.
+    #[serde(default)]
+    unplaced: Vec<PendingOrder>,
+    sz_decimals: u32,
+    cursor: FillCursor,
+}
+
+impl Finished for GridBot {
+    fn finished_at(&self) -> Option<u64> {
+        self.stopped_at
+    }
+}
+
+/// Order placed by a grid bot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GridOrder {
+    /// Index of the order's price in the grid.
+    pub level: usize,
+    /// Side of the order.
+    pub is_buy: bool,
+    /// Limit price.
+    pub px: f64,
+    /// Client order id.
+    pub cloid: Uuid,
+    /// Exchange-assigned order id, once known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oid: Option<u64>,
+    /// Size filled so far.
+    pub filled: f64,
+    /// Whether the order replaced a filled one, closing a round trip once
+    /// filled.
+    pub counter: bool,
+}
+
+/// Order the bot still has to place.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingOrder {
+    level: usize,
+    is_buy: bool,
+    counter: bool,
+}
+
+/// Position in the account's fills up to which they were read: the latest
+/// fill time and the trade ids already read at that time.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct FillCursor {
+    time: u64,
+    tids: Vec<u64>,
+}
+
+/// Stored copy of a grid bot, with the agent key needed to trade again after
+/// a restart.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    #[serde(
+        serialize_with = "serialize_wallet",
+        deserialize_with = "deserialize_wallet"
+    )]
+    agent: Arc<LocalWallet>,
+    #[serde(flatten)]
+    bot: GridBot,
+}
+
+/// Handle to the Redis hash backing the grid bots.
+#[derive(Clone)]
+pub struct GridStore {
+    records: RecordStore,
+}
+
+impl GridStore {
+    /// Store the grid bots over `connection`, sealed with `cipher`.
+    pub fn new(connection: ConnectionManager, cipher: RecordCipher) -> Self {
+        Self {
+            records: RecordStore::new(connection, cipher, GRID_KEY),
+        }
+    }
+
+    /// Insert or overwrite the stored copy of a grid bot.
+    async fn save(&self, bot: &GridBot, agent: &Arc<LocalWallet>) -> anyhow::Result<()> {
+        let record = Record {
+            agent: agent.clone(),
+            bot: bot.clone(),
+        };
+
+        self.records.save(bot.id, &record).await
+    }
+
+    /// Delete the stored copy of grid bot `id`.
+    async fn remove(&self, id: Uuid) -> anyhow::Result<()> {
+        self.records.remove(id).await
+    }
+
+    /// Load every stored grid bot.
+    async fn load(&self) -> anyhow::Result<Vec<Record>> {
+        self.records.load().await
+    }
+}
+
+/// Registry of the grid bots.
+pub struct GridBots;
+
+impl GridBots {
+    /// Place the initial ladder of `config` and start following it. Returns
+    /// the id of the bot.
+    pub async fn start(
+        store: &GridStore,
+        chain: Chain,
+        owner: Address,
+        agent: Arc<LocalWallet>,
+        config: GridBotConfig,
+        vault_address: Option<Address>,
+    ) -> anyhow::Result<Uuid> {
+        let info: Info = Hyperliquid::new(chain);
+        let exchange: Exchange = Hyperliquid::new(chain);
+
+        let market = market::market(&info, config.asset).await?;
+        let prices = prices(&config, &market).map_err(|err| anyhow!(err))?;
+
+        let sz = market.round_down(config.investment / (config.levels - 1) as f64 / market.mark_px);
+        if sz * prices[0] < MIN_NOTIONAL {
+            return Err(anyhow!(
+                "Investment is too small: {} per order at {} is below the ${} minimum order value",
+                sz,
+                prices[0],
+                MIN_NOTIONAL
+            ));
+        }
+
+        // The level closest to the market stays empty, as the one a filled
+        // order's replacement would otherwise land on.
+        let inside = market.mark_px >= prices[0] && market.mark_px <= prices[prices.len() - 1];
+        let nearest = prices
+            .iter()
+            .enumerate()
+            .min_by(|(_, px1), (_, px2)| {
+                (*px1 - market.mark_px)
+                    .abs()
+                    .total_cmp(&(*px2 - market.mark_px).abs())
+            })
+            .map(|(level, _)| level)
+            .filter(|_| inside);
+        let unplaced = prices
+            .iter()
+            .enumerate()
+            .filter(|(level, _)| Some(*level) != nearest)
+            .map(|(level, &px)| PendingOrder {
+                level,
+                is_buy: px < market.mark_px,
+                counter: false,
+            })
+            .collect();
+
+        let now = SystemClock.now();
+        let mut bot = GridBot {
+            id: Uuid::new_v4(),
+            owner,
+            vault_address,
+            config,
+            status: GridStatus::Running,
+            prices,
+            sz,
+            orders: Vec::new(),
+            realized_profit: 0.0,
+            round_trips: 0,
+            created_at: now,
+            stopped_at: None,
+            last_error: None,
+            unplaced,
+            sz_decimals: market.sz_decimals,
+            cursor: FillCursor {
+                time: now,
+                tids: Vec::new(),
+            },
+        };
+        for id in GRIDS.prune(now).await {
+            if let Err(err) = store.remove(id).await {
+                tracing::error!("Grid bot {}: {:?}", id, err);
+            }
+        }
+        bot.place(&exchange, store, &agent).await?;
+
+        let id = bot.id;
+        let entry = Arc::new(Entry::new(bot, agent));
+        GRIDS.insert(id, entry.clone()).await;
+        tokio::spawn(track(info, exchange, store.clone(), entry));
+
+        Ok(id)
+    }
+
+    /// Grid bots of `owner`, most recent first.
+    pub async fn list(owner: &Address) -> Vec<GridBot> {
+        let entries = GRIDS.entries().await;
+
+        let mut owned = Vec::new();
+        for entry in entries {
+            let bot = entry.bot.lock().await;
+            if bot.owner == *owner {
+                owned.push(bot.clone());
+            }
+        }
+        owned.sort_by_key(|bot| Reverse(bot.created_at));
+        owned
+    }
+
+    /// Cancel the resting orders of `owner`'s grid bot `id` and stop it.
+    pub async fn stop(
+        store: &GridStore,
+        chain: Chain,
+        owner: &Address,
+        id: Uuid,
+    ) -> anyhow::Result<()> {
+        let entry = GRIDS
+            .get(id)
+            .await
+            .ok_or_else(|| anyhow!("Grid bot not found"))?;
+
+        let mut bot = entry.bot.lock().await;
+        if bot.owner != *owner {
+            return Err(anyhow!("Grid bot not found"));
+        }
+        if bot.status != GridStatus::Running {
+            return Err(anyhow!("Grid bot is already stopped"));
+        }
+
+        let info: Info = Hyperliquid::new(chain);
+        let exchange: Exchange = Hyperliquid::new(chain);
+
+        bot.resolve(&info).await?;
+        bot.cancel_orders(&exchange, &entry.agent).await?;
+
+        let now = SystemClock.now();
+        bot.status = GridStatus::Stopped;
+        bot.stopped_at = Some(now);
+        let _ = entry.stopped_at.set(now);
+        bot.orders.clear();
+        bot.unplaced.clear();
+        store.save(&bot, &entry.agent).await?;
+
+        Ok(())
+    }
+
+    /// Reload the grid bots persisted in `store` and resume the running ones;
+    /// bots stopped longer than [`FINISHED_TTL`] ago are deleted. Returns the
+    /// number of bots resumed.
+    ///
+    /// [`FINISHED_TTL`]: crate::service::registry::FINISHED_TTL
+    pub async fn restore(store: GridStore, chain: Chain) -> anyhow::Result<usize> {
+        let now = SystemClock.now();
+
+        let mut running = 0;
+        for Record { agent, bot } in store.load().await? {
+            let id = bot.id;
+            if bot.expired(now) {
+                if let Err(err) = store.remove(id).await {
+                    tracing::error!("Grid bot {}: {:?}", id, err);
+                }
+                continue;
+            }
+
+            let resume = bot.status == GridStatus::Running;
+            let entry = Arc::new(Entry::new(bot, agent));
+            GRIDS.insert(id, entry.clone()).await;
+
+            if resume {
+                let info: Info = Hyperliquid::new(chain);
+                let exchange: Exchange = Hyperliquid::new(chain);
+                tokio::spawn(track(info, exchange, store.clone(), entry));
+                running += 1;
+            }
+        }
+
+        Ok(running)
+    }
+}
+
+impl GridBot {
+    /// Account the orders are placed for.
+    fn user(&self) -> Address {
+        self.vault_address.unwrap_or(self.owner)
+    }
+
+    /// Place the unplaced orders, replace the filled ones and account for
+    /// the completed round trips, persisting the bot if it changed.
+    async fn sync(
+        &mut self,
+        info: &Info,
+        exchange: &Exchange,
+        store: &GridStore,
+        agent: &Arc<LocalWallet>,
+    ) -> anyhow::Result<()> {
+        let mut changed = !self.unplaced.is_empty() || self.orders.iter().any(|o| o.oid.is_none());
+        // Fills are only matched to orders by their order id.
+        self.resolve(info).await?;
+
+        let mut fills = info::user_fills(info, self.user(), self.cursor.time)
+            .await
+            .map_err(|err| anyhow!("Failed to fetch fills: {}", err))?;
+        fills.sort_by_key(|fill| (fill.time, fill.tid));
+
+        let lot = 10f64.powi(-(self.sz_decimals as i32));
+        for fill in fills {
+            if fill.time < self.cursor.time
+                || (fill.time == self.cursor.time && self.cursor.tids.contains(&fill.tid))
+            {
+                continue;
+            }
+            if fill.time > self.cursor.time {
+                self.cursor.time = fill.time;
+                self.cursor.tids.clear();
+            }
+            self.cursor.tids.push(fill.tid);
+            changed = true;
+
+            let Some(index) = self.orders.iter().position(|o| o.oid == Some(fill.oid)) else {
+                continue;
+            };
+            let order = &mut self.orders[index];
+            order.filled += fill.sz;
+            if order.filled < self.sz - lot / 2.0 {
+                continue;
+            }
+
+            let order = self.orders.remove(index);
+            self.complete(&order);
+        }
+
+        if !self.unplaced.is_empty() {
+            // `place` persists the bot; the orders are kept for the next
+            // poll if it fails.
+            match self.place(exchange, store, agent).await {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    tracing::error!("Grid bot {}: {:?}", self.id, err);
+                    self.last_error = Some(err.to_string());
+                }
+            }
+        }
+
+        if changed {
+            store.save(self, agent).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Account for the filled `order` and queue its replacement one level
+    /// away.
+    fn complete(&mut self, order: &GridOrder) {
+        if order.counter {
+            let spacing = if order.is_buy {
+                self.prices[order.level + 1] - order.px
+            } else {
+                order.px - self.prices[order.level - 1]
+            };
+            self.realized_profit += self.sz * spacing;
+            self.round_trips += 1;
+        }
+
+        let level = if order.is_buy {
+            Some(order.level + 1).filter(|&level| level < self.prices.len())
+        } else {
+            order.level.checked_sub(1)
+        };
+        if let Some(level) = level {
+            self.unplaced.push(PendingOrder {
+                level,
+                is_buy: !order.is_buy,
+                counter: true,
+            });
+        }
+    }
+
+    /// Place the unplaced orders in a single batch and persist the bot. A
+    /// batch rejected as a whole is kept for the next attempt, while orders
+    /// rejected one by one are dropped.
+    async fn place(
+        &mut self,
+        exchange: &Exchange,
+        store: &GridStore,
+        agent: &Arc<LocalWallet>,
+    ) -> anyhow::Result<()> {
+        let orders: Vec<GridOrder> = self
+            .unplaced
+            .iter()
+            .map(|pending| GridOrder {
+                level: pending.level,
+                is_buy: pending.is_buy,
+                px: self.prices[pending.level],
+                cloid: Uuid::new_v4(),
+                oid: None,
+                filled: 0.0,
+                counter: pending.counter,
+            })
+            .collect();
+        let requests = orders
+            .iter()
+            .map(|order| OrderRequest {
+                asset: self.config.asset,
+                is_buy: order.is_buy,
+                limit_px: parse_price(order.px),
+                sz: parse_size(self.sz, self.sz_decimals),
+                reduce_only: false,
+                order_type: OrderType::Limit(Limit {
+                    tif: if self.config.post_only {
+                        Tif::Alo
+                    } else {
+                        Tif::Gtc
+                    },
+                }),
+                cloid: Some(order.cloid),
+            })
+            .collect();
+
+        // Orders without a status are kept without an order id until
+        // `resolve` finds out whether they reached the book.
+        let statuses = match exchange
+            .place_order(agent.clone(), requests, self.vault_address)
+            .await
+        {
+            Ok(ExchangeResponse::Err(err)) => {
+                return Err(anyhow!("Grid orders rejected: {}", err));
+            }
+            Ok(response) => place_statuses(response),
+            Err(err) => Err(anyhow!("Failed to place grid orders: {}", err)),
+        };
+        let (statuses, error) = match statuses {
+            Ok(statuses) => (statuses, None),
+            Err(err) => (Vec::new(), Some(err)),
+        };
+
+        self.unplaced.clear();
+        let mut statuses = statuses.into_iter();
+        for mut order in orders {
+            match statuses.next() {
+                Some(PlaceStatus::Resting { oid } | PlaceStatus::Filled { oid, .. }) => {
+                    order.oid = Some(oid);
+                }
+                Some(PlaceStatus::Rejected(reason)) => {
+                    tracing::info!(
+                        "Grid bot {} order at {} rejected: {}",
+                        self.id,
+                        order.px,
+                        reason
+                    );
+                    self.last_error = Some(format!("Order at {} rejected: {}", order.px, reason));
+                    continue;
+                }
+                None => {}
+            }
+            self.orders.push(order);
+        }
+        if let Some(err) = error {
+            tracing::error!("Grid bot {}: {:?}", self.id, err);
+            self.last_error = Some(err.to_string());
+        }
+
+        // Persisted right away, so a restart cannot lose track of the
+        // orders.
+        store.save(self, agent).await
+    }
+
+    /// Look up the order id of the orders whose placement was not
+    /// confirmed. Orders that left the book are dropped, and the ones
+    /// Hyperliquid does not know, which most likely never reached it, are
+    /// placed again.
+    async fn resolve(&mut self, info: &Info) -> anyhow::Result<()> {
+        let user = self.user();
+        let mut dropped = Vec::new();
+        let mut unplaced = Vec::new();
+        for order in self.orders.iter_mut().filter(|order| order.oid.is_none()) {
+            match info::order_status(info, user, order.cloid).await? {
+                Some(status) if matches!(status.status.as_str(), "open" | "filled") => {
+                    order.oid = Some(status.order.oid);
+                }
+                Some(status) => {
+                    tracing::info!(
+                        "Grid bot {} order {} at {} left the book: {}",
+                        self.id,
+                        order.cloid,
+                        order.px,
+                        status.status
+                    );
+                    dropped.push(order.cloid);
+                }
+                None => {
+                    dropped.push(order.cloid);
+                    unplaced.push(PendingOrder {
+                        level: order.level,
+                        is_buy: order.is_buy,
+                        counter: order.counter,
+                    });
+                }
+            }
+        }
+        self.orders.retain(|order| !dropped.contains(&order.cloid));
+        self.unplaced.extend(unplaced);
+
+        Ok(())
+    }
+
+    /// Cancel every order of the grid in a single batch.
+    async fn cancel_orders(
+        &self,
+        exchange: &Exchange,
+        agent: &Arc<LocalWallet>,
+    ) -> anyhow::Result<()> {
+        let cancels: Vec<CancelRequest> = self
+            .orders
+            .iter()
+            .filter_map(|order| order.oid)
+            .map(|oid| CancelRequest {
+                asset: self.config.asset,
+                oid,
+            })
+            .collect();
+        if cancels.is_empty() {
+            return Ok(());
+        }
+
+        // Orders filled since the last poll are rejected individually, which
+        // leaves the rest of the batch cancelled.
+        let response = exchange
+            .cancel_order(agent.clone(), cancels, self.vault_address)
+            .await
+            .map_err(|err| anyhow!("Failed to cancel grid orders: {}", err))?;
+        if let ExchangeResponse::Err(err) = response {
+            return Err(anyhow!("Cancel rejected: {}", err));
+        }
+
+        Ok(())
+    }
+}
+
+/// Follow the grid bot of `entry` until it is stopped.
+async fn track(info: Info, exchange: Exchange, store: GridStore, entry: Arc<Entry>) {
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let mut bot = entry.bot.lock().await;
+        if bot.status != GridStatus::Running {
+            return;
+        }
+
+        if let Err(err) = bot.sync(&info, &exchange, &store, &entry.agent).await {
+            tracing::error!("Grid bot {}: {:?}", bot.id, err);
+            bot.last_error = Some(err.to_string());
+        }
+    }
+}
+
+/// Grid prices of `config` on `market`, lowest first, rounded down to the
+/// tick grid. Fails when two levels would share a price.
+pub fn prices(config: &GridBotConfig, market: &Market) -> Result<Vec<f64>, String> {
+    let step = (config.upper_px - config.lower_px) / (config.levels - 1) as f64;
+    let prices: Vec<f64> = (0..config.levels)
+        .map(|i| market.round_px(config.lower_px + step * i as f64, false))
+        .collect();
+    if prices.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(format!(
+            "Price range is too narrow for {} levels at the tick size",
+            config.levels
+        ));
+    }
+
+    Ok(prices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::market::fixtures::{assert_close, market};
+
+    fn config(lower_px: f64, upper_px: f64, levels: u32) -> GridBotConfig {
+        GridBotConfig {
+            asset: 1,
+            lower_px,
+            upper_px,
+            levels,
+            investment: 5000.0,
+            post_only: false,
+        }
+    }
+
+    fn bot(prices: Vec<f64>) -> GridBot {
+        GridBot {
+            id: Uuid::new_v4(),
+            owner: Address::zero(),
+            vault_address: None,
+            config: config(prices[0], prices[prices.len() - 1], prices.len() as u32),
+            status: GridStatus::Running,
+            prices,
+            sz: 0.5,
+            orders: Vec::new(),
+            realized_profit: 0.0,
+            round_trips: 0,
+            created_at: 0,
+            stopped_at: None,
+            last_error: None,
+            unplaced: Vec::new(),
+            sz_decimals: 2,
+            cursor: FillCursor::default(),
+        }
+    }
+
+    fn filled(bot: &GridBot, level: usize, is_buy: bool, counter: bool) -> GridOrder {
+        GridOrder {
+            level,
+            is_buy,
+            px: bot.prices[level],
+            cloid: Uuid::new_v4(),
+            oid: Some(1),
+            filled: bot.sz,
+            counter,
+        }
+    }
+
+    fn unplaced(bot: &GridBot) -> Vec<(usize, bool, bool)> {
+        bot.unplaced
+            .iter()
+            .map(|pending| (pending.level, pending.is_buy, pending.counter))
+            .collect()
+    }
+
+    #[test]
+    fn filled_orders_are_replaced_one_level_away_on_the_other_side() {
+        let mut bot = bot(vec![2800.0, 2900.0, 3000.0, 3100.0]);
+
+        let buy = filled(&bot, 1, true, false);
+        bot.complete(&buy);
+        let sell = filled(&bot, 2, false, false);
+        bot.complete(&sell);
+
+        assert_eq!(unplaced(&bot), vec![(2, false, true), (1, true, true)]);
+        // Orders of the initial ladder close no round trip.
+        assert_eq!(bot.round_trips, 0);
+        assert_eq!(bot.realized_profit, 0.0);
+    }
+
+    #[test]
+    fn filled_counter_orders_realize_the_level_spacing() {
+        let mut bot = bot(vec![2800.0, 2900.0, 3050.0]);
+
+        // Bought at 2900, sold at 3050.
+        let sell = filled(&bot, 2, false, true);
+        bot.complete(&sell);
+        // Sold at 2900, bought back at 2800.
+        let buy = filled(&bot, 0, true, true);
+        bot.complete(&buy);
+
+        assert_eq!(bot.round_trips, 2);
+        assert_close(&[bot.realized_profit], &[0.5 * 150.0 + 0.5 * 100.0]);
+        assert_eq!(unplaced(&bot), vec![(1, true, true), (1, false, true)]);
+    }
+
+    #[test]
+    fn orders_at_the_ends_of_the_grid_are_not_replaced() {
+        let mut bot = bot(vec![2800.0, 2900.0, 3000.0]);
+
+        let buy = filled(&bot, 2, true, false);
+        bot.complete(&buy);
+        let sell = filled(&bot, 0, false, false);
+        bot.complete(&sell);
+
+        assert!(bot.unplaced.is_empty());
+    }
+
+    #[test]
+    fn prices_are_spaced_evenly_over_the_range() {
+        let prices = prices(&config(2800.0, 3200.0, 5), &market(3000.0, 2)).unwrap();
+
+        assert_close(&prices, &[2800.0, 2900.0, 3000.0, 3100.0, 3200.0]);
+    }
+
+    #[test]
+    fn prices_are_rounded_down_to_the_tick() {
+        let prices = prices(&config(2800.05, 2800.85, 3), &market(3000.0, 2)).unwrap();
+
+        assert_close(&prices, &[2800.0, 2800.4, 2800.8]);
+    }
+
+    #[test]
+    fn prices_sharing_a_tick_are_rejected() {
+        assert!(prices(&config(2800.0, 2800.1, 3), &market(3000.0, 2)).is_err());
+        assert!(prices(&config(2800.0, 2800.2, 3), &market(3000.0, 2)).is_ok());
+    }
+}
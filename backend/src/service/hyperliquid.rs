@@ -2,11 +2,20 @@
 //! layers.
 
 pub mod info {
+    use anyhow::anyhow;
     use ethers::types::Address;
     use hyperliquid::{
-        types::info::response::{CandleSnapshot, SubAccount},
+        types::{
+            info::response::{CandleSnapshot, SubAccount},
+            API,
+        },
         Info, Result,
     };
+    use uuid::Uuid;
+
+    use crate::model::hyperliquid::{
+        OrderStatus, OrderStatusRequest, OrderStatusResponse, UserFill, UserFillsRequest,
+    };
 
     /// Fetch the list of sub-accounts belonging to the provided user address.
     #[tracing::instrument(name = "Fetching sub accounts", skip(info))]
@@ -42,6 +51,200 @@ pub mod info {
         info.candle_snapshot(coin, interval, start_time, end_time)
             .await
     }
+
+    /// Look up `user`'s order by client order id; `None` when Hyperliquid
+    /// does not know it.
+    #[tracing::instrument(name = "Fetching order status", skip(info))]
+    pub async fn order_status(
+        info: &Info,
+        user: Address,
+        cloid: Uuid,
+    ) -> anyhow::Result<Option<OrderStatus>> {
+        let response: OrderStatusResponse = info
+            .client
+            .post(
+                &API::Info,
+                &OrderStatusRequest {
+                    type_: "orderStatus".into(),
+                    user,
+                    oid: format!("0x{}", cloid.simple()),
+                },
+            )
+            .await
+            .map_err(|err| anyhow!("Failed to fetch order status: {}", err))?;
+
+        Ok(match response {
+            OrderStatusResponse::Order { order } => Some(order),
+            OrderStatusResponse::UnknownOid => None,
+        })
+    }
+
+    /// Fetch `user`'s fills since `start_time`.
+    #[tracing::instrument(name = "Fetching user fills", skip(info))]
+    pub async fn user_fills(info: &Info, user: Address, start_time: u64) -> Result<Vec<UserFill>> {
+        info.client
+            .post(
+                &API::Info,
+                &UserFillsRequest {
+                    type_: "userFillsByTime".into(),
+                    user,
+                    start_time,
+                },
+            )
+            .await
+    }
+}
+
+/// Helpers reading the responses of exchange actions.
+pub mod exchange {
+    use anyhow::anyhow;
+    use hyperliquid::types::exchange::response::Response;
+    use serde_json::Value;
+
+    /// Outcome of one order of a `place_order` request.
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum PlaceStatus {
+        /// The order rests on the book.
+        Resting { oid: u64 },
+        /// The order filled `total_sz` at an average of `avg_px` right away.
+        Filled {
+            oid: u64,
+            total_sz: f64,
+            avg_px: f64,
+        },
+        /// Hyperliquid rejected the order.
+        Rejected(String),
+    }
+
+    /// Per-order statuses of a `place_order` response, in request order.
+    pub fn place_statuses(response: Response) -> anyhow::Result<Vec<PlaceStatus>> {
+        let data = match response {
+            Response::Ok(data) => serde_json::to_value(data)?,
+            Response::Err(err) => return Err(anyhow!("Orders rejected: {}", err)),
+        };
+
+        statuses(&data)
+    }
+
+    /// Statuses of the wire form of a response,
+    /// `{"type": "order", "data": {"statuses": [...]}}`.
+    fn statuses(data: &Value) -> anyhow::Result<Vec<PlaceStatus>> {
+        find(data, "statuses")
+            .and_then(Value::as_array)
+            .ok_or_else(|| anyhow!("Response has no order statuses: {}", data))?
+            .iter()
+            .map(status)
+            .collect()
+    }
+
+    fn status(value: &Value) -> anyhow::Result<PlaceStatus> {
+        if let Some(resting) = field(value, "resting") {
+            return Ok(PlaceStatus::Resting {
+                oid: number(resting, "oid")? as u64,
+            });
+        }
+        if let Some(filled) = field(value, "filled") {
+            return Ok(PlaceStatus::Filled {
+                oid: number(filled, "oid")? as u64,
+                total_sz: number(filled, "totalSz")?,
+                avg_px: number(filled, "avgPx")?,
+            });
+        }
+        if let Some(error) = field(value, "error").and_then(Value::as_str) {
+            return Ok(PlaceStatus::Rejected(error.to_string()));
+        }
+
+        Err(anyhow!("Unexpected order status: {}", value))
+    }
+
+    /// Field `name` of `value`, ignoring case and underscores so both the wire
+    /// names and the SDK's serialized names match.
+    fn field<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+        let normalize = |key: &str| key.replace('_', "").to_lowercase();
+
+        value
+            .as_object()?
+            .iter()
+            .find(|(key, _)| normalize(key) == normalize(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Number in field `name`, sent either as a number or a decimal string.
+    fn number(value: &Value, name: &str) -> anyhow::Result<f64> {
+        match field(value, name) {
+            Some(Value::Number(number)) => number.as_f64(),
+            Some(Value::String(number)) => number.parse().ok(),
+            _ => None,
+        }
+        .ok_or_else(|| anyhow!("Order status has no {}: {}", name, value))
+    }
+
+    /// First field `name` found in `value` or nested in it, matched like
+    /// [`field`].
+    fn find<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+        field(value, name).or_else(|| {
+            value
+                .as_object()?
+                .values()
+                .find_map(|value| find(value, name))
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use serde_json::json;
+
+        use super::*;
+
+        #[test]
+        fn reads_every_status_in_request_order() {
+            let data = json!({
+                "type": "order",
+                "data": {
+                    "statuses": [
+                        { "resting": { "oid": 77738308 } },
+                        { "filled": { "totalSz": "0.02", "avgPx": "1891.4", "oid": 77747314 } },
+                        { "error": "Order must have minimum value of $10." }
+                    ]
+                }
+            });
+
+            assert_eq!(
+                statuses(&data).unwrap(),
+                vec![
+                    PlaceStatus::Resting { oid: 77738308 },
+                    PlaceStatus::Filled {
+                        oid: 77747314,
+                        total_sz: 0.02,
+                        avg_px: 1891.4
+                    },
+                    PlaceStatus::Rejected("Order must have minimum value of $10.".into()),
+                ]
+            );
+        }
+
+        #[test]
+        fn matches_serialized_sdk_names() {
+            let data = json!({
+                "Order": { "Statuses": [{ "Filled": { "total_sz": 1.5, "avg_px": "2", "oid": 3 } }] }
+            });
+
+            assert_eq!(
+                statuses(&data).unwrap(),
+                vec![PlaceStatus::Filled {
+                    oid: 3,
+                    total_sz: 1.5,
+                    avg_px: 2.0
+                }]
+            );
+        }
+
+        #[test]
+        fn rejects_responses_without_statuses() {
+            assert!(statuses(&json!({ "type": "cancel" })).is_err());
+            assert!(statuses(&json!({ "statuses": [{ "waitingForFill": null }] })).is_err());
+        }
+    }
 }
 
 /// Utilities for composing higher-level synthetic data from Hyperliquid
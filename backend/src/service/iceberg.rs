@@ -0,0 +1,466 @@
+//! Iceberg orders: a large limit order worked in clips of a display size, so
+//! the book only ever shows one clip of it.
+//!
+//! Each iceberg is followed by its own task polling the visible clip's
+//! `orderStatus` by client order id. Once the clip is filled, the next one is
+//! placed at the limit price or, with [`Reprice::Touch`], at the best price
+//! of the iceberg's side, until the total size is done. A clip leaving the
+//! book any other way (cancelled outside the backend, rejected) ends the
+//! iceberg, and so do [`MAX_CONSECUTIVE_ERRORS`] failed polls in a row.
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use ethers::{signers::LocalWallet, types::Address};
+use hyperliquid::{
+    types::{
+        exchange::{
+            request::{CancelRequest, Limit, OrderRequest, OrderType, Tif},
+            response::Response as ExchangeResponse,
+        },
+        Chain,
+    },
+    utils::{parse_price, parse_size},
+    Exchange, Hyperliquid, Info,
+};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    model::hyperliquid::{IcebergOrder, Reprice},
+    service::{
+        hyperliquid::{
+            exchange::{place_statuses, PlaceStatus},
+            info,
+        },
+        market::{self, Market, MIN_NOTIONAL},
+        registry::Registry,
+    },
+};
+
+/// How often the visible clip is polled.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Failed polls in a row after which an iceberg gives up.
+const MAX_CONSECUTIVE_ERRORS: usize = 5;
+
+lazy_static! {
+    /// Icebergs still being worked, keyed by iceberg id.
+    static ref ICEBERGS: Registry<Mutex<Iceberg>> = Registry::default();
+}
+
+/// Tracked state of one iceberg order.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Iceberg {
+    /// Id handed back to the user.
+    pub id: Uuid,
+    /// User that placed the iceberg.
+    pub owner: Address,
+    /// Wallet signing the clip and cancel requests.
+    #[serde(skip)]
+    agent: Arc<LocalWallet>,
+    /// Vault executing the clips, if set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vault_address: Option<Address>,
+    /// Iceberg as requested.
+    pub order: IcebergOrder,
+    /// Client order id of the visible clip.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clip: Option<Uuid>,
+    /// Order id of the visible clip, once its placement was confirmed.
+    #[serde(skip)]
+    clip_oid: Option<u64>,
+    /// Price of the visible clip.
+    pub clip_px: f64,
+    /// Number of clips placed so far.
+    pub clips: u64,
+    /// Total size filled so far.
+    pub filled: f64,
+    /// Size filled by clips that left the book.
+    #[serde(skip)]
+    settled: f64,
+    #[serde(skip)]
+    market: Market,
+    /// Polls that failed in a row.
+    #[serde(skip)]
+    errors: usize,
+    /// Set once the iceberg was torn down, stopping its task.
+    #[serde(skip)]
+    closed: bool,
+}
+
+/// Registry of the iceberg orders being worked.
+pub struct Icebergs;
+
+impl Icebergs {
+    /// Place the first clip of `order` and start replenishing it. Returns the
+    /// id of the iceberg.
+    pub async fn open(
+        chain: Chain,
+        owner: Address,
+        agent: Arc<LocalWallet>,
+        order: IcebergOrder,
+        vault_address: Option<Address>,
+    ) -> anyhow::Result<Uuid> {
+        let info: Info = Hyperliquid::new(chain);
+        let exchange: Exchange = Hyperliquid::new(chain);
+
+        let market = market::market(&info, order.asset).await?;
+
+        let id = Uuid::new_v4();
+        let mut iceberg = Iceberg {
+            id,
+            owner,
+            agent,
+            vault_address,
+            order,
+            clip: None,
+            clip_oid: None,
+            clip_px: 0.0,
+            clips: 0,
+            filled: 0.0,
+            settled: 0.0,
+            market,
+            errors: 0,
+            closed: false,
+        };
+        // A rejected first clip fails the request. One whose placement is
+        // unconfirmed is followed like any other, the first poll finds out
+        // whether it reached the book.
+        match iceberg.replenish(&info, &exchange).await {
+            Ok(Placed::Working) => {}
+            Ok(Placed::Done) => return Err(anyhow!("Iceberg size is below one lot")),
+            Ok(Placed::Rejected(reason)) => return Err(anyhow!("Clip rejected: {}", reason)),
+            Err(err) => tracing::error!("Iceberg {}: {:?}", id, err),
+        }
+
+        let iceberg = Arc::new(Mutex::new(iceberg));
+        ICEBERGS.insert(id, iceberg.clone()).await;
+        tokio::spawn(track(info, exchange, iceberg));
+
+        Ok(id)
+    }
+
+    /// Icebergs of `owner` that are still being worked.
+    pub async fn list(owner: &Address) -> Vec<Iceberg> {
+        let icebergs = ICEBERGS.entries().await;
+
+        let mut owned = Vec::new();
+        for iceberg in icebergs {
+            let iceberg = iceberg.lock().await;
+            if iceberg.owner == *owner && !iceberg.closed {
+                owned.push(iceberg.clone());
+            }
+        }
+        owned
+    }
+
+    /// Cancel the visible clip of `owner`'s iceberg `id` and stop placing
+    /// new ones.
+    pub async fn cancel(chain: Chain, owner: &Address, id: Uuid) -> anyhow::Result<()> {
+        let iceberg = ICEBERGS
+            .get(id)
+            .await
+            .ok_or_else(|| anyhow!("Iceberg order not found"))?;
+
+        let mut iceberg = iceberg.lock().await;
+        if iceberg.owner != *owner || iceberg.closed {
+            return Err(anyhow!("Iceberg order not found"));
+        }
+
+        let info: Info = Hyperliquid::new(chain);
+        let exchange: Exchange = Hyperliquid::new(chain);
+
+        iceberg.cancel_clip(&info, &exchange).await?;
+
+        iceberg.closed = true;
+        ICEBERGS.remove(id).await;
+
+        Ok(())
+    }
+}
+
+impl Iceberg {
+    /// Account the clips are placed for.
+    fn user(&self) -> Address {
+        self.vault_address.unwrap_or(self.owner)
+    }
+
+    /// Follow the visible clip, placing the next one once it filled. Returns
+    /// whether the iceberg is still being worked.
+    async fn sync(&mut self, info: &Info, exchange: &Exchange) -> anyhow::Result<bool> {
+        if let Some(clip) = self.clip {
+            let Some(status) = info::order_status(info, self.user(), clip).await? else {
+                if self.clip_oid.is_some() {
+                    // A confirmed clip is only missing from a lagging status.
+                    return Err(anyhow!("Clip {} is unknown", clip));
+                }
+                // One whose placement failed most likely never reached the
+                // book, so it is placed again.
+                self.clip = None;
+                self.clips -= 1;
+                return self.next(info, exchange).await;
+            };
+            self.clip_oid = Some(status.order.oid);
+
+            let filled = status.filled();
+            self.filled = self.settled + filled;
+
+            match status.status.as_str() {
+                "open" => return Ok(true),
+                "filled" => {}
+                other => {
+                    tracing::info!("Iceberg {} clip {} left the book: {}", self.id, clip, other);
+                    return Ok(false);
+                }
+            }
+
+            self.settled += filled;
+            self.clip = None;
+        }
+
+        self.next(info, exchange).await
+    }
+
+    /// [`Iceberg::replenish`], ending the iceberg once its size is done or a
+    /// clip is rejected.
+    async fn next(&mut self, info: &Info, exchange: &Exchange) -> anyhow::Result<bool> {
+        match self.replenish(info, exchange).await? {
+            Placed::Working => Ok(true),
+            Placed::Done => Ok(false),
+            Placed::Rejected(reason) => {
+                tracing::warn!("Iceberg {} clip rejected: {}", self.id, reason);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Place the next clip. A clip whose placement fails is kept without an
+    /// order id until a poll finds out whether it reached the book.
+    async fn replenish(&mut self, info: &Info, exchange: &Exchange) -> anyhow::Result<Placed> {
+        let Some(sz) = clip_sz(&self.market, &self.order, self.order.sz - self.settled) else {
+            return Ok(Placed::Done);
+        };
+
+        let limit_px = self.price(info).await?;
+        let cloid = Uuid::new_v4();
+        let order = OrderRequest {
+            asset: self.order.asset,
+            is_buy: self.order.is_buy,
+            limit_px: parse_price(limit_px),
+            sz: parse_size(sz, self.market.sz_decimals),
+            reduce_only: self.order.reduce_only,
+            order_type: OrderType::Limit(Limit {
+                tif: if self.order.post_only {
+                    Tif::Alo
+                } else {
+                    Tif::Gtc
+                },
+            }),
+            cloid: Some(cloid),
+        };
+
+        let status = match exchange
+            .place_order(self.agent.clone(), vec![order], self.vault_address)
+            .await
+        {
+            Ok(ExchangeResponse::Err(err)) => return Ok(Placed::Rejected(err)),
+            Ok(response) => place_statuses(response).map(|statuses| statuses.into_iter().next()),
+            Err(err) => Err(anyhow!("Failed to place clip: {}", err)),
+        };
+        if let Ok(Some(PlaceStatus::Rejected(reason))) = status {
+            return Ok(Placed::Rejected(reason));
+        }
+
+        self.clip = Some(cloid);
+        self.clip_oid = None;
+        self.clip_px = limit_px;
+        self.clips += 1;
+
+        match status? {
+            Some(PlaceStatus::Resting { oid }) => self.clip_oid = Some(oid),
+            Some(PlaceStatus::Filled { total_sz, .. }) => {
+                self.settled += total_sz;
+                self.filled = self.settled;
+                self.clip = None;
+            }
+            Some(PlaceStatus::Rejected(_)) | None => {
+                return Err(anyhow!("No status for clip {}", cloid));
+            }
+        }
+
+        Ok(Placed::Working)
+    }
+
+    /// Price of the next clip.
+    async fn price(&self, info: &Info) -> anyhow::Result<f64> {
+        if self.order.reprice == Reprice::Same {
+            return Ok(self.order.limit_px);
+        }
+
+        let book = info
+            .l2_book(self.market.coin.clone())
+            .await
+            .map_err(|err| anyhow!("Failed to fetch the book: {}", err))?;
+        let side = if self.order.is_buy { 0 } else { 1 };
+        let touch = book
+            .levels
+            .get(side)
+            .and_then(|levels| levels.first())
+            .and_then(|level| level.px.parse::<f64>().ok());
+
+        // Never past the limit, and at the limit on an empty side.
+        Ok(match touch {
+            Some(px) if self.order.is_buy => px.min(self.order.limit_px),
+            Some(px) => px.max(self.order.limit_px),
+            None => self.order.limit_px,
+        })
+    }
+
+    /// Cancel the visible clip if it still rests.
+    async fn cancel_clip(&self, info: &Info, exchange: &Exchange) -> anyhow::Result<()> {
+        let Some(clip) = self.clip else {
+            return Ok(());
+        };
+        let Some(status) = info::order_status(info, self.user(), clip).await? else {
+            return Ok(());
+        };
+        if status.status != "open" {
+            return Ok(());
+        }
+
+        let cancel = CancelRequest {
+            asset: self.order.asset,
+            oid: status.order.oid,
+        };
+        let response = exchange
+            .cancel_order(self.agent.clone(), vec![cancel], self.vault_address)
+            .await
+            .map_err(|err| anyhow!("Failed to cancel clip: {}", err))?;
+        if let ExchangeResponse::Err(err) = response {
+            return Err(anyhow!("Cancel rejected: {}", err));
+        }
+
+        Ok(())
+    }
+}
+
+/// Work `iceberg` until its total size is done, a clip leaves the book
+/// unfilled, it is cancelled or its polls keep failing.
+async fn track(info: Info, exchange: Exchange, iceberg: Arc<Mutex<Iceberg>>) {
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        let mut iceberg = iceberg.lock().await;
+        if iceberg.closed {
+            return;
+        }
+
+        match iceberg.sync(&info, &exchange).await {
+            Ok(true) => {
+                iceberg.errors = 0;
+                continue;
+            }
+            Ok(false) => {
+                tracing::info!(
+                    "Iceberg {} done with {} filled over {} clips",
+                    iceberg.id,
+                    iceberg.filled,
+                    iceberg.clips
+                );
+            }
+            Err(err) => {
+                tracing::error!("Iceberg {}: {:?}", iceberg.id, err);
+                iceberg.errors += 1;
+                if iceberg.errors < MAX_CONSECUTIVE_ERRORS {
+                    continue;
+                }
+
+                tracing::error!(
+                    "Iceberg {} gave up after {} failed polls",
+                    iceberg.id,
+                    iceberg.errors
+                );
+                if let Err(err) = iceberg.cancel_clip(&info, &exchange).await {
+                    tracing::error!("Iceberg {}: {:?}", iceberg.id, err);
+                }
+            }
+        }
+
+        iceberg.closed = true;
+        ICEBERGS.remove(iceberg.id).await;
+        return;
+    }
+}
+
+/// Size of the next clip of `order` with `remaining` left to fill, or `None`
+/// once less than a lot remains. A remainder too small to be placed on its
+/// own goes out with the clip.
+fn clip_sz(market: &Market, order: &IcebergOrder, remaining: f64) -> Option<f64> {
+    let mut sz = market.round_down(order.display_sz.min(remaining));
+    if remaining - sz < MIN_NOTIONAL / order.limit_px {
+        sz = market.round_down(remaining);
+    }
+    (sz >= market.lot()).then_some(sz)
+}
+
+/// Outcome of placing the next clip.
+enum Placed {
+    /// The clip rests on the book, filled right away or awaits confirmation.
+    Working,
+    /// The total size is done, no clip was placed.
+    Done,
+    /// Hyperliquid rejected the clip.
+    Rejected(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::market::fixtures::{assert_close, market};
+
+    fn order(sz: f64, display_sz: f64) -> IcebergOrder {
+        IcebergOrder {
+            asset: 1,
+            is_buy: true,
+            limit_px: 100.0,
+            sz,
+            display_sz,
+            reduce_only: false,
+            post_only: false,
+            reprice: Reprice::Same,
+        }
+    }
+
+    #[test]
+    fn clips_are_the_display_size_rounded_to_whole_lots() {
+        let market = market(100.0, 2);
+
+        assert_close(clip_sz(&market, &order(5.0, 1.0), 5.0).as_slice(), &[1.0]);
+        assert_close(clip_sz(&market, &order(5.0, 1.005), 5.0).as_slice(), &[1.0]);
+    }
+
+    #[test]
+    fn small_remainders_go_out_with_the_last_clip() {
+        let market = market(100.0, 2);
+        let order = order(5.0, 1.0);
+
+        // 0.05 left after a 1.0 clip is worth $5, below the minimum.
+        assert_close(clip_sz(&market, &order, 1.05).as_slice(), &[1.05]);
+        // 0.1 left is worth $10 and gets its own clip.
+        assert_close(clip_sz(&market, &order, 1.1).as_slice(), &[1.0]);
+        assert_close(clip_sz(&market, &order, 0.1).as_slice(), &[0.1]);
+    }
+
+    #[test]
+    fn nothing_is_placed_below_one_lot() {
+        let market = market(100.0, 2);
+        let order = order(5.0, 1.0);
+
+        assert_close(clip_sz(&market, &order, 0.009).as_slice(), &[]);
+        assert_close(clip_sz(&market, &order, 0.0).as_slice(), &[]);
+    }
+}
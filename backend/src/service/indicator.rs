@@ -0,0 +1,566 @@
+//! Streaming technical indicators computed from closed candles.
+//!
+//! The same [`Indicator`] state machines back both the `indicator` condition,
+//! which feeds them closed bars from the candle stream, and the `indicator`
+//! info request, which replays [`info::candle_snapshot`] history through them.
+//! The values the frontend overlays are therefore the values the worker
+//! trades on.
+
+use anyhow::anyhow;
+use hyperliquid::{types::info::response::CandleSnapshot, Info};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::{
+    model::hyperliquid::{Candle, IndicatorRequest},
+    service::hyperliquid::info,
+};
+
+/// Price data of one candle.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Ohlc {
+    /// Opening timestamp in milliseconds since the Unix epoch.
+    pub open_time: u64,
+    /// Closing timestamp in milliseconds since the Unix epoch.
+    pub close_time: u64,
+    /// Opening price.
+    pub open: f64,
+    /// Highest price of the bar.
+    pub high: f64,
+    /// Lowest price of the bar.
+    pub low: f64,
+    /// Closing (or, for the open bar, latest) price.
+    pub close: f64,
+}
+
+impl From<&Candle> for Ohlc {
+    fn from(candle: &Candle) -> Self {
+        Self {
+            open_time: candle.open_time as u64,
+            close_time: candle.close_time as u64,
+            open: candle.open_price,
+            high: candle.high_price,
+            low: candle.low_price,
+            close: candle.close_price,
+        }
+    }
+}
+
+impl TryFrom<&CandleSnapshot> for Ohlc {
+    type Error = anyhow::Error;
+
+    fn try_from(candle: &CandleSnapshot) -> anyhow::Result<Self> {
+        Ok(Self {
+            open_time: candle.t,
+            close_time: candle.t_,
+            open: candle.o.parse()?,
+            high: candle.h.parse()?,
+            low: candle.l.parse()?,
+            close: candle.c.parse()?,
+        })
+    }
+}
+
+/// Bollinger band selected by an indicator.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Band {
+    /// Moving average plus `std_dev` standard deviations.
+    Upper,
+    /// Moving average.
+    Middle,
+    /// Moving average minus `std_dev` standard deviations.
+    Lower,
+}
+
+/// Indicator and its parameters.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum IndicatorSpec {
+    /// Simple moving average of closes.
+    Sma { period: usize },
+    /// Exponential moving average of closes, seeded with the SMA.
+    Ema { period: usize },
+    /// Wilder's relative strength index, between 0 and 100.
+    Rsi { period: usize },
+    /// Bollinger band around the SMA of closes.
+    #[serde(rename_all = "camelCase")]
+    Bollinger {
+        period: usize,
+        std_dev: f64,
+        band: Band,
+    },
+    /// Wilder's average true range.
+    Atr { period: usize },
+}
+
+impl IndicatorSpec {
+    fn period(&self) -> usize {
+        match *self {
+            IndicatorSpec::Sma { period }
+            | IndicatorSpec::Ema { period }
+            | IndicatorSpec::Rsi { period }
+            | IndicatorSpec::Bollinger { period, .. }
+            | IndicatorSpec::Atr { period } => period,
+        }
+    }
+
+    /// Reject parameters the indicator cannot be computed with.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.period() == 0 {
+            return Err(anyhow!("Indicator period must be positive"));
+        }
+        if let IndicatorSpec::Bollinger { std_dev, .. } = self {
+            if !std_dev.is_finite() || *std_dev < 0.0 {
+                return Err(anyhow!("Bollinger deviation must be a non-negative number"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Number of closed bars to replay before the value is trusted.
+    /// Smoothed indicators get extra bars so their seed has decayed.
+    pub fn warmup(&self) -> usize {
+        let period = self.period();
+
+        match self {
+            IndicatorSpec::Sma { .. } | IndicatorSpec::Bollinger { .. } => period,
+            IndicatorSpec::Ema { .. } | IndicatorSpec::Rsi { .. } | IndicatorSpec::Atr { .. } => {
+                period * 3 + 1
+            }
+        }
+    }
+}
+
+/// Streaming state of one indicator, updated once per closed bar.
+#[derive(Debug, Clone)]
+pub struct Indicator {
+    spec: IndicatorSpec,
+    state: State,
+}
+
+#[derive(Debug, Clone)]
+enum State {
+    /// Last `period` closes, for SMA and Bollinger bands.
+    Window(VecDeque<f64>),
+    /// Running average seeded with the plain mean of the first `period`
+    /// samples, for EMA, RSI (gains in `up`, losses in `down`) and ATR.
+    Smoothed {
+        prev_close: Option<f64>,
+        seen: usize,
+        up: f64,
+        down: f64,
+    },
+}
+
+impl Indicator {
+    /// Empty indicator; it yields values once enough bars were fed.
+    pub fn new(spec: IndicatorSpec) -> Self {
+        let state = match spec {
+            IndicatorSpec::Sma { period } | IndicatorSpec::Bollinger { period, .. } => {
+                State::Window(VecDeque::with_capacity(period))
+            }
+            IndicatorSpec::Ema { .. } | IndicatorSpec::Rsi { .. } | IndicatorSpec::Atr { .. } => {
+                State::Smoothed {
+                    prev_close: None,
+                    seen: 0,
+                    up: 0.0,
+                    down: 0.0,
+                }
+            }
+        };
+
+        Self { spec, state }
+    }
+
+    /// Indicator fed with every bar of `bars`.
+    pub fn seeded(spec: IndicatorSpec, bars: &[Ohlc]) -> Self {
+        let mut indicator = Self::new(spec);
+        for bar in bars {
+            indicator.update(bar);
+        }
+        indicator
+    }
+
+    /// Feed the next closed bar.
+    pub fn update(&mut self, bar: &Ohlc) {
+        let period = self.spec.period();
+        let n = period as f64;
+
+        match &mut self.state {
+            State::Window(closes) => {
+                if closes.len() == period {
+                    closes.pop_front();
+                }
+                closes.push_back(bar.close);
+            }
+            State::Smoothed {
+                prev_close,
+                seen,
+                up,
+                down,
+            } => {
+                let (gain, loss) = match self.spec {
+                    IndicatorSpec::Ema { .. } => (bar.close, 0.0),
+                    IndicatorSpec::Rsi { .. } => {
+                        let Some(prev) = prev_close.replace(bar.close) else {
+                            return;
+                        };
+                        let change = bar.close - prev;
+                        (change.max(0.0), (-change).max(0.0))
+                    }
+                    IndicatorSpec::Atr { .. } => {
+                        let range = match prev_close.replace(bar.close) {
+                            Some(prev) => (bar.high - bar.low)
+                                .max((bar.high - prev).abs())
+                                .max((bar.low - prev).abs()),
+                            None => bar.high - bar.low,
+                        };
+                        (range, 0.0)
+                    }
+                    IndicatorSpec::Sma { .. } | IndicatorSpec::Bollinger { .. } => return,
+                };
+
+                *seen += 1;
+                if *seen <= period {
+                    *up += gain / n;
+                    *down += loss / n;
+                } else if let IndicatorSpec::Ema { .. } = self.spec {
+                    let alpha = 2.0 / (n + 1.0);
+                    *up += alpha * (gain - *up);
+                } else {
+                    *up = (*up * (n - 1.0) + gain) / n;
+                    *down = (*down * (n - 1.0) + loss) / n;
+                }
+            }
+        }
+    }
+
+    /// Current value, once enough bars were fed.
+    pub fn value(&self) -> Option<f64> {
+        let period = self.spec.period();
+
+        match &self.state {
+            State::Window(closes) => {
+                if closes.len() < period {
+                    return None;
+                }
+
+                let mean = closes.iter().sum::<f64>() / period as f64;
+                let IndicatorSpec::Bollinger { std_dev, band, .. } = self.spec else {
+                    return Some(mean);
+                };
+
+                let variance = closes
+                    .iter()
+                    .map(|close| (close - mean).powi(2))
+                    .sum::<f64>()
+                    / period as f64;
+                let width = std_dev * variance.sqrt();
+
+                Some(match band {
+                    Band::Upper => mean + width,
+                    Band::Middle => mean,
+                    Band::Lower => mean - width,
+                })
+            }
+            State::Smoothed { seen, up, down, .. } => {
+                if *seen < period {
+                    return None;
+                }
+
+                match self.spec {
+                    IndicatorSpec::Rsi { .. } if *down == 0.0 => {
+                        Some(if *up == 0.0 { 50.0 } else { 100.0 })
+                    }
+                    IndicatorSpec::Rsi { .. } => Some(100.0 - 100.0 / (1.0 + up / down)),
+                    _ => Some(*up),
+                }
+            }
+        }
+    }
+}
+
+/// Indicator value at the close of one bar.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndicatorPoint {
+    /// Closing timestamp of the bar.
+    pub time: u64,
+    /// Indicator value, `None` while warming up.
+    pub value: Option<f64>,
+}
+
+/// Length of a Hyperliquid candle interval in milliseconds. Monthly candles
+/// (`1M`) follow calendar months, which have no fixed length, so they are
+/// left out: history windows and sampling periods cannot be sized in them.
+pub fn interval_millis(interval: &str) -> Option<u64> {
+    const MINUTE: u64 = 60_000;
+
+    let minutes = match interval {
+        "1m" => 1,
+        "3m" => 3,
+        "5m" => 5,
+        "15m" => 15,
+        "30m" => 30,
+        "1h" => 60,
+        "2h" => 120,
+        "4h" => 240,
+        "8h" => 480,
+        "12h" => 720,
+        "1d" => 1_440,
+        "3d" => 4_320,
+        "1w" => 10_080,
+        _ => return None,
+    };
+
+    Some(minutes * MINUTE)
+}
+
+/// Whether `interval` is a Hyperliquid candle interval, calendar months
+/// included.
+pub fn is_interval(interval: &str) -> bool {
+    interval == "1M" || interval_millis(interval).is_some()
+}
+
+/// Closed bars of `coin` over the `bars` intervals before `end_time`.
+pub async fn history(
+    info: &Info,
+    coin: &str,
+    interval: &str,
+    bars: usize,
+    end_time: u64,
+) -> anyhow::Result<Vec<Ohlc>> {
+    let length = interval_millis(interval)
+        .ok_or_else(|| anyhow!("Unsupported candle interval {}", interval))?;
+    let start_time = end_time.saturating_sub(length * bars as u64);
+
+    closed_bars(info, coin, interval, start_time, end_time).await
+}
+
+/// Indicator series for the `indicator` info request. Bars before
+/// `start_time` are fetched to warm the indicator up and left out of the
+/// result.
+pub async fn query(info: &Info, req: &IndicatorRequest) -> anyhow::Result<Vec<IndicatorPoint>> {
+    req.indicator.validate()?;
+
+    let length = interval_millis(&req.interval)
+        .ok_or_else(|| anyhow!("Unsupported candle interval {}", req.interval))?;
+    let warmup_start = req
+        .start_time
+        .saturating_sub(length * req.indicator.warmup() as u64);
+
+    let bars = closed_bars(info, &req.coin, &req.interval, warmup_start, req.end_time).await?;
+
+    let mut indicator = Indicator::new(req.indicator);
+    let points = bars
+        .iter()
+        .filter_map(|bar| {
+            indicator.update(bar);
+            (bar.close_time >= req.start_time).then(|| IndicatorPoint {
+                time: bar.close_time,
+                value: indicator.value(),
+            })
+        })
+        .collect();
+
+    Ok(points)
+}
+
+/// Candles of `coin` closed before `end_time`, oldest first.
+async fn closed_bars(
+    info: &Info,
+    coin: &str,
+    interval: &str,
+    start_time: u64,
+    end_time: u64,
+) -> anyhow::Result<Vec<Ohlc>> {
+    let candles = info::candle_snapshot(
+        info,
+        coin.to_string(),
+        interval.to_string(),
+        start_time,
+        end_time,
+    )
+    .await
+    .map_err(|err| anyhow!("Failed to fetch candle history: {}", err))?;
+
+    closed(&candles, end_time)
+}
+
+/// Bars of `candles` closed before `end_time`, oldest first.
+fn closed(candles: &[CandleSnapshot], end_time: u64) -> anyhow::Result<Vec<Ohlc>> {
+    let mut bars = candles
+        .iter()
+        .map(Ohlc::try_from)
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    bars.retain(|bar| bar.close_time < end_time);
+    bars.sort_by_key(|bar| bar.open_time);
+
+    Ok(bars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(high: f64, low: f64, close: f64) -> Ohlc {
+        Ohlc {
+            open_time: 0,
+            close_time: 0,
+            open: close,
+            high,
+            low,
+            close,
+        }
+    }
+
+    fn closes(closes: &[f64]) -> Vec<Ohlc> {
+        closes
+            .iter()
+            .map(|&close| bar(close, close, close))
+            .collect()
+    }
+
+    /// Values of an indicator fed `bars` one by one.
+    fn series(spec: IndicatorSpec, bars: &[Ohlc]) -> Vec<Option<f64>> {
+        let mut indicator = Indicator::new(spec);
+        bars.iter()
+            .map(|bar| {
+                indicator.update(bar);
+                indicator.value()
+            })
+            .collect()
+    }
+
+    fn assert_values(actual: Vec<Option<f64>>, expected: &[Option<f64>]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected) {
+            match (actual, expected) {
+                (Some(actual), Some(expected)) => {
+                    assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}")
+                }
+                _ => assert_eq!(actual, expected),
+            }
+        }
+    }
+
+    #[test]
+    fn sma_averages_the_last_period_closes() {
+        let values = series(
+            IndicatorSpec::Sma { period: 3 },
+            &closes(&[1.0, 2.0, 3.0, 4.0, 5.0]),
+        );
+        assert_values(values, &[None, None, Some(2.0), Some(3.0), Some(4.0)]);
+    }
+
+    #[test]
+    fn ema_is_seeded_with_the_sma() {
+        // alpha = 2 / (3 + 1) = 0.5 once the SMA of the first three closes
+        // seeded it.
+        let values = series(
+            IndicatorSpec::Ema { period: 3 },
+            &closes(&[1.0, 2.0, 3.0, 4.0, 5.0]),
+        );
+        assert_values(values, &[None, None, Some(2.0), Some(3.0), Some(4.0)]);
+    }
+
+    #[test]
+    fn rsi_smooths_gains_and_losses() {
+        // Changes +1, -0.5, +1.5: average gain 0.5 and loss 0.25 after two,
+        // then 1.0 and 0.125 with Wilder's smoothing.
+        let values = series(
+            IndicatorSpec::Rsi { period: 2 },
+            &closes(&[10.0, 11.0, 10.5, 12.0]),
+        );
+        assert_values(
+            values,
+            &[
+                None,
+                None,
+                Some(100.0 - 100.0 / 3.0),
+                Some(100.0 - 100.0 / 9.0),
+            ],
+        );
+
+        let flat = series(IndicatorSpec::Rsi { period: 2 }, &closes(&[5.0; 3]));
+        assert_eq!(flat[2], Some(50.0));
+        let rising = series(IndicatorSpec::Rsi { period: 2 }, &closes(&[1.0, 2.0, 3.0]));
+        assert_eq!(rising[2], Some(100.0));
+    }
+
+    #[test]
+    fn bollinger_bands_use_the_population_deviation() {
+        let bars = closes(&[1.0, 2.0, 3.0]);
+        let width = 2.0 * (2.0f64 / 3.0).sqrt();
+
+        for (band, expected) in [
+            (Band::Upper, 2.0 + width),
+            (Band::Middle, 2.0),
+            (Band::Lower, 2.0 - width),
+        ] {
+            let spec = IndicatorSpec::Bollinger {
+                period: 3,
+                std_dev: 2.0,
+                band,
+            };
+            assert_values(series(spec, &bars), &[None, None, Some(expected)]);
+        }
+    }
+
+    #[test]
+    fn atr_counts_gaps_from_the_previous_close() {
+        // True ranges 2, 2, 3 and, for the gap up from 12, 15 - 12 = 3.
+        let bars = [
+            bar(10.0, 8.0, 9.0),
+            bar(11.0, 9.0, 10.0),
+            bar(13.0, 10.0, 12.0),
+            bar(15.0, 14.0, 14.5),
+        ];
+        let values = series(IndicatorSpec::Atr { period: 2 }, &bars);
+        assert_values(values, &[None, Some(2.0), Some(2.5), Some(2.75)]);
+    }
+
+    #[test]
+    fn history_feeds_closed_bars_oldest_first() {
+        let candle = |open_time: u64, close: &str| {
+            serde_json::from_value::<CandleSnapshot>(serde_json::json!({
+                "t": open_time,
+                "T": open_time + 59_999,
+                "s": "BTC",
+                "i": "1m",
+                "o": close,
+                "c": close,
+                "h": close,
+                "l": close,
+                "v": "1",
+                "n": 1,
+            }))
+            .unwrap()
+        };
+        // Out of order, with the bar still open at `end_time` last.
+        let candles = [
+            candle(120_000, "3"),
+            candle(0, "1"),
+            candle(60_000, "2"),
+            candle(180_000, "100"),
+        ];
+
+        let bars = closed(&candles, 200_000).unwrap();
+        assert_eq!(
+            bars.iter().map(|bar| bar.close).collect::<Vec<_>>(),
+            vec![1.0, 2.0, 3.0]
+        );
+        let sma = Indicator::seeded(IndicatorSpec::Sma { period: 3 }, &bars);
+        assert_eq!(sma.value(), Some(2.0));
+    }
+
+    #[test]
+    fn monthly_candles_have_no_fixed_length() {
+        assert_eq!(interval_millis("1M"), None);
+        assert!(is_interval("1M"));
+        assert_eq!(interval_millis("1h"), Some(3_600_000));
+    }
+}
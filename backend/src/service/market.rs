@@ -0,0 +1,168 @@
+//! Price and size constraints of the assets orders are placed on.
+//!
+//! Every service building orders itself (TWAP slices, chases, icebergs,
+//! grids and scaled ladders) sizes and prices them through [`Market`], so
+//! they are rounded the way Hyperliquid accepts them.
+
+use anyhow::anyhow;
+use hyperliquid::{types::info::response::AssetContext, Info};
+
+/// Smallest order value Hyperliquid accepts, in USD.
+pub const MIN_NOTIONAL: f64 = 10.0;
+
+/// Price and size constraints of an asset.
+#[derive(Debug, Clone)]
+pub struct Market {
+    /// Name of the asset's book.
+    pub coin: String,
+    /// Current mark price.
+    pub mark_px: f64,
+    /// Number of decimals order sizes are rounded to.
+    pub sz_decimals: u32,
+}
+
+impl Market {
+    /// Smallest size increment.
+    pub fn lot(&self) -> f64 {
+        10f64.powi(-(self.sz_decimals as i32))
+    }
+
+    /// Smallest accepted order size: [`MIN_NOTIONAL`] at the mark price,
+    /// rounded up to a whole lot.
+    pub fn min_sz(&self) -> f64 {
+        let lot = self.lot();
+        (MIN_NOTIONAL / self.mark_px / lot).ceil().max(1.0) * lot
+    }
+
+    /// `sz` rounded down to a whole number of lots.
+    pub fn round_down(&self, sz: f64) -> f64 {
+        let lot = self.lot();
+        // The epsilon keeps sizes that are whole lots up to float error.
+        (sz / lot + 1e-9).floor() * lot
+    }
+
+    /// Price increment at `px`. Prices have at most five significant figures
+    /// and `6 - sz_decimals` decimals; integer prices are always valid.
+    pub fn tick(&self, px: f64) -> f64 {
+        let significant = 4 - px.log10().floor() as i32;
+        let decimals = significant.min(6 - self.sz_decimals as i32).max(0);
+        10f64.powi(-decimals)
+    }
+
+    /// `px` rounded to a valid price, `up` or down.
+    pub fn round_px(&self, px: f64, up: bool) -> f64 {
+        let tick = self.tick(px);
+        let ticks = px / tick;
+        // The epsilon keeps prices that are whole ticks up to float error.
+        let ticks = if up {
+            (ticks - 1e-9).ceil()
+        } else {
+            (ticks + 1e-9).floor()
+        };
+        ticks * tick
+    }
+}
+
+/// Book name, mark price and size decimals of `asset`.
+pub async fn market(info: &Info, asset: u32) -> anyhow::Result<Market> {
+    let ctxs = info
+        .contexts()
+        .await
+        .map_err(|err| anyhow!("Failed to fetch asset contexts: {}", err))?;
+
+    let (Some(AssetContext::Meta(meta)), Some(AssetContext::Ctx(asset_ctxs))) =
+        (ctxs.first(), ctxs.get(1))
+    else {
+        return Err(anyhow!("Failed to get asset contexts"));
+    };
+
+    let universe = meta
+        .universe
+        .get(asset as usize)
+        .ok_or_else(|| anyhow!("Failed to get sz_decimals"))?;
+    let mark_px: f64 = asset_ctxs
+        .get(asset as usize)
+        .ok_or_else(|| anyhow!("Failed to get mark price"))?
+        .mark_px
+        .parse()?;
+
+    Ok(Market {
+        coin: universe.name.clone(),
+        mark_px,
+        sz_decimals: universe.sz_decimals as u32,
+    })
+}
+
+/// Fixtures shared by the tests of the services sizing orders through
+/// [`Market`].
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::Market;
+
+    /// ETH market at `mark_px`, with sizes rounded to `sz_decimals`.
+    pub fn market(mark_px: f64, sz_decimals: u32) -> Market {
+        Market {
+            coin: "ETH".into(),
+            mark_px,
+            sz_decimals,
+        }
+    }
+
+    /// Assert that `actual` and `expected` are equal up to float error.
+    pub fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(
+            actual.len(),
+            expected.len(),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fixtures::{assert_close, market};
+
+    #[test]
+    fn round_down_keeps_whole_lots() {
+        let market = market(100.0, 2);
+
+        assert_eq!(market.round_down(0.1 + 0.2), 0.3);
+        assert_eq!(market.round_down(1.239), 1.23);
+        assert_eq!(market.round_down(0.009), 0.0);
+    }
+
+    #[test]
+    fn min_sz_is_the_minimum_notional_in_whole_lots() {
+        assert_close(&[market(100.0, 2).min_sz()], &[0.1]);
+        assert_close(&[market(3.0, 1).min_sz()], &[3.4]);
+        // Never below one lot.
+        assert_close(&[market(1e6, 2).min_sz()], &[0.01]);
+    }
+
+    #[test]
+    fn ticks_keep_five_significant_figures_within_the_size_decimals() {
+        let market = market(100.0, 2);
+
+        assert_close(&[market.tick(1234.5)], &[0.1]);
+        assert_close(&[market.tick(100.1)], &[0.01]);
+        // At most 6 - 2 decimals.
+        assert_close(&[market.tick(0.012345)], &[0.0001]);
+        // Integer prices are always valid.
+        assert_close(&[market.tick(123456.0)], &[1.0]);
+    }
+
+    #[test]
+    fn round_px_rounds_to_the_tick_in_either_direction() {
+        let market = market(100.0, 2);
+
+        assert_close(&[market.round_px(1234.56, false)], &[1234.5]);
+        assert_close(&[market.round_px(1234.56, true)], &[1234.6]);
+        assert_close(&[market.round_px(1234.5, true)], &[1234.5]);
+        assert_close(&[market.round_px(1234.5, false)], &[1234.5]);
+    }
+}
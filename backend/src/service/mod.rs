@@ -2,6 +2,33 @@
 //!
 //! Each service module contains thin wrappers that translate backend requests
 //! into SDK calls or other IO operations. The Hyperliquid service currently
-//! covers REST/WS helper logic shared across the API and websocket handlers.
+//! covers REST/WS helper logic shared across the API and websocket handlers,
+//! while the queue service persists conditional orders in Redis (sealing the
+//! agent keys they carry with the cipher service) and the indicator service
+//! computes technical indicators from candles.
+//!
+//! The scaled service builds ladders of limit orders, the grid service runs
+//! grid bots persisted in Redis, and the order services work orders over
+//! time: the bracket service attaches take-profit/stop-loss legs to filled
+//! entries (kept across restarts through the store service), the chase
+//! service keeps post-only orders at the top of the book, the iceberg service
+//! works large orders in small visible clips, and the TWAP service tracks and
+//! executes TWAP jobs, sized by the volume profiles of the VWAP service for
+//! VWAP orders. They size and price orders through the market service and
+//! keep them in the registries of the registry service.
 
+pub mod bracket;
+pub mod chase;
+pub mod cipher;
+pub mod clock;
+pub mod grid;
 pub mod hyperliquid;
+pub mod iceberg;
+pub mod indicator;
+pub mod market;
+pub mod queue;
+pub mod registry;
+pub mod scaled;
+pub mod store;
+pub mod twap;
+pub mod vwap;
@@ -0,0 +1,1161 @@
+//! Conditional-order queue: in-memory index plus Redis-backed persistence.
+//!
+//! Every [`QueueElem`] is stored as JSON in a single Redis hash keyed by its
+//! id, and `main.rs` reloads whatever is left on boot so pending triggers
+//! survive deploys and crashes. Entries carry the agent key that signs the
+//! order, so each payload is sealed with [`RecordCipher`] before it is written.
+//!
+//! In memory, [`CondQueue`] indexes entries by the streams (book, trades,
+//! asset context, ...) their conditions depend on. A small forwarder task per
+//! stream watches the shared receiver in `CONNECTIONS` and records the topic in
+//! [`Wakeups`] whenever it changes, so the background worker only re-evaluates
+//! the conditions that could have flipped instead of spinning over the whole
+//! queue. Entries with time conditions or an expiry are also kept in a
+//! deadline index and re-evaluated when the worker's timer reaches
+//! [`CondQueue::next_deadline`].
+//!
+//! Orders that leave the queue without firing (cancelled or expired), as well
+//! as executed ones, are recorded per owner as [`ClosedCondOrder`]s.
+
+use anyhow::Context;
+use ethers::types::Address;
+use hyperliquid::{types::Chain, Hyperliquid, Info};
+use lazy_static::lazy_static;
+use redis::{aio::ConnectionManager, AsyncCommands};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    sync::{watch, Notify, RwLock, RwLockReadGuard},
+    task::JoinHandle,
+};
+use uuid::Uuid;
+
+use crate::{
+    model::hyperliquid::{
+        CloseReason, ClosedCondOrder, CondAction, Condition, Feeds, QueueElem, QueueElemView,
+        Subscribe, TrailingLevel,
+    },
+    service::{cipher::RecordCipher, clock::Clock},
+    ws::hyperliquid::book_price::Feed,
+};
+
+/// Redis hash holding the serialized queue entries.
+const QUEUE_KEY: &str = "cond_orders";
+
+/// Prefix of the per-owner Redis lists holding closed-order records.
+const CLOSED_KEY_PREFIX: &str = "cond_orders_closed";
+
+/// Number of closed-order records kept per owner.
+const CLOSED_HISTORY_LEN: isize = 100;
+
+/// Minimum time in milliseconds between two saves of a moving trailing stop.
+/// In a trending market the extreme moves on almost every book update; a
+/// restart within this interval resumes from a slightly older extreme.
+const TRAILING_SAVE_INTERVAL: u64 = 5_000;
+
+/// Time in milliseconds before entries put back after a failed retirement
+/// are looked at again on the clock, so a Redis outage is not retried in a
+/// busy loop.
+const RETIRE_RETRY_DELAY: u64 = 1_000;
+
+/// Handle to the Redis hash backing the conditional-order queue.
+#[derive(Clone)]
+pub struct QueueStore {
+    connection: ConnectionManager,
+    cipher: RecordCipher,
+}
+
+impl QueueStore {
+    /// Store the queue over `connection`, sealing entries with `cipher`.
+    pub fn new(connection: ConnectionManager, cipher: RecordCipher) -> Self {
+        Self { connection, cipher }
+    }
+
+    /// Insert or overwrite the stored copy of a queue entry.
+    pub async fn save(&self, elem: &QueueElem) -> anyhow::Result<()> {
+        self.put(elem.id, &self.seal(elem)?).await
+    }
+
+    /// Serialize and seal `elem` for [`QueueStore::put`], so the copy can be
+    /// taken under the queue lock and written once it is released.
+    pub fn seal(&self, elem: &QueueElem) -> anyhow::Result<String> {
+        let payload = serde_json::to_string(elem).context("Failed to serialize queue entry")?;
+        self.cipher.seal(&payload)
+    }
+
+    /// Insert or overwrite the stored copy of entry `id` with `sealed`.
+    pub async fn put(&self, id: Uuid, sealed: &str) -> anyhow::Result<()> {
+        self.connection
+            .clone()
+            .hset::<_, _, _, ()>(QUEUE_KEY, id.to_string(), sealed)
+            .await
+            .context("Failed to persist queue entry")?;
+
+        Ok(())
+    }
+
+    /// Load every persisted queue entry. Entries that can no longer be decoded
+    /// are logged and skipped so a single bad record cannot block the boot.
+    pub async fn load(&self) -> anyhow::Result<Vec<QueueElem>> {
+        let entries: HashMap<String, String> = self
+            .connection
+            .clone()
+            .hgetall(QUEUE_KEY)
+            .await
+            .context("Failed to load queue entries")?;
+
+        let mut elems = Vec::with_capacity(entries.len());
+        for (id, payload) in entries {
+            let decoded = self.cipher.open(&payload).and_then(|json| {
+                serde_json::from_str::<QueueElem>(&json).context("Invalid queue entry")
+            });
+
+            match decoded {
+                Ok(elem) => elems.push(elem),
+                Err(err) => tracing::error!("Failed to decode queue entry {}: {:?}", id, err),
+            }
+        }
+
+        Ok(elems)
+    }
+
+    /// Delete the queue entries `ids` and record `closed` in one `MULTI`, so
+    /// a crash cannot leave some of them persisted (e.g. the siblings of an
+    /// executed one-cancels-other order) or drop their history.
+    pub async fn retire(&self, ids: &[Uuid], closed: &[ClosedCondOrder]) -> anyhow::Result<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+
+        if !ids.is_empty() {
+            let fields = ids.iter().map(Uuid::to_string).collect::<Vec<_>>();
+            pipe.hdel(QUEUE_KEY, fields).ignore();
+        }
+
+        for record in closed {
+            let key = closed_key(&record.owner);
+            let payload =
+                serde_json::to_string(record).context("Failed to serialize closed order record")?;
+
+            pipe.lpush(&key, payload)
+                .ignore()
+                .ltrim(&key, 0, CLOSED_HISTORY_LEN - 1)
+                .ignore();
+        }
+
+        pipe.query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .context("Failed to retire queue entries")?;
+
+        Ok(())
+    }
+
+    /// Prepend a closed-order record to its owner's history, trimming the
+    /// history to the most recent [`CLOSED_HISTORY_LEN`] entries.
+    pub async fn record_closed(&self, closed: &ClosedCondOrder) -> anyhow::Result<()> {
+        let key = closed_key(&closed.owner);
+        let payload =
+            serde_json::to_string(closed).context("Failed to serialize closed order record")?;
+
+        let mut connection = self.connection.clone();
+        connection
+            .lpush::<_, _, ()>(&key, payload)
+            .await
+            .context("Failed to record closed order")?;
+        connection
+            .ltrim::<_, ()>(&key, 0, CLOSED_HISTORY_LEN - 1)
+            .await
+            .context("Failed to trim closed order history")?;
+
+        Ok(())
+    }
+
+    /// Closed-order records of `owner`, most recent first.
+    pub async fn closed(&self, owner: &Address) -> anyhow::Result<Vec<ClosedCondOrder>> {
+        let entries: Vec<String> = self
+            .connection
+            .clone()
+            .lrange(closed_key(owner), 0, -1)
+            .await
+            .context("Failed to load closed order history")?;
+
+        let records = entries
+            .into_iter()
+            .filter_map(|payload| match serde_json::from_str(&payload) {
+                Ok(record) => Some(record),
+                Err(err) => {
+                    tracing::error!("Failed to decode closed order record: {}", err);
+                    None
+                }
+            })
+            .collect();
+
+        Ok(records)
+    }
+}
+
+/// Redis key of the closed-order history of `owner`.
+fn closed_key(owner: &Address) -> String {
+    format!("{}:{:?}", CLOSED_KEY_PREFIX, owner)
+}
+
+lazy_static! {
+    /// Latest trailing-stop levels of queued orders, keyed by order id.
+    static ref TRAILING_LEVELS: Mutex<HashMap<Uuid, watch::Sender<Vec<TrailingLevel>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Watch channels publishing the trailing-stop levels of queued orders to
+/// websocket clients. A channel closes when its order leaves the queue or is
+/// amended to a condition without trailing stop.
+pub struct TrailingLevels;
+
+impl TrailingLevels {
+    /// Publish the current levels of order `id`.
+    fn publish(id: Uuid, levels: Vec<TrailingLevel>) {
+        let mut channels = TRAILING_LEVELS.lock().expect("trailing levels poisoned");
+
+        match channels.get(&id) {
+            Some(sender) => {
+                sender.send_replace(levels);
+            }
+            None => {
+                channels.insert(id, watch::channel(levels).0);
+            }
+        }
+    }
+
+    /// Close the channel of order `id`, ending its streams.
+    fn close(id: Uuid) {
+        TRAILING_LEVELS
+            .lock()
+            .expect("trailing levels poisoned")
+            .remove(&id);
+    }
+
+    /// Follow the levels of order `id`, if it has a trailing stop.
+    pub fn receiver(id: Uuid) -> Option<watch::Receiver<Vec<TrailingLevel>>> {
+        TRAILING_LEVELS
+            .lock()
+            .expect("trailing levels poisoned")
+            .get(&id)
+            .map(watch::Sender::subscribe)
+    }
+}
+
+/// Coalescing set of topics whose market data changed since the worker last
+/// looked. Repeated updates for the same topic collapse into a single wakeup.
+#[derive(Default)]
+pub struct Wakeups {
+    pending: Mutex<HashSet<Subscribe>>,
+    notify: Notify,
+}
+
+impl Wakeups {
+    /// Mark `topic` as changed and wake the worker.
+    pub fn wake(&self, topic: &Subscribe) {
+        self.pending
+            .lock()
+            .expect("wakeup set poisoned")
+            .insert(topic.clone());
+        self.notify.notify_one();
+    }
+
+    /// Wake the worker without marking any topic, so it recomputes its timer
+    /// after an entry with a time boundary was added or changed.
+    pub fn reschedule(&self) {
+        self.notify.notify_one();
+    }
+
+    /// Wait until a topic changed or the worker was rescheduled, and drain
+    /// the pending set. The returned set is empty for a bare reschedule.
+    pub async fn wait(&self) -> HashSet<Subscribe> {
+        let pending = self.take();
+        if !pending.is_empty() {
+            return pending;
+        }
+
+        self.notify.notified().await;
+        self.take()
+    }
+
+    fn take(&self) -> HashSet<Subscribe> {
+        std::mem::take(&mut *self.pending.lock().expect("wakeup set poisoned"))
+    }
+}
+
+/// Queue entries depending on a topic, plus the task forwarding its updates.
+struct Watched {
+    ids: HashSet<Uuid>,
+    forwarder: JoinHandle<()>,
+}
+
+/// In-memory part of the queue, guarded by the lock inside [`CondQueue`].
+///
+/// Only CPU work happens on it: every subscription, seeding request and Redis
+/// write is done by [`CondQueue`] before the lock is taken or after it is
+/// released.
+#[derive(Default)]
+pub struct QueueState {
+    elems: HashMap<Uuid, QueueElem>,
+    index: HashMap<Subscribe, Watched>,
+    feeds: Feeds,
+    deadlines: Deadlines,
+    /// Entries with a trailing move not persisted yet, keyed by when it is
+    /// due to be saved.
+    unsaved: Deadlines,
+    /// Revision of each entry changed since it was queued, bumped whenever
+    /// the change has to reach Redis.
+    revisions: HashMap<Uuid, u64>,
+}
+
+/// Outcome of one evaluation pass over the queue.
+#[derive(Default)]
+struct Evaluation {
+    /// Entries whose condition fired.
+    fired: Vec<QueueElem>,
+    /// Entries that left without firing, with the reason.
+    closed: Vec<(QueueElem, CloseReason)>,
+    /// Entries whose persisted state (trailing extreme, relative baseline)
+    /// changed and need to be saved.
+    changed: Vec<Uuid>,
+}
+
+/// Next time boundary of every entry that has one, ordered so the worker
+/// finds the earliest and the due ones without scanning the queue.
+#[derive(Default)]
+struct Deadlines {
+    queue: BTreeSet<(u64, Uuid)>,
+    by_id: HashMap<Uuid, u64>,
+}
+
+impl Deadlines {
+    /// Set or clear the deadline of entry `id`.
+    fn schedule(&mut self, id: Uuid, at: Option<u64>) {
+        if let Some(previous) = self.by_id.remove(&id) {
+            self.queue.remove(&(previous, id));
+        }
+        if let Some(at) = at {
+            self.queue.insert((at, id));
+            self.by_id.insert(id, at);
+        }
+    }
+
+    /// Whether entry `id` has a deadline.
+    fn contains(&self, id: Uuid) -> bool {
+        self.by_id.contains_key(&id)
+    }
+
+    /// Earliest scheduled deadline.
+    fn next(&self) -> Option<u64> {
+        self.queue.first().map(|&(at, _)| at)
+    }
+
+    /// Remove and return the entries whose deadline is at or before `now`.
+    fn pop_due(&mut self, now: u64) -> Vec<Uuid> {
+        let mut due = Vec::new();
+
+        while let Some(&(at, id)) = self.queue.first() {
+            if at > now {
+                break;
+            }
+            self.queue.pop_first();
+            self.by_id.remove(&id);
+            due.push(id);
+        }
+
+        due
+    }
+}
+
+impl QueueState {
+    /// Iterate over the pending entries.
+    pub fn iter(&self) -> impl Iterator<Item = &QueueElem> {
+        self.elems.values()
+    }
+
+    /// Look up a pending entry by id.
+    pub fn get(&self, id: Uuid) -> Option<&QueueElem> {
+        self.elems.get(&id)
+    }
+
+    /// Number of pending entries.
+    pub fn len(&self) -> usize {
+        self.elems.len()
+    }
+
+    /// Whether the queue has no pending entries.
+    pub fn is_empty(&self) -> bool {
+        self.elems.is_empty()
+    }
+
+    /// Evaluate the entries depending on one of `topics` and the ones whose
+    /// deadline has come, removing fired, expired and group-cancelled
+    /// entries from memory. Evaluated entries are rescheduled at their next
+    /// time boundary. Trailing moves are saved at most once per
+    /// [`TRAILING_SAVE_INTERVAL`]; pinned baselines right away.
+    async fn evaluate(&mut self, topics: &HashSet<Subscribe>, clock: &dyn Clock) -> Evaluation {
+        let now = clock.now();
+        let mut evaluation = Evaluation::default();
+
+        let ids = topics
+            .iter()
+            .filter_map(|topic| self.index.get(topic))
+            .flat_map(|watched| watched.ids.iter().copied())
+            .chain(self.deadlines.pop_due(now))
+            .collect::<HashSet<_>>();
+
+        let mut triggered = Vec::new();
+
+        for id in ids {
+            if self.elems.get(&id).is_some_and(|elem| elem.is_expired(now)) {
+                if let Some(elem) = self.remove(id) {
+                    tracing::info!("Conditional order {} expired", id);
+                    evaluation.closed.push((elem, CloseReason::Expired));
+                }
+                continue;
+            }
+
+            let Some(elem) = self.elems.get_mut(&id) else {
+                continue;
+            };
+
+            let levels = elem.condition.trailing_levels();
+            let unpinned = elem.condition.unpinned_baselines();
+
+            match elem.check(&self.feeds, clock).await {
+                Ok(Some(true)) => triggered.push(id),
+                Ok(Some(false) | None) => {}
+                Err(err) => tracing::error!("{:?}", err),
+            }
+
+            self.deadlines.schedule(id, elem.next_deadline(now));
+
+            // Trailing stops moved: publish the level to websocket clients
+            // and have the new extreme persisted so a restart resumes from
+            // it, batching the moves of one interval into a single save.
+            let levels_now = elem.condition.trailing_levels();
+            if levels_now != levels {
+                TrailingLevels::publish(id, levels_now);
+                if !self.unsaved.contains(id) {
+                    self.unsaved
+                        .schedule(id, Some(now + TRAILING_SAVE_INTERVAL));
+                }
+            }
+
+            // A baseline pinned by this update changes the trigger level, so
+            // it is saved at once, along with any unsaved trailing move.
+            if elem.condition.unpinned_baselines() < unpinned {
+                self.unsaved.schedule(id, None);
+                evaluation.changed.push(id);
+            }
+        }
+
+        for id in triggered {
+            // Skip members already cancelled by a sibling that fired first.
+            let Some(elem) = self.remove(id) else {
+                continue;
+            };
+
+            let siblings = self
+                .elems
+                .values()
+                .filter(|other| elem.is_sibling(other))
+                .map(|other| other.id)
+                .collect::<Vec<_>>();
+
+            for sibling in siblings {
+                if let Some(sibling) = self.remove(sibling) {
+                    tracing::info!(
+                        "Conditional order {} cancelled by group member {}",
+                        sibling.id,
+                        elem.id
+                    );
+                    evaluation
+                        .closed
+                        .push((sibling, CloseReason::GroupCancelled));
+                }
+            }
+
+            evaluation.fired.push(elem);
+        }
+
+        evaluation.changed.extend(self.unsaved.pop_due(now));
+        for id in &evaluation.changed {
+            self.touch(*id);
+        }
+
+        evaluation
+    }
+
+    /// Mark entry `id` as changed since its last save.
+    fn touch(&mut self, id: Uuid) {
+        *self.revisions.entry(id).or_default() += 1;
+    }
+
+    /// Revision of entry `id`, if it is still queued.
+    fn revision(&self, id: Uuid) -> Option<u64> {
+        self.elems
+            .contains_key(&id)
+            .then(|| self.revisions.get(&id).copied().unwrap_or_default())
+    }
+
+    /// Drop an entry from memory and from the topic index. The caller
+    /// releases its subscriptions and persisted copy once the lock is gone.
+    fn remove(&mut self, id: Uuid) -> Option<QueueElem> {
+        let elem = self.elems.remove(&id)?;
+        self.unindex(&elem);
+        self.unsaved.schedule(id, None);
+        self.revisions.remove(&id);
+        TrailingLevels::close(id);
+
+        Some(elem)
+    }
+
+    /// Register an entry under each of its topics, spawning a forwarder for
+    /// topics nobody else was watching, and publish its trailing levels,
+    /// opening the channel if needed.
+    /// `receivers` are the stream receivers of the entry's topics, fetched
+    /// before the lock was taken.
+    ///
+    /// Entries with a time leaf or an expiry are due right away, at `now`:
+    /// their first evaluation also catches boundaries that passed while the
+    /// server was down, and later ones are scheduled from its outcome.
+    fn index(&mut self, elem: &QueueElem, mut receivers: Feeds, wakeups: &Arc<Wakeups>, now: u64) {
+        if elem.has_deadline() {
+            self.deadlines.schedule(elem.id, Some(now));
+        }
+
+        if elem.condition.has_trailing_stop() {
+            TrailingLevels::publish(elem.id, elem.condition.trailing_levels());
+        }
+
+        for topic in elem.condition.topics() {
+            if let Some(watched) = self.index.get_mut(&topic) {
+                watched.ids.insert(elem.id);
+                continue;
+            }
+
+            let Some(receiver) = receivers.remove(&topic) else {
+                tracing::warn!("No stream to watch for {:?}", topic);
+                continue;
+            };
+
+            let mut changes = receiver.clone();
+            let wakeups = wakeups.clone();
+            let watched_topic = topic.clone();
+            let forwarder = tokio::spawn(async move {
+                while changes.changed().await.is_ok() {
+                    wakeups.wake(&watched_topic);
+                }
+            });
+
+            self.feeds.insert(topic.clone(), receiver);
+            self.index.insert(
+                topic,
+                Watched {
+                    ids: HashSet::from([elem.id]),
+                    forwarder,
+                },
+            );
+        }
+    }
+
+    /// Drop an entry from the topic index and the deadlines, stopping
+    /// forwarders that no longer have any dependent entry.
+    fn unindex(&mut self, elem: &QueueElem) {
+        self.deadlines.schedule(elem.id, None);
+
+        for topic in elem.condition.topics() {
+            let Some(watched) = self.index.get_mut(&topic) else {
+                continue;
+            };
+
+            watched.ids.remove(&elem.id);
+            if watched.ids.is_empty() {
+                if let Some(watched) = self.index.remove(&topic) {
+                    watched.forwarder.abort();
+                }
+                self.feeds.remove(&topic);
+            }
+        }
+    }
+}
+
+/// Pending conditional orders indexed by the topics they depend on.
+///
+/// Every mutation goes through this type so the in-memory entries, the symbol
+/// index, the refcounts in `CONNECTIONS` and the Redis copy stay in sync. The
+/// in-memory [`QueueState`] sits behind a lock that is only held for CPU work,
+/// so request handlers are never stuck behind the worker's Redis writes or
+/// another request's stream subscription.
+pub struct CondQueue {
+    state: RwLock<QueueState>,
+    store: QueueStore,
+    wakeups: Arc<Wakeups>,
+    clock: Arc<dyn Clock>,
+    chain: Chain,
+}
+
+impl CondQueue {
+    /// Rebuild the queue from the entries persisted in `store`, taking the
+    /// websocket subscriptions each restored condition relies on and
+    /// re-seeding indicator conditions from `chain`'s candle history.
+    pub async fn restore(
+        store: QueueStore,
+        wakeups: Arc<Wakeups>,
+        clock: Arc<dyn Clock>,
+        chain: Chain,
+    ) -> anyhow::Result<Self> {
+        let queue = Self {
+            state: RwLock::new(QueueState::default()),
+            store,
+            wakeups,
+            clock,
+            chain,
+        };
+
+        for mut elem in queue.store.load().await? {
+            queue.seed(&mut elem.condition).await;
+            if let Err(err) = elem.subscribe().await {
+                tracing::error!("Failed to resubscribe queued order {}: {:?}", elem.id, err);
+            }
+            let receivers = receivers(&elem.condition).await;
+
+            let mut state = queue.state.write().await;
+            state.index(&elem, receivers, &queue.wakeups, queue.clock.now());
+            state.elems.insert(elem.id, elem);
+        }
+
+        // Restored entries with a time boundary are due now; have the worker
+        // compute its timer from them instead of waiting for a market update.
+        queue.wakeups.reschedule();
+
+        Ok(queue)
+    }
+
+    /// Read access to the pending entries.
+    pub async fn read(&self) -> RwLockReadGuard<'_, QueueState> {
+        self.state.read().await
+    }
+
+    /// Look up a pending entry by id.
+    pub async fn get(&self, id: Uuid) -> Option<RwLockReadGuard<'_, QueueElem>> {
+        RwLockReadGuard::try_map(self.state.read().await, |state| state.elems.get(&id)).ok()
+    }
+
+    /// Current time according to the queue's clock.
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Closed-order records of `owner`, most recent first.
+    pub async fn history(&self, owner: &Address) -> anyhow::Result<Vec<ClosedCondOrder>> {
+        self.store.closed(owner).await
+    }
+
+    /// Earliest instant at which some entry needs a look without any market
+    /// update, for time conditions and expiries, or a trailing move is due to
+    /// be saved.
+    pub async fn next_deadline(&self) -> Option<u64> {
+        let state = self.state.read().await;
+
+        match (state.deadlines.next(), state.unsaved.next()) {
+            (Some(deadline), Some(save)) => Some(deadline.min(save)),
+            (deadline, save) => deadline.or(save),
+        }
+    }
+
+    /// Subscribe, persist and index a new entry. Nothing is kept if any step
+    /// fails. The entry is only locked into memory once its streams and its
+    /// Redis copy are in place.
+    pub async fn insert(&self, mut elem: QueueElem) -> anyhow::Result<()> {
+        elem.condition.arm(self.clock.now());
+        self.seed(&mut elem.condition).await;
+        elem.subscribe().await?;
+
+        if let Err(err) = self.store.save(&elem).await {
+            elem.unsubscribe().await;
+            return Err(err);
+        }
+
+        let receivers = receivers(&elem.condition).await;
+        let topics = elem.condition.topics();
+
+        {
+            let mut state = self.state.write().await;
+            state.index(&elem, receivers, &self.wakeups, self.clock.now());
+            state.elems.insert(elem.id, elem);
+        }
+
+        // Evaluate against the data already cached for the topics instead of
+        // waiting for the next update.
+        for topic in topics {
+            self.wakeups.wake(&topic);
+        }
+        self.wakeups.reschedule();
+
+        Ok(())
+    }
+
+    /// Withdraw an entry on behalf of its owner and record the cancellation.
+    pub async fn cancel(&self, id: Uuid) -> Option<QueueElem> {
+        let elem = self.state.write().await.remove(id)?;
+
+        let closed = ClosedCondOrder::new(&elem, CloseReason::Cancelled, self.clock.now());
+        if let Err(err) = self.store.retire(&[id], &[closed]).await {
+            tracing::error!("{:?}", err);
+        }
+        elem.unsubscribe().await;
+
+        Some(elem)
+    }
+
+    /// Replace the condition and/or action of an entry and return its new
+    /// view. A new condition is seeded and subscribed before the lock is
+    /// taken and before the old one is released, so shared streams are never
+    /// torn down and reopened needlessly. Clients following the trailing
+    /// levels keep their stream and receive the new levels, unless the new
+    /// condition has none. The entry is saved once the lock is released.
+    pub async fn amend(
+        &self,
+        id: Uuid,
+        condition: Option<Condition>,
+        action: Option<CondAction>,
+    ) -> anyhow::Result<Option<QueueElemView>> {
+        let prepared = match condition {
+            Some(mut condition) => {
+                condition.arm(self.clock.now());
+                self.seed(&mut condition).await;
+                condition.subscribe().await?;
+                let receivers = receivers(&condition).await;
+
+                Some((condition, receivers))
+            }
+            None => None,
+        };
+
+        let mut state = self.state.write().await;
+
+        let Some(mut elem) = state.elems.remove(&id) else {
+            drop(state);
+            if let Some((condition, _)) = prepared {
+                condition.unsubscribe().await;
+            }
+            return Ok(None);
+        };
+
+        let previous = prepared.map(|(condition, receivers)| {
+            state.unindex(&elem);
+            let previous = std::mem::replace(&mut elem.condition, condition);
+            state.index(&elem, receivers, &self.wakeups, self.clock.now());
+            if !elem.condition.has_trailing_stop() {
+                TrailingLevels::close(id);
+            }
+
+            for topic in elem.condition.topics() {
+                self.wakeups.wake(&topic);
+            }
+            self.wakeups.reschedule();
+
+            previous
+        });
+
+        if let Some(action) = action {
+            elem.action = action;
+        }
+
+        let view = elem.view();
+        state.elems.insert(id, elem);
+        state.unsaved.schedule(id, None);
+        state.touch(id);
+        drop(state);
+
+        self.persist(id).await;
+        if let Some(previous) = previous {
+            previous.unsubscribe().await;
+        }
+
+        Ok(Some(view))
+    }
+
+    /// Evaluate every entry depending on one of `topics` or due on the clock
+    /// and remove the ones whose condition is met, returning them for
+    /// execution. Due entries past their expiry are dropped instead. The
+    /// persisted copies are dropped first so a crash mid-execution cannot
+    /// replay them; if that fails, nothing is returned and the entries stay
+    /// queued. The group siblings of a fired entry are removed in the same
+    /// pass, so at most one member of a group ever executes.
+    ///
+    /// The write lock only covers the evaluation; Redis writes and stream
+    /// releases happen once it is released.
+    pub async fn take_triggered(&self, topics: &HashSet<Subscribe>) -> Vec<QueueElem> {
+        let evaluation = self
+            .state
+            .write()
+            .await
+            .evaluate(topics, self.clock.as_ref())
+            .await;
+
+        for id in evaluation.changed {
+            self.persist(id).await;
+        }
+
+        // Fired entries leave Redis together with the siblings they cancelled
+        // and the records of those siblings, before anything is executed.
+        let now = self.clock.now();
+        let ids = evaluation
+            .fired
+            .iter()
+            .chain(evaluation.closed.iter().map(|(elem, _)| elem))
+            .map(|elem| elem.id)
+            .collect::<Vec<_>>();
+        let records = evaluation
+            .closed
+            .iter()
+            .map(|(elem, reason)| ClosedCondOrder::new(elem, *reason, now))
+            .collect::<Vec<_>>();
+
+        if !ids.is_empty() {
+            if let Err(err) = self.store.retire(&ids, &records).await {
+                // Still persisted, they would run again after a restart:
+                // keep them queued and retire them on a later pass instead.
+                tracing::error!("{:?}", err);
+                let elems = evaluation
+                    .fired
+                    .into_iter()
+                    .chain(evaluation.closed.into_iter().map(|(elem, _)| elem))
+                    .collect();
+                self.reinstate(elems).await;
+                return Vec::new();
+            }
+        }
+
+        for elem in evaluation
+            .fired
+            .iter()
+            .chain(evaluation.closed.iter().map(|(elem, _)| elem))
+        {
+            elem.unsubscribe().await;
+        }
+
+        evaluation.fired
+    }
+
+    /// Put back entries an evaluation took out, with the subscriptions they
+    /// still hold. Entries with a time boundary are due again after
+    /// [`RETIRE_RETRY_DELAY`]; the others on their next market update.
+    async fn reinstate(&self, elems: Vec<QueueElem>) {
+        let mut prepared = Vec::with_capacity(elems.len());
+        for elem in elems {
+            let receivers = receivers(&elem.condition).await;
+            prepared.push((elem, receivers));
+        }
+
+        let retry_at = self.clock.now() + RETIRE_RETRY_DELAY;
+        let mut state = self.state.write().await;
+        for (elem, receivers) in prepared {
+            state.index(&elem, receivers, &self.wakeups, retry_at);
+            state.elems.insert(elem.id, elem);
+        }
+        drop(state);
+
+        self.wakeups.reschedule();
+    }
+
+    /// Save entry `id` without holding the lock across the Redis write. The
+    /// copy is taken under a read lock; once written, the entry is looked at
+    /// again: a revision made while the write was in flight is written over
+    /// it, and the copy is deleted if a cancel or a firing removed the entry
+    /// meanwhile, since that removal may have reached Redis first.
+    async fn persist(&self, id: Uuid) {
+        loop {
+            let (revision, sealed) = {
+                let state = self.state.read().await;
+                let (Some(revision), Some(elem)) = (state.revision(id), state.get(id)) else {
+                    return;
+                };
+                (revision, self.store.seal(elem))
+            };
+
+            let written = match sealed {
+                Ok(sealed) => self.store.put(id, &sealed).await,
+                Err(err) => Err(err),
+            };
+            if let Err(err) = written {
+                tracing::error!("{:?}", err);
+                return;
+            }
+
+            let current = self.state.read().await.revision(id);
+            match current {
+                Some(current) if current == revision => return,
+                Some(_) => continue,
+                None => {
+                    if let Err(err) = self.store.retire(&[id], &[]).await {
+                        tracing::error!("{:?}", err);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Load the candle history indicator conditions start from.
+    async fn seed(&self, condition: &mut Condition) {
+        let info: Info = Hyperliquid::new(self.chain);
+        condition.seed(&info, self.clock.now()).await;
+    }
+}
+
+/// Receivers of the streams `condition` reads, fetched from `CONNECTIONS`
+/// before the queue lock is taken. Topics without a live stream are left out.
+async fn receivers(condition: &Condition) -> Feeds {
+    let mut receivers = Feeds::new();
+
+    for topic in condition.topics() {
+        if let Some(receiver) = Feed::receiver(&topic).await {
+            receivers.insert(topic, receiver);
+        }
+    }
+
+    receivers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::hyperliquid::{
+            ActiveAssetCtx, AssetCtx, AssetCtxMetric, AssetMetric, At, L2Book, Level, PositionSide,
+            Trail, TrailingStop, Trigger, UpdateLeverage, WSResponse,
+        },
+        service::clock::ManualClock,
+    };
+    use ethers::{core::rand::thread_rng, signers::LocalWallet};
+
+    fn elem(condition: Condition, expires_at: Option<u64>) -> QueueElem {
+        let mut elem = QueueElem::new(
+            Address::zero(),
+            Default::default(),
+            Arc::new(LocalWallet::new(&mut thread_rng())),
+            CondAction::UpdateLeverage(UpdateLeverage {
+                asset: 0,
+                is_cross: true,
+                leverage: 1,
+            }),
+            condition,
+            None,
+        );
+        elem.expires_at = expires_at;
+        elem
+    }
+
+    fn insert(state: &mut QueueState, elem: QueueElem, clock: &ManualClock) -> Uuid {
+        let id = elem.id;
+        state.index(&elem, Feeds::new(), &Arc::default(), clock.now());
+        state.elems.insert(id, elem);
+        id
+    }
+
+    #[test]
+    fn deadlines_pop_in_time_order() {
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut deadlines = Deadlines::default();
+
+        deadlines.schedule(a, Some(300));
+        deadlines.schedule(b, Some(100));
+        deadlines.schedule(c, Some(200));
+        deadlines.schedule(b, Some(400));
+        deadlines.schedule(c, None);
+
+        assert_eq!(deadlines.next(), Some(300));
+        assert_eq!(deadlines.pop_due(350), vec![a]);
+        assert_eq!(deadlines.next(), Some(400));
+        assert!(deadlines.pop_due(399).is_empty());
+        assert_eq!(deadlines.pop_due(400), vec![b]);
+        assert_eq!(deadlines.next(), None);
+    }
+
+    #[tokio::test]
+    async fn time_order_due_while_down_fires_on_restore() {
+        let clock = ManualClock::at(10_000);
+        let mut state = QueueState::default();
+        let id = insert(
+            &mut state,
+            elem(Condition::At(At { time: 5_000 }), None),
+            &clock,
+        );
+
+        assert_eq!(state.deadlines.next(), Some(10_000));
+
+        let evaluation = state.evaluate(&HashSet::new(), &clock).await;
+        assert_eq!(evaluation.fired.len(), 1);
+        assert_eq!(evaluation.fired[0].id, id);
+        assert!(state.is_empty());
+        assert_eq!(state.deadlines.next(), None);
+    }
+
+    #[tokio::test]
+    async fn expiry_passed_while_down_drops_the_order() {
+        let clock = ManualClock::at(10_000);
+        let mut state = QueueState::default();
+        insert(
+            &mut state,
+            elem(Condition::At(At { time: 50_000 }), Some(8_000)),
+            &clock,
+        );
+
+        let evaluation = state.evaluate(&HashSet::new(), &clock).await;
+        assert!(evaluation.fired.is_empty());
+        assert!(matches!(
+            evaluation.closed.as_slice(),
+            [(_, CloseReason::Expired)]
+        ));
+        assert!(state.is_empty());
+    }
+
+    #[tokio::test]
+    async fn timed_order_sleeps_until_its_next_boundary() {
+        let clock = ManualClock::at(1_000);
+        let mut state = QueueState::default();
+        insert(
+            &mut state,
+            elem(Condition::At(At { time: 4_000 }), Some(9_000)),
+            &clock,
+        );
+
+        assert!(state
+            .evaluate(&HashSet::new(), &clock)
+            .await
+            .fired
+            .is_empty());
+        assert_eq!(state.deadlines.next(), Some(4_000));
+
+        clock.set(3_999);
+        assert!(state
+            .evaluate(&HashSet::new(), &clock)
+            .await
+            .fired
+            .is_empty());
+        assert_eq!(state.deadlines.next(), Some(4_000));
+
+        clock.set(4_000);
+        assert_eq!(state.evaluate(&HashSet::new(), &clock).await.fired.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn pinned_baseline_is_persisted_once() {
+        let clock = ManualClock::at(0);
+        let mut state = QueueState::default();
+
+        let metric = AssetCtxMetric {
+            symbol: "ETH".into(),
+            metric: AssetMetric::OpenInterest,
+            trigger: Trigger::Below,
+            value: -10.0,
+            relative: true,
+            baseline: None,
+            last_value: None,
+        };
+        let topic = metric.topic();
+        let (_sender, receiver) =
+            watch::channel(Some(WSResponse::ActiveAssetCtx(ActiveAssetCtx {
+                coin: "ETH".into(),
+                ctx: AssetCtx {
+                    funding: 0.0,
+                    open_interest: 1_000.0,
+                    oracle_px: 1.0,
+                    mark_px: 1.0,
+                    premium: None,
+                },
+            })));
+
+        let elem = elem(Condition::AssetCtx(metric), None);
+        let id = elem.id;
+        state.index(
+            &elem,
+            Feeds::from([(topic.clone(), receiver)]),
+            &Arc::default(),
+            clock.now(),
+        );
+        state.elems.insert(id, elem);
+
+        let topics = HashSet::from([topic]);
+        assert_eq!(state.evaluate(&topics, &clock).await.changed, vec![id]);
+        assert!(state.evaluate(&topics, &clock).await.changed.is_empty());
+    }
+
+    #[tokio::test]
+    async fn firing_member_cancels_its_group_in_the_same_pass() {
+        let clock = ManualClock::at(1_000);
+        let mut state = QueueState::default();
+
+        for _ in 0..2 {
+            let mut elem = elem(Condition::At(At { time: 500 }), None);
+            elem.group = Some("exit".into());
+            insert(&mut state, elem, &clock);
+        }
+
+        let evaluation = state.evaluate(&HashSet::new(), &clock).await;
+        assert_eq!(evaluation.fired.len(), 1);
+        assert!(matches!(
+            evaluation.closed.as_slice(),
+            [(_, CloseReason::GroupCancelled)]
+        ));
+        assert!(state.is_empty());
+    }
+
+    #[tokio::test]
+    async fn trailing_moves_are_saved_once_per_interval() {
+        let clock = ManualClock::at(0);
+        let mut state = QueueState::default();
+
+        let book = |bid: f64| {
+            let level = |px: f64| Level {
+                px: px.to_string(),
+                sz: "1".into(),
+                n: 1,
+            };
+            Some(WSResponse::L2Book(L2Book {
+                coin: "ETH".into(),
+                levels: vec![vec![level(bid)], vec![level(bid + 1.0)]],
+                time: 0,
+            }))
+        };
+        let topic = Subscribe::L2Book { coin: "ETH".into() };
+        let (sender, receiver) = watch::channel(book(100.0));
+
+        let elem = elem(
+            Condition::TrailingStop(TrailingStop {
+                symbol: "ETH".into(),
+                side: PositionSide::Long,
+                trail: Trail::Absolute(10.0),
+                extreme: None,
+            }),
+            None,
+        );
+        let id = elem.id;
+        state.index(
+            &elem,
+            Feeds::from([(topic.clone(), receiver)]),
+            &Arc::default(),
+            clock.now(),
+        );
+        state.elems.insert(id, elem);
+
+        let topics = HashSet::from([topic]);
+        assert!(state.evaluate(&topics, &clock).await.changed.is_empty());
+
+        clock.set(1_000);
+        sender.send_replace(book(105.0));
+        assert!(state.evaluate(&topics, &clock).await.changed.is_empty());
+        assert_eq!(state.unsaved.next(), Some(TRAILING_SAVE_INTERVAL));
+
+        clock.set(TRAILING_SAVE_INTERVAL);
+        let evaluation = state.evaluate(&HashSet::new(), &clock).await;
+        assert_eq!(evaluation.changed, vec![id]);
+        assert_eq!(state.unsaved.next(), None);
+    }
+}
@@ -0,0 +1,104 @@
+//! In-memory registries of the orders worked by the order services.
+//!
+//! Every service keeps the orders it follows in a [`Registry`] keyed by id.
+//! Services whose orders stay inspectable once finished drop them
+//! [`FINISHED_TTL`] later with [`Registry::prune`].
+
+use std::{collections::HashMap, sync::Arc};
+
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// How long finished orders stay inspectable, in milliseconds.
+pub const FINISHED_TTL: u64 = 24 * 60 * 60 * 1000;
+
+/// Registry entry of an order that finishes at some point.
+pub trait Finished {
+    /// When the order finished, in milliseconds since the Unix epoch.
+    fn finished_at(&self) -> Option<u64>;
+
+    /// Whether the order finished longer than [`FINISHED_TTL`] before `now`.
+    fn expired(&self, now: u64) -> bool {
+        self.finished_at()
+            .is_some_and(|finished_at| finished_at + FINISHED_TTL <= now)
+    }
+}
+
+/// Entries of one order service, keyed by order id.
+pub struct Registry<T> {
+    entries: Mutex<HashMap<Uuid, Arc<T>>>,
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T> Registry<T> {
+    /// Insert or replace the entry `id`.
+    pub async fn insert(&self, id: Uuid, entry: Arc<T>) {
+        self.entries.lock().await.insert(id, entry);
+    }
+
+    /// Entry `id`, if registered.
+    pub async fn get(&self, id: Uuid) -> Option<Arc<T>> {
+        self.entries.lock().await.get(&id).cloned()
+    }
+
+    /// Take the entry `id` out of the registry.
+    pub async fn remove(&self, id: Uuid) -> Option<Arc<T>> {
+        self.entries.lock().await.remove(&id)
+    }
+
+    /// Every entry, to be inspected without holding the registry lock.
+    pub async fn entries(&self) -> Vec<Arc<T>> {
+        self.entries.lock().await.values().cloned().collect()
+    }
+}
+
+impl<T: Finished> Registry<T> {
+    /// Drop the entries that expired at `now`, returning their ids.
+    pub async fn prune(&self, now: u64) -> Vec<Uuid> {
+        let mut entries = self.entries.lock().await;
+
+        let expired: Vec<_> = entries
+            .iter()
+            .filter(|(_, entry)| entry.expired(now))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            entries.remove(id);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Order(Option<u64>);
+
+    impl Finished for Order {
+        fn finished_at(&self) -> Option<u64> {
+            self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn prune_drops_orders_finished_before_the_ttl() {
+        let registry = Registry::default();
+        let (running, recent, expired) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        registry.insert(running, Arc::new(Order(None))).await;
+        registry.insert(recent, Arc::new(Order(Some(2_000)))).await;
+        registry.insert(expired, Arc::new(Order(Some(1_000)))).await;
+
+        assert_eq!(registry.prune(1_000 + FINISHED_TTL).await, vec![expired]);
+        assert!(registry.get(running).await.is_some());
+        assert!(registry.get(recent).await.is_some());
+        assert!(registry.get(expired).await.is_none());
+    }
+}
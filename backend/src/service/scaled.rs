@@ -0,0 +1,201 @@
+//! Scaled (ladder) orders: a batch of limit orders spread evenly over a price
+//! range, with the total size split by a [`Distribution`].
+//!
+//! The ladder is built here from the asset's [`Market`] so that every price
+//! sits on the tick grid and every size on `sz_decimals`; the API submits the
+//! whole batch in a single `place_order` call.
+
+use hyperliquid::{
+    types::exchange::request::{Limit, OrderRequest, OrderType, Tif},
+    utils::{parse_price, parse_size},
+};
+
+use crate::{
+    model::hyperliquid::{Distribution, LadderEnd, ScaledOrder},
+    service::market::{Market, MIN_NOTIONAL},
+};
+
+/// Relative size of each order of a ladder of `count`, from the start price
+/// to the end price.
+pub fn weights(distribution: &Distribution, count: u32) -> Vec<f64> {
+    let mut weights: Vec<f64> = (0..count)
+        .map(|i| match *distribution {
+            Distribution::Flat => 1.0,
+            Distribution::Linear { .. } => (i + 1) as f64,
+            Distribution::Exponential { ratio, .. } => ratio.powi(i as i32),
+        })
+        .collect();
+
+    if let Distribution::Linear {
+        toward: LadderEnd::Start,
+    }
+    | Distribution::Exponential {
+        toward: LadderEnd::Start,
+        ..
+    } = distribution
+    {
+        weights.reverse();
+    }
+    weights
+}
+
+/// The orders of `order` on `market`, from the start price to the end price.
+///
+/// Prices are rounded to the tick grid away from the touch (buys down, sells
+/// up) and sizes down to `sz_decimals`; the lots rounded away go to the
+/// largest order. Fails when two orders would share a price or an order is
+/// worth less than [`MIN_NOTIONAL`].
+pub fn ladder(order: &ScaledOrder, market: &Market) -> Result<Vec<OrderRequest>, String> {
+    let count = order.count.max(2);
+    let weights = weights(&order.distribution, count);
+    let total: f64 = weights.iter().sum();
+
+    let step = (order.end_px - order.start_px) / (count - 1) as f64;
+    let prices: Vec<f64> = (0..count)
+        .map(|i| market.round_px(order.start_px + step * i as f64, !order.is_buy))
+        .collect();
+    if prices.windows(2).any(|pair| pair[0] == pair[1]) {
+        return Err(format!(
+            "Price range is too narrow for {} orders at the tick size",
+            count
+        ));
+    }
+
+    let mut sizes: Vec<f64> = weights
+        .iter()
+        .map(|weight| market.round_down(order.sz * weight / total))
+        .collect();
+    let largest = weights
+        .iter()
+        .enumerate()
+        .max_by(|(_, w1), (_, w2)| w1.total_cmp(w2))
+        .map(|(i, _)| i)
+        .unwrap_or_default();
+    let rounded: f64 = sizes.iter().sum();
+    sizes[largest] = market.round_down(sizes[largest] + order.sz - rounded);
+
+    prices
+        .iter()
+        .zip(&sizes)
+        .enumerate()
+        .map(|(i, (&px, &sz))| {
+            if px * sz < MIN_NOTIONAL {
+                return Err(format!(
+                    "Order {} ({} at {}) is below the ${} minimum order value",
+                    i + 1,
+                    sz,
+                    px,
+                    MIN_NOTIONAL
+                ));
+            }
+
+            Ok(OrderRequest {
+                asset: order.asset,
+                is_buy: order.is_buy,
+                limit_px: parse_price(px),
+                sz: parse_size(sz, market.sz_decimals),
+                reduce_only: order.reduce_only,
+                order_type: OrderType::Limit(Limit {
+                    tif: if order.post_only { Tif::Alo } else { Tif::Gtc },
+                }),
+                cloid: None,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::market::fixtures::{assert_close, market};
+
+    fn order(is_buy: bool, sz: f64, distribution: Distribution) -> ScaledOrder {
+        ScaledOrder {
+            asset: 1,
+            is_buy,
+            start_px: 2000.05,
+            end_px: 1990.05,
+            sz,
+            count: 3,
+            distribution,
+            reduce_only: false,
+            post_only: false,
+        }
+    }
+
+    fn parse(values: impl Iterator<Item = String>) -> Vec<f64> {
+        values.map(|value| value.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn weights_follow_the_distribution() {
+        let linear = |toward| Distribution::Linear { toward };
+        let exponential = |toward| Distribution::Exponential { toward, ratio: 2.0 };
+
+        assert_eq!(weights(&Distribution::Flat, 3), vec![1.0, 1.0, 1.0]);
+        assert_eq!(weights(&linear(LadderEnd::End), 3), vec![1.0, 2.0, 3.0]);
+        assert_eq!(weights(&linear(LadderEnd::Start), 3), vec![3.0, 2.0, 1.0]);
+        assert_eq!(
+            weights(&exponential(LadderEnd::End), 3),
+            vec![1.0, 2.0, 4.0]
+        );
+        assert_eq!(
+            weights(&exponential(LadderEnd::Start), 3),
+            vec![4.0, 2.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn ladder_prices_round_away_from_the_touch() {
+        let buys = ladder(&order(true, 1.5, Distribution::Flat), &market(2000.0, 2)).unwrap();
+        let sells = ladder(&order(false, 1.5, Distribution::Flat), &market(2000.0, 2)).unwrap();
+
+        let buy_prices = parse(buys.into_iter().map(|order| order.limit_px));
+        let sell_prices = parse(sells.into_iter().map(|order| order.limit_px));
+        assert_close(&buy_prices, &[2000.0, 1995.0, 1990.0]);
+        assert_close(&sell_prices, &[2000.1, 1995.1, 1990.1]);
+    }
+
+    #[test]
+    fn ladder_gives_the_rounded_away_lots_to_the_largest_order() {
+        let flat = ladder(&order(true, 1.0, Distribution::Flat), &market(2000.0, 2)).unwrap();
+        let linear = Distribution::Linear {
+            toward: LadderEnd::Start,
+        };
+        let linear = ladder(&order(true, 1.0, linear), &market(2000.0, 2)).unwrap();
+
+        assert_close(
+            &parse(flat.into_iter().map(|order| order.sz)),
+            &[0.33, 0.33, 0.34],
+        );
+        // 1/2, 1/3 and 1/6 of the size, rounded down to 0.5, 0.33 and 0.16.
+        assert_close(
+            &parse(linear.into_iter().map(|order| order.sz)),
+            &[0.51, 0.33, 0.16],
+        );
+    }
+
+    #[test]
+    fn ladder_rejects_prices_sharing_a_tick() {
+        let narrow = ScaledOrder {
+            start_px: 100.0,
+            end_px: 100.01,
+            ..order(true, 1.0, Distribution::Flat)
+        };
+
+        assert!(ladder(&narrow, &market(2000.0, 2)).is_err());
+    }
+
+    #[test]
+    fn ladder_rejects_orders_below_the_minimum_value() {
+        let cheap = |sz| ScaledOrder {
+            start_px: 10.0,
+            end_px: 9.0,
+            ..order(true, sz, Distribution::Flat)
+        };
+
+        // 1.33 at 9 is worth $11.97, 0.66 only $5.94.
+        assert!(ladder(&cheap(4.0), &market(2000.0, 2)).is_ok());
+        assert!(ladder(&cheap(2.0), &market(2000.0, 2)).is_err());
+    }
+}
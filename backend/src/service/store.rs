@@ -0,0 +1,115 @@
+//! Sealed Redis hashes for the order services that keep following orders
+//! after a restart.
+//!
+//! Every record is keyed by its id, serialized as JSON and sealed with the
+//! [`RecordCipher`], since each of them carries the agent key signing its
+//! orders.
+
+use std::collections::HashMap;
+
+use anyhow::Context;
+use redis::{aio::ConnectionManager, AsyncCommands};
+use serde::{de::DeserializeOwned, Serialize};
+use uuid::Uuid;
+
+use crate::service::{
+    bracket::BracketStore, cipher::RecordCipher, grid::GridStore, twap::TwapStore,
+};
+
+/// Stores of the order services, shared with the request handlers.
+#[derive(Clone)]
+pub struct Stores {
+    /// Bracket orders still being tracked.
+    pub brackets: BracketStore,
+    /// Grid bots, running and stopped.
+    pub grids: GridStore,
+    /// TWAP jobs, including recently finished ones.
+    pub twaps: TwapStore,
+}
+
+/// Open a connection to Redis using the same URL as the session middleware.
+pub async fn connect(redis_url: &str) -> anyhow::Result<ConnectionManager> {
+    let client = redis::Client::open(redis_url).context("Failed to create redis client")?;
+
+    ConnectionManager::new(client)
+        .await
+        .context("Failed to connect to redis")
+}
+
+/// Handle to one Redis hash of sealed records.
+#[derive(Clone)]
+pub struct RecordStore {
+    connection: ConnectionManager,
+    cipher: RecordCipher,
+    key: &'static str,
+}
+
+impl RecordStore {
+    /// Store records in the hash `key`.
+    pub fn new(connection: ConnectionManager, cipher: RecordCipher, key: &'static str) -> Self {
+        Self {
+            connection,
+            cipher,
+            key,
+        }
+    }
+
+    /// Insert or overwrite the record `id`.
+    pub async fn save<T: Serialize>(&self, id: Uuid, record: &T) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(record).context("Failed to serialize record")?;
+
+        self.put(&id.to_string(), &payload).await
+    }
+
+    /// Delete the record `id`.
+    pub async fn remove(&self, id: Uuid) -> anyhow::Result<()> {
+        self.connection
+            .clone()
+            .hdel::<_, _, ()>(self.key, id.to_string())
+            .await
+            .with_context(|| format!("Failed to delete {} record", self.key))?;
+
+        Ok(())
+    }
+
+    /// Load every record. Records that can no longer be decoded are logged
+    /// and skipped so a single bad record cannot block the boot.
+    pub async fn load<T: DeserializeOwned>(&self) -> anyhow::Result<Vec<T>> {
+        let entries: HashMap<String, String> = self
+            .connection
+            .clone()
+            .hgetall(self.key)
+            .await
+            .with_context(|| format!("Failed to load {} records", self.key))?;
+
+        let mut records = Vec::with_capacity(entries.len());
+        for (id, payload) in entries {
+            let decoded = self
+                .cipher
+                .open(&payload)
+                .and_then(|json| serde_json::from_str(&json).context("Invalid record"));
+
+            match decoded {
+                Ok(record) => records.push(record),
+                Err(err) => {
+                    tracing::error!("Failed to decode {} record {}: {:?}", self.key, id, err)
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Seal the JSON `payload` and store it as the record `id`.
+    async fn put(&self, id: &str, payload: &str) -> anyhow::Result<()> {
+        let payload = self.cipher.seal(payload)?;
+
+        self.connection
+            .clone()
+            .hset::<_, _, _, ()>(self.key, id, payload)
+            .await
+            .with_context(|| format!("Failed to persist {} record", self.key))?;
+
+        Ok(())
+    }
+}
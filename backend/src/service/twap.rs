@@ -0,0 +1,1761 @@
+//! Registry and executor of TWAP orders.
+//!
+//! Every TWAP submitted through the API or a conditional order is registered
+//! here with an id and owner before it reaches the background worker, which
+//! runs it slice by slice through [`Twaps::run`]. The registry keeps each
+//! job's progress (slices sent, filled size, average price, errors) and a
+//! control channel the owner uses to pause, resume or cancel it between
+//! slices.
+//!
+//! VWAP orders run here as well, with slices sized by the volume profile of
+//! [`crate::service::vwap`] instead of evenly, and so do POV orders, whose
+//! slices follow the volume on the asset's trades stream instead of a
+//! schedule.
+//!
+//! Slices are priced off the asset's live L2 book (see [`quote`]) and held
+//! back while the book is outside the request's slippage cap.
+//!
+//! The [`TwapScheduler`] runs every job as its own task, limited by
+//! [`TwapLimits`]; jobs above the limits stay queued until a slot frees up.
+//! A job paused before it started does not take a slot, while a running job
+//! keeps its slot when paused and is cancelled once paused for [`MAX_PAUSE`].
+//!
+//! Jobs are stored, sealed with their agent key, in a Redis hash on every
+//! change. A schedule cannot be picked up halfway, so on boot jobs that were
+//! already sending slices are marked failed, while jobs that never started
+//! are queued again.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use ethers::{
+    core::rand::{self, rngs::StdRng, Rng, SeedableRng},
+    signers::LocalWallet,
+    types::Address,
+};
+use hyperliquid::{
+    types::{
+        exchange::{
+            request::{CancelRequest, Limit, OrderRequest, OrderType, Tif},
+            response::Response as ExchangeResponse,
+        },
+        Chain,
+    },
+    utils::{parse_price, parse_size},
+    Exchange, Hyperliquid, Info,
+};
+use lazy_static::lazy_static;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{mpsc, watch, Notify},
+    time::Instant,
+};
+use uuid::Uuid;
+
+use crate::{
+    model::hyperliquid::{
+        deserialize_wallet, serialize_wallet, Algorithm, OrderStatus, Subscribe, TwapOrderRequest,
+        TwapPricing, WSResponse,
+    },
+    service::{
+        cipher::RecordCipher,
+        clock::{Clock, SystemClock},
+        hyperliquid::{
+            exchange::{place_statuses, PlaceStatus},
+            info,
+        },
+        market::{market, Market},
+        registry::{Finished, Registry},
+        store::RecordStore,
+        vwap::VolumeProfile,
+    },
+    ws::hyperliquid::book_price::{BookPrice, Feed},
+};
+
+/// Redis hash holding the TWAP jobs, including recently finished ones.
+const TWAP_KEY: &str = "twap_orders";
+
+/// How long a slice waits for the first snapshot of the book stream.
+const BOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of most recent errors kept on a job.
+const MAX_ERRORS: usize = 20;
+
+/// A job fails after this many slices in a row could not be placed.
+const MAX_CONSECUTIVE_ERRORS: usize = 3;
+
+/// Wait between the attempts to settle the last slice of a job.
+const SETTLE_RETRY: Duration = Duration::from_secs(2);
+
+/// How long a running job may stay paused, holding its slot, before it is
+/// cancelled.
+pub const MAX_PAUSE: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum relative jitter of the intervals and sizes of randomized TWAPs,
+/// before renormalisation (see [`schedule`]).
+const JITTER: f64 = 0.25;
+
+lazy_static! {
+    /// TWAP jobs keyed by id, including recently finished ones.
+    static ref TWAPS: Registry<Entry> = Registry::default();
+
+    /// Signalled when a job waiting for a slot is resumed.
+    static ref RESUMED: Notify = Notify::new();
+}
+
+/// Lifecycle state of a TWAP job.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TwapStatus {
+    /// Waiting for the worker to pick it up.
+    Queued,
+    /// Sending slices.
+    Running,
+    /// Held by the owner; no slices are sent until resumed.
+    Paused,
+    /// Every slice was sent and the requested size filled.
+    Completed,
+    /// Every slice was sent, but less than the requested size filled.
+    PartiallyFilled,
+    /// Stopped by the owner.
+    Cancelled,
+    /// Stopped after repeated errors.
+    Failed,
+}
+
+impl TwapStatus {
+    fn is_finished(self) -> bool {
+        matches!(
+            self,
+            TwapStatus::Completed
+                | TwapStatus::PartiallyFilled
+                | TwapStatus::Cancelled
+                | TwapStatus::Failed
+        )
+    }
+}
+
+/// Progress of a TWAP job.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TwapJob {
+    /// Id handed back to the user and used as the persistence key.
+    pub id: Uuid,
+    /// User that submitted the TWAP.
+    pub owner: Address,
+    /// Vault executing the slices, if set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vault_address: Option<Address>,
+    /// TWAP parameters as submitted.
+    pub request: TwapOrderRequest,
+    /// How the slices are sized.
+    pub algorithm: Algorithm,
+    /// Current lifecycle state.
+    pub status: TwapStatus,
+    /// Number of slices the TWAP is split into, final once it started.
+    pub slices: u64,
+    /// Seed of the slice schedule, which is jittered when the request sets
+    /// `randomize`.
+    pub seed: u64,
+    /// Number of slices sent so far.
+    pub slices_sent: u64,
+    /// Total size filled so far.
+    pub filled_sz: f64,
+    /// Average fill price, once anything filled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avg_px: Option<f64>,
+    /// Client order id of the latest slice while it rests on the book or its
+    /// outcome is not confirmed yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_slice: Option<Uuid>,
+    /// Most recent errors, oldest first.
+    pub errors: Vec<String>,
+    /// Why the most recent deferred slices were not sent, oldest first.
+    pub deferrals: Vec<String>,
+    /// When the job was submitted, in milliseconds since the Unix epoch.
+    pub created_at: u64,
+    /// When the first slice was sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u64>,
+    /// When the job finished.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<u64>,
+    /// Filled notional backing `avg_px`.
+    #[serde(skip)]
+    notional: f64,
+}
+
+impl TwapJob {
+    fn record_error(&mut self, error: impl ToString) {
+        if self.errors.len() == MAX_ERRORS {
+            self.errors.remove(0);
+        }
+        self.errors.push(error.to_string());
+    }
+
+    fn record_deferral(&mut self, reason: String) {
+        if self.deferrals.len() == MAX_ERRORS {
+            self.deferrals.remove(0);
+        }
+        self.deferrals.push(reason);
+    }
+
+    fn record_fill(&mut self, fill: &SliceFill) {
+        self.filled_sz += fill.sz;
+        self.notional += fill.notional;
+        if self.filled_sz > 0.0 {
+            self.avg_px = Some(self.notional / self.filled_sz);
+        }
+    }
+
+    fn finish(&mut self, status: TwapStatus) {
+        self.status = status;
+        self.finished_at = Some(SystemClock.now());
+    }
+}
+
+impl Finished for TwapJob {
+    fn finished_at(&self) -> Option<u64> {
+        self.finished_at
+    }
+}
+
+/// Stored copy of a job, with the agent key needed to run it after a
+/// restart.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    #[serde(
+        serialize_with = "serialize_wallet",
+        deserialize_with = "deserialize_wallet"
+    )]
+    agent: Arc<LocalWallet>,
+    notional: f64,
+    #[serde(flatten)]
+    job: TwapJob,
+}
+
+/// Handle to the Redis hash backing the TWAP jobs.
+#[derive(Clone)]
+pub struct TwapStore {
+    records: RecordStore,
+}
+
+impl TwapStore {
+    /// Store the jobs over `connection`, sealed with `cipher`.
+    pub fn new(connection: ConnectionManager, cipher: RecordCipher) -> Self {
+        Self {
+            records: RecordStore::new(connection, cipher, TWAP_KEY),
+        }
+    }
+
+    /// Insert or overwrite the stored copy of `job`.
+    async fn save(&self, job: &TwapJob, agent: &Arc<LocalWallet>) -> anyhow::Result<()> {
+        let record = Record {
+            agent: agent.clone(),
+            notional: job.notional,
+            job: job.clone(),
+        };
+
+        self.records.save(job.id, &record).await
+    }
+
+    /// Delete the stored copy of job `id`.
+    async fn remove(&self, id: Uuid) -> anyhow::Result<()> {
+        self.records.remove(id).await
+    }
+
+    /// Load every stored job.
+    async fn load(&self) -> anyhow::Result<Vec<Record>> {
+        self.records.load().await
+    }
+}
+
+/// What the owner last asked the executor to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    Run,
+    Pause,
+    Cancel,
+}
+
+/// Registry entry of one job.
+struct Entry {
+    job: Mutex<TwapJob>,
+    agent: Arc<LocalWallet>,
+    control: watch::Sender<Control>,
+    store: TwapStore,
+    /// Held while a copy of the job is written, so that copies land in the
+    /// order they were taken.
+    saving: tokio::sync::Mutex<()>,
+}
+
+impl Finished for Entry {
+    fn finished_at(&self) -> Option<u64> {
+        self.update(|job| job.finished_at)
+    }
+}
+
+impl Entry {
+    fn new(job: TwapJob, agent: Arc<LocalWallet>, store: TwapStore) -> Self {
+        Self {
+            job: Mutex::new(job),
+            agent,
+            control: watch::Sender::new(Control::Run),
+            store,
+            saving: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn job(&self) -> TwapJob {
+        self.job.lock().unwrap().clone()
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut TwapJob) -> T) -> T {
+        f(&mut self.job.lock().unwrap())
+    }
+
+    /// Store the current state of the job. Failures are only logged: the
+    /// job keeps running and is stored again on its next change.
+    async fn save(&self) {
+        let _saving = self.saving.lock().await;
+        let job = self.job();
+
+        if let Err(err) = self.store.save(&job, &self.agent).await {
+            tracing::error!("TWAP {}: {:?}", job.id, err);
+        }
+    }
+
+    /// Mark the job as failed with `error`, unless it already finished.
+    async fn fail(&self, error: impl ToString) {
+        self.update(|job| {
+            if !job.status.is_finished() {
+                job.record_error(error);
+                job.finish(TwapStatus::Failed);
+            }
+        });
+        self.save().await;
+    }
+}
+
+/// Registry of the TWAP jobs.
+pub struct Twaps;
+
+impl Twaps {
+    /// Register a TWAP of `owner` as queued and store it. The worker starts
+    /// it once it receives the returned id. Nothing is registered if the job
+    /// could not be stored, since it would not survive a restart.
+    pub async fn submit(
+        store: &TwapStore,
+        owner: Address,
+        agent: Arc<LocalWallet>,
+        request: TwapOrderRequest,
+        algorithm: Algorithm,
+        vault_address: Option<Address>,
+    ) -> anyhow::Result<Uuid> {
+        let now = SystemClock.now();
+
+        let slices = nominal_slices(&request);
+
+        let job = TwapJob {
+            id: Uuid::new_v4(),
+            owner,
+            vault_address,
+            request,
+            algorithm,
+            status: TwapStatus::Queued,
+            slices,
+            seed: rand::random(),
+            slices_sent: 0,
+            filled_sz: 0.0,
+            avg_px: None,
+            open_slice: None,
+            errors: Vec::new(),
+            deferrals: Vec::new(),
+            created_at: now,
+            started_at: None,
+            finished_at: None,
+            notional: 0.0,
+        };
+        let id = job.id;
+
+        for id in TWAPS.prune(now).await {
+            if let Err(err) = store.remove(id).await {
+                tracing::error!("TWAP {}: {:?}", id, err);
+            }
+        }
+        store.save(&job, &agent).await?;
+        TWAPS
+            .insert(id, Arc::new(Entry::new(job, agent, store.clone())))
+            .await;
+
+        Ok(id)
+    }
+
+    /// Drop a job the worker never received.
+    pub async fn discard(id: Uuid) {
+        if let Some(entry) = TWAPS.remove(id).await {
+            if let Err(err) = entry.store.remove(id).await {
+                tracing::error!("TWAP {}: {:?}", id, err);
+            }
+        }
+    }
+
+    /// Reload the jobs stored before the last shutdown. Jobs that were
+    /// already sending slices are marked failed, since their schedule cannot
+    /// be picked up halfway, and the slice they left open is cancelled; the
+    /// ids of the ones that never started are returned to be queued again.
+    pub async fn restore(store: &TwapStore, chain: Chain) -> anyhow::Result<Vec<Uuid>> {
+        let now = SystemClock.now();
+        let info: Info = Hyperliquid::new(chain);
+        let exchange: Exchange = Hyperliquid::new(chain);
+
+        let mut queued = Vec::new();
+        for Record {
+            agent,
+            notional,
+            job,
+        } in store.load().await?
+        {
+            let id = job.id;
+            if job.expired(now) {
+                if let Err(err) = store.remove(id).await {
+                    tracing::error!("TWAP {}: {:?}", id, err);
+                }
+                continue;
+            }
+
+            let entry = Arc::new(Entry::new(
+                TwapJob { notional, ..job },
+                agent,
+                store.clone(),
+            ));
+            let interrupted = entry.update(|job| {
+                if job.status.is_finished() {
+                    return None;
+                }
+                if job.started_at.is_none() {
+                    // Never sent a slice, so it can start over; a paused one
+                    // waits for its owner like before.
+                    queued.push(job.id);
+                    return None;
+                }
+                Some(job.open_slice)
+            });
+
+            if let Some(open_slice) = interrupted {
+                tracing::warn!("TWAP {} was interrupted by a restart", id);
+
+                let cancelled = match open_slice {
+                    Some(cloid) => {
+                        cancel_slice(&info, &exchange, &entry, cloid)
+                            .await
+                            .map_err(|err| {
+                                format!("Slice {} may still rest on the book: {}", cloid, err)
+                            })
+                    }
+                    None => Ok(()),
+                };
+                entry.update(|job| {
+                    job.record_error("Interrupted by a server restart");
+                    match cancelled {
+                        Ok(()) => job.open_slice = None,
+                        Err(err) => job.record_error(err),
+                    }
+                    job.finish(TwapStatus::Failed);
+                });
+                entry.save().await;
+            }
+
+            TWAPS.insert(id, entry).await;
+        }
+
+        Ok(queued)
+    }
+
+    /// Jobs of `owner`, most recent first.
+    pub async fn list(owner: &Address) -> Vec<TwapJob> {
+        let mut jobs: Vec<_> = TWAPS
+            .entries()
+            .await
+            .iter()
+            .map(|entry| entry.job())
+            .filter(|job| job.owner == *owner)
+            .collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+        jobs
+    }
+
+    /// Job `id` of `owner`.
+    pub async fn get(owner: &Address, id: Uuid) -> Option<TwapJob> {
+        Self::entry(owner, id).await.map(|entry| entry.job())
+    }
+
+    /// Stop sending slices until the job is resumed.
+    pub async fn pause(owner: &Address, id: Uuid) -> anyhow::Result<TwapJob> {
+        Self::control(owner, id, Control::Pause, |job| match job.status {
+            TwapStatus::Queued | TwapStatus::Running => Ok(TwapStatus::Paused),
+            _ => Err(anyhow!("Only queued or running TWAPs can be paused")),
+        })
+        .await
+    }
+
+    /// Continue a paused job from the next slice.
+    pub async fn resume(owner: &Address, id: Uuid) -> anyhow::Result<TwapJob> {
+        Self::control(owner, id, Control::Run, |job| match job.status {
+            TwapStatus::Paused if job.started_at.is_some() => Ok(TwapStatus::Running),
+            TwapStatus::Paused => Ok(TwapStatus::Queued),
+            _ => Err(anyhow!("Only paused TWAPs can be resumed")),
+        })
+        .await
+    }
+
+    /// Stop the job for good. Slices already sent are not undone.
+    pub async fn cancel(owner: &Address, id: Uuid) -> anyhow::Result<TwapJob> {
+        Self::control(owner, id, Control::Cancel, |job| {
+            if job.status.is_finished() {
+                Err(anyhow!("TWAP already finished"))
+            } else {
+                Ok(TwapStatus::Cancelled)
+            }
+        })
+        .await
+    }
+
+    async fn entry(owner: &Address, id: Uuid) -> Option<Arc<Entry>> {
+        TWAPS
+            .get(id)
+            .await
+            .filter(|entry| entry.job().owner == *owner)
+    }
+
+    /// Move the job to the status returned by `transition` and signal the
+    /// executor.
+    async fn control(
+        owner: &Address,
+        id: Uuid,
+        control: Control,
+        transition: impl FnOnce(&TwapJob) -> anyhow::Result<TwapStatus>,
+    ) -> anyhow::Result<TwapJob> {
+        let entry = Self::entry(owner, id)
+            .await
+            .ok_or_else(|| anyhow!("TWAP not found"))?;
+
+        let job = entry.update(|job| {
+            let status = transition(job)?;
+            if status.is_finished() {
+                job.finish(status);
+            } else {
+                job.status = status;
+            }
+            anyhow::Ok(job.clone())
+        })?;
+        entry.control.send_replace(control);
+        if job.status == TwapStatus::Queued {
+            RESUMED.notify_one();
+        }
+        entry.save().await;
+
+        Ok(job)
+    }
+
+    /// Mark job `id` as failed with `error`, unless it already finished.
+    async fn fail(id: Uuid, error: impl ToString) {
+        if let Some(entry) = TWAPS.get(id).await {
+            entry.fail(error).await;
+        }
+    }
+
+    /// Execute job `id` until every slice was sent or it is cancelled.
+    pub async fn run(chain: Chain, id: Uuid) {
+        let Some(entry) = TWAPS.get(id).await else {
+            tracing::warn!("TWAP {} is not registered", id);
+            return;
+        };
+
+        let info: Info = Hyperliquid::new(chain);
+        let mut control = entry.control.subscribe();
+
+        let TwapJob {
+            request,
+            algorithm,
+            vault_address,
+            seed,
+            owner,
+            ..
+        } = entry.job();
+        let account = Account {
+            agent: entry.agent.clone(),
+            user: vault_address.unwrap_or(owner),
+            vault_address,
+        };
+
+        if !proceed(&entry, &mut control).await {
+            return;
+        }
+
+        // The market at the start names the asset's streams and sizes the
+        // plan, so every slice meets the minimum order size.
+        let market = match market(&info, request.asset).await {
+            Ok(market) => market,
+            Err(err) => {
+                tracing::error!("TWAP {} could not be planned: {:?}", id, err);
+                entry.fail(err).await;
+                return;
+            }
+        };
+
+        // Slices are priced off the live book, shared with every other
+        // consumer of the asset's stream.
+        let book = BookPrice::topic(&market.coin);
+        if let Err(err) = Feed::subscribe(&book).await {
+            tracing::error!("TWAP {} could not stream the book: {:?}", id, err);
+            entry.fail(err).await;
+            return;
+        }
+
+        let mut executor = Executor {
+            id,
+            entry,
+            info,
+            exchange: Hyperliquid::new(chain),
+            account,
+            request,
+            market: market.clone(),
+            book: book.clone(),
+            control,
+            open: None,
+            settle_errors: 0,
+        };
+        match algorithm {
+            Algorithm::Pov {
+                participation,
+                min_slice_sz,
+                max_slice_sz,
+            } => {
+                let trades = Subscribe::Trades {
+                    coin: market.coin.clone(),
+                };
+                match Feed::subscribe(&trades).await {
+                    Ok(()) => {
+                        executor
+                            .participate(&trades, participation, min_slice_sz, max_slice_sz)
+                            .await;
+                        Feed::unsubscribe(&trades).await;
+                    }
+                    Err(err) => {
+                        tracing::error!("POV {} could not stream trades: {:?}", id, err);
+                        executor.entry.fail(err).await;
+                    }
+                }
+            }
+            algorithm => match executor.plan(algorithm, &market, seed).await {
+                Ok(schedule) => executor.execute(schedule).await,
+                Err(err) => {
+                    tracing::error!("TWAP {} could not be planned: {:?}", id, err);
+                    executor.entry.fail(err).await;
+                }
+            },
+        }
+
+        Feed::unsubscribe(&book).await;
+    }
+}
+
+/// Sends the slices of one running job.
+struct Executor {
+    id: Uuid,
+    entry: Arc<Entry>,
+    info: Info,
+    exchange: Exchange,
+    account: Account,
+    request: TwapOrderRequest,
+    /// Market when the job started, which sized its plan.
+    market: Market,
+    /// L2 book stream the slices are priced from.
+    book: Subscribe,
+    control: watch::Receiver<Control>,
+    /// Latest slice, until it is settled.
+    open: Option<OpenSlice>,
+    /// Failed attempts to settle `open` in a row.
+    settle_errors: usize,
+}
+
+impl Executor {
+    /// Slice schedule of a TWAP or VWAP job on `market`.
+    async fn plan(
+        &self,
+        algorithm: Algorithm,
+        market: &Market,
+        seed: u64,
+    ) -> anyhow::Result<Vec<TwapSlice>> {
+        let slices = slice_count(&self.request, market);
+        self.entry.update(|job| job.slices = slices);
+
+        let mut schedule = schedule(&self.request, slices, &mut StdRng::seed_from_u64(seed));
+        if let Algorithm::Vwap { lookback_days } = algorithm {
+            let now = SystemClock.now();
+            VolumeProfile::fetch(&self.info, &market.coin, lookback_days, now)
+                .await?
+                .weight(&mut schedule, now, self.request.frequency);
+        }
+
+        Ok(schedule)
+    }
+
+    /// Send `schedule` slice by slice.
+    async fn execute(&mut self, schedule: Vec<TwapSlice>) {
+        let slices = schedule.len();
+        let mut planned = 0.0;
+        let mut consecutive_errors = 0;
+
+        for (i, slice) in schedule.into_iter().enumerate() {
+            let cancelled = self.sleep(slice.delay).await;
+
+            // Quantity earlier slices left unfilled, rounded away, deferred
+            // or held back rolls into this one; the last slice takes whatever
+            // is left.
+            planned += slice.sz;
+
+            // The previous slice is settled first, so the target counts all
+            // it filled; while its outcome is unknown no more size is sent.
+            let settled = match self.reconcile().await {
+                Ok(settled) => settled,
+                Err(err) => {
+                    self.entry.fail(err).await;
+                    return;
+                }
+            };
+            if cancelled || !proceed(&self.entry, &mut self.control).await {
+                self.wind_down().await;
+                return;
+            }
+            if !settled {
+                self.entry.update(|job| {
+                    job.record_deferral(format!("Slice {}: previous slice is not settled", i + 1))
+                });
+                self.entry.save().await;
+                continue;
+            }
+            let filled = self.mark_running();
+
+            let last = i + 1 == slices;
+            let target = if last { self.request.sz } else { planned } - filled;
+
+            match self.send(target, last).await {
+                Ok(SliceResult::Filled(fill)) => {
+                    consecutive_errors = 0;
+                    self.entry.update(|job| {
+                        job.slices_sent += 1;
+                        job.record_fill(&fill);
+                    });
+                }
+                Ok(SliceResult::Open) => {
+                    consecutive_errors = 0;
+                    self.entry.update(|job| job.slices_sent += 1);
+                }
+                Ok(SliceResult::Skipped) => {
+                    tracing::debug!("TWAP {} slice {} carried over", self.id, i + 1);
+                }
+                Ok(SliceResult::Deferred(reason)) => {
+                    tracing::debug!("TWAP {} slice {} deferred: {}", self.id, i + 1, reason);
+                    self.entry
+                        .update(|job| job.record_deferral(format!("Slice {}: {}", i + 1, reason)));
+                }
+                Err(err) => {
+                    consecutive_errors += 1;
+                    if self.record_failure(i + 1, err, consecutive_errors) {
+                        self.wind_down().await;
+                        return;
+                    }
+                }
+            }
+            self.entry.save().await;
+        }
+
+        if self.open.is_some() {
+            // The last open slice gets one more interval to fill.
+            self.sleep(Duration::from_secs(self.request.frequency))
+                .await;
+        }
+        if let Err(err) = self.close().await {
+            self.entry.fail(err).await;
+            return;
+        }
+
+        self.complete().await;
+    }
+
+    /// Send slices sized to `participation` of the volume streamed by
+    /// `trades` since the previous slice, one every `frequency` seconds at
+    /// most, until `sz` is filled or `runtime` elapsed.
+    ///
+    /// Slices below `min_slice_sz` wait for more volume; slices above
+    /// `max_slice_sz` are capped and the volume they leave out is caught up
+    /// on by the next ones.
+    async fn participate(
+        &mut self,
+        trades: &Subscribe,
+        participation: f64,
+        min_slice_sz: f64,
+        max_slice_sz: Option<f64>,
+    ) {
+        let Some(mut tally) = Feed::volume(trades).await else {
+            self.entry.fail("Trades stream is closed").await;
+            return;
+        };
+
+        let frequency = Duration::from_secs(self.request.frequency);
+        let deadline = Instant::now() + Duration::from_secs(self.request.runtime);
+        let mut next = Instant::now() + frequency;
+
+        // Our slices trade too: sizing them to this ratio of everyone
+        // else's volume keeps them at `participation` of the total.
+        let ratio = participation / (1.0 - participation);
+
+        // Tally already added to `volume`, so trades from before the job
+        // are left out.
+        let mut seen = tally.borrow_and_update().total;
+        // Volume of the other traders not yet covered by a slice.
+        let mut volume = 0.0;
+        // Filled size already taken out of `volume`.
+        let mut counted = 0.0;
+        let mut slice = 0;
+        let mut consecutive_errors = 0;
+
+        loop {
+            let event = tokio::select! {
+                changed = tally.changed() => match changed {
+                    Ok(()) => Event::Trades,
+                    Err(_) => Event::Closed,
+                },
+                _ = tokio::time::sleep_until(next) => Event::Slice,
+                _ = tokio::time::sleep_until(deadline) => Event::Deadline,
+                _ = self.control.wait_for(|control| *control == Control::Cancel) => {
+                    Event::Cancelled
+                }
+            };
+
+            match event {
+                Event::Trades => {
+                    let total = tally.borrow_and_update().total;
+                    volume += total - seen;
+                    seen = total;
+                    continue;
+                }
+                Event::Slice => {}
+                Event::Deadline | Event::Cancelled => break,
+                Event::Closed => {
+                    self.wind_down().await;
+                    self.entry.fail("Trades stream is closed").await;
+                    return;
+                }
+            }
+            next = Instant::now() + frequency;
+
+            // As in `execute`, no more size is sent while the previous
+            // slice's outcome is unknown.
+            let settled = match self.reconcile().await {
+                Ok(settled) => settled,
+                Err(err) => {
+                    self.entry.fail(err).await;
+                    return;
+                }
+            };
+            if *self.control.borrow() == Control::Pause {
+                if !proceed(&self.entry, &mut self.control).await {
+                    self.wind_down().await;
+                    return;
+                }
+                // Volume traded while paused is not caught up on.
+                volume = 0.0;
+                next = Instant::now() + frequency;
+                continue;
+            }
+            if !settled {
+                self.entry.save().await;
+                continue;
+            }
+            let filled = self.mark_running();
+
+            // Our own fills are part of the streamed volume.
+            volume = (volume - (filled - counted)).max(0.0);
+            counted = filled;
+
+            let remaining = self.request.sz - filled;
+            if remaining <= 0.0 {
+                break;
+            }
+            let mut sz = volume * ratio;
+            if sz < min_slice_sz {
+                continue;
+            }
+            if let Some(max_slice_sz) = max_slice_sz {
+                sz = sz.min(max_slice_sz);
+            }
+            let sz = sz.min(remaining);
+
+            slice += 1;
+            match self.send(sz, false).await {
+                Ok(SliceResult::Filled(fill)) => {
+                    consecutive_errors = 0;
+                    volume = (volume - sz / ratio).max(0.0);
+                    self.entry.update(|job| {
+                        job.slices_sent += 1;
+                        job.record_fill(&fill);
+                    });
+                }
+                Ok(SliceResult::Open) => {
+                    consecutive_errors = 0;
+                    volume = (volume - sz / ratio).max(0.0);
+                    self.entry.update(|job| job.slices_sent += 1);
+                }
+                Ok(SliceResult::Skipped) => {
+                    tracing::debug!("POV {} slice {} waits for more volume", self.id, slice);
+                }
+                Ok(SliceResult::Deferred(reason)) => {
+                    tracing::debug!("POV {} slice {} deferred: {}", self.id, slice, reason);
+                    self.entry
+                        .update(|job| job.record_deferral(format!("Slice {}: {}", slice, reason)));
+                }
+                Err(err) => {
+                    consecutive_errors += 1;
+                    if self.record_failure(slice, err, consecutive_errors) {
+                        self.wind_down().await;
+                        return;
+                    }
+                }
+            }
+            self.entry.save().await;
+        }
+
+        if let Err(err) = self.close().await {
+            self.entry.fail(err).await;
+            return;
+        }
+
+        self.complete().await;
+    }
+
+    /// Mark the job as running. Returns the size filled so far.
+    fn mark_running(&self) -> f64 {
+        self.entry.update(|job| {
+            if job.status == TwapStatus::Queued {
+                job.status = TwapStatus::Running;
+            }
+            job.started_at.get_or_insert_with(|| SystemClock.now());
+            job.filled_sz
+        })
+    }
+
+    /// Record that `slice` failed with `err`, the `consecutive_errors`th
+    /// failure in a row. Returns whether the job failed for good.
+    fn record_failure(&self, slice: usize, err: anyhow::Error, consecutive_errors: usize) -> bool {
+        tracing::error!("TWAP {} slice {} failed: {:?}", self.id, slice, err);
+
+        self.entry.update(|job| {
+            job.record_error(&err);
+            if consecutive_errors == MAX_CONSECUTIVE_ERRORS {
+                job.finish(TwapStatus::Failed);
+            }
+            job.status == TwapStatus::Failed
+        })
+    }
+
+    /// Mark the job as completed, or as partially filled when more than
+    /// rounding is left of its size, unless it was cancelled or failed.
+    async fn complete(&self) {
+        let status = self.entry.update(|job| {
+            if !job.status.is_finished() {
+                let left = job.request.sz - job.filled_sz;
+                if left >= self.market.lot() / 2.0 {
+                    job.record_error(format!(
+                        "{:.*} of {} left unfilled",
+                        self.market.sz_decimals as usize, left, job.request.sz
+                    ));
+                    job.finish(TwapStatus::PartiallyFilled);
+                } else {
+                    job.finish(TwapStatus::Completed);
+                }
+            }
+            job.status
+        });
+        self.entry.save().await;
+        tracing::info!("TWAP {} finished as {:?}", self.id, status);
+    }
+
+    /// Sleep for `delay`. Returns `true` if the job was cancelled meanwhile.
+    async fn sleep(&mut self, delay: Duration) -> bool {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => false,
+            _ = self.control.wait_for(|control| *control == Control::Cancel) => true,
+        }
+    }
+
+    /// Price and send a slice covering `target`.
+    async fn send(&mut self, target: f64, last: bool) -> anyhow::Result<SliceResult> {
+        let market = market(&self.info, self.request.asset).await?;
+
+        let sz = market.round_down(target);
+        if sz <= 0.0 {
+            return Ok(SliceResult::Skipped);
+        }
+        if sz < market.min_sz() {
+            if last {
+                return Err(anyhow!("Remaining {} is below the minimum order size", sz));
+            }
+            return Ok(SliceResult::Skipped);
+        }
+
+        let book = self.best_prices().await?;
+        let (limit_px, tif) = match quote(&self.request, &market, &book) {
+            Ok(quote) => quote,
+            Err(reason) => return Ok(SliceResult::Deferred(reason)),
+        };
+
+        let mut slice = OpenSlice {
+            cloid: Uuid::new_v4(),
+            oid: None,
+            placed_at: SystemClock.now(),
+            limit_px,
+        };
+        let order = OrderRequest {
+            asset: self.request.asset,
+            is_buy: self.request.is_buy,
+            limit_px: parse_price(limit_px),
+            sz: parse_size(sz, market.sz_decimals),
+            reduce_only: self.request.reduce_only,
+            order_type: OrderType::Limit(Limit { tif }),
+            cloid: Some(slice.cloid),
+        };
+
+        // Once the request went out the slice may be on the book, so unless
+        // it was rejected it is kept open until it is settled.
+        let response = match self
+            .exchange
+            .place_order(
+                self.account.agent.clone(),
+                vec![order],
+                self.account.vault_address,
+            )
+            .await
+        {
+            Ok(response) => response,
+            Err(err) => {
+                self.hold(slice);
+                return Err(anyhow!("Failed to place slice: {}", err));
+            }
+        };
+        if let ExchangeResponse::Err(err) = response {
+            return Err(anyhow!("Slice rejected: {}", err));
+        }
+
+        let status = place_statuses(response).and_then(|statuses| {
+            statuses
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("Response has no slice status"))
+        });
+        match status {
+            Ok(PlaceStatus::Filled {
+                total_sz, avg_px, ..
+            }) => Ok(SliceResult::Filled(SliceFill {
+                sz: total_sz,
+                notional: total_sz * avg_px,
+            })),
+            Ok(PlaceStatus::Resting { oid }) => {
+                slice.oid = Some(oid);
+                self.hold(slice);
+                Ok(SliceResult::Open)
+            }
+            Ok(PlaceStatus::Rejected(err)) => Err(anyhow!("Slice rejected: {}", err)),
+            Err(err) => {
+                self.hold(slice);
+                Err(err.context("Slice outcome is unknown"))
+            }
+        }
+    }
+
+    /// Keep `slice` open until it is settled.
+    fn hold(&mut self, slice: OpenSlice) {
+        self.entry.update(|job| job.open_slice = Some(slice.cloid));
+        self.open = Some(slice);
+    }
+
+    /// Settle the open slice, if any, and record what it filled. Returns
+    /// `false` while its outcome is unknown or its cancel failed; it then
+    /// stays open and is settled again before the next slice. Fails once
+    /// settling failed [`MAX_CONSECUTIVE_ERRORS`] times in a row, since the
+    /// slice may still be working on the book.
+    async fn reconcile(&mut self) -> anyhow::Result<bool> {
+        let Some(slice) = self.open.clone() else {
+            return Ok(true);
+        };
+
+        match self.settle(&slice).await {
+            Ok(Some(fill)) => {
+                self.open = None;
+                self.settle_errors = 0;
+                self.entry.update(|job| {
+                    job.record_fill(&fill);
+                    job.open_slice = None;
+                });
+                Ok(true)
+            }
+            Ok(None) => {
+                tracing::debug!("TWAP {} slice {} is not known yet", self.id, slice.cloid);
+                Ok(false)
+            }
+            Err(err) => {
+                tracing::error!("TWAP {} slice could not be settled: {:?}", self.id, err);
+                self.entry.update(|job| job.record_error(&err));
+                self.settle_errors += 1;
+                if self.settle_errors == MAX_CONSECUTIVE_ERRORS {
+                    return Err(anyhow!(
+                        "Slice {} could not be settled and may still rest on the book",
+                        slice.cloid
+                    ));
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    /// Settle the open slice before the job ends, retrying every
+    /// [`SETTLE_RETRY`].
+    async fn close(&mut self) -> anyhow::Result<()> {
+        let Some(cloid) = self.open.as_ref().map(|slice| slice.cloid) else {
+            return Ok(());
+        };
+
+        for _ in 0..MAX_CONSECUTIVE_ERRORS {
+            if self.reconcile().await? {
+                return Ok(());
+            }
+            tokio::time::sleep(SETTLE_RETRY).await;
+        }
+
+        Err(anyhow!("Last slice {} could not be settled", cloid))
+    }
+
+    /// [`Executor::close`] for a job that was stopped, recording why the open
+    /// slice could not be settled.
+    async fn wind_down(&mut self) {
+        if let Err(err) = self.close().await {
+            tracing::error!("TWAP {}: {:?}", self.id, err);
+            self.entry.update(|job| job.record_error(&err));
+        }
+        self.entry.save().await;
+    }
+
+    /// Cancel what is left of the open `slice` and read what it filled;
+    /// `None` while Hyperliquid does not report the slice yet.
+    async fn settle(&self, slice: &OpenSlice) -> anyhow::Result<Option<SliceFill>> {
+        let status = info::order_status(&self.info, self.account.user, slice.cloid).await?;
+        let Some(status) = status else {
+            // A confirmed slice is only missing from a lagging status, while
+            // one whose placement failed most likely never reached the book.
+            return Ok(slice.oid.is_none().then(SliceFill::default));
+        };
+
+        if status.status == "open" {
+            let cancel = CancelRequest {
+                asset: self.request.asset,
+                oid: status.order.oid,
+            };
+            let response = self
+                .exchange
+                .cancel_order(
+                    self.account.agent.clone(),
+                    vec![cancel],
+                    self.account.vault_address,
+                )
+                .await
+                .map_err(|err| anyhow!("Failed to cancel slice: {}", err))?;
+            if let ExchangeResponse::Err(err) = response {
+                return Err(anyhow!("Slice cancel rejected: {}", err));
+            }
+        }
+
+        // Read the status again, the order may have filled until the cancel
+        // went through.
+        let status = info::order_status(&self.info, self.account.user, slice.cloid)
+            .await?
+            .ok_or_else(|| anyhow!("Slice is unknown"))?;
+        if status.status == "open" {
+            return Err(anyhow!("Slice is still open after its cancel"));
+        }
+        self.read_fill(slice, &status).await.map(Some)
+    }
+
+    /// Size and notional `order` filled, priced from its fills.
+    async fn read_fill(
+        &self,
+        order: &OpenSlice,
+        status: &OrderStatus,
+    ) -> anyhow::Result<SliceFill> {
+        let filled = status.filled();
+        if filled <= 0.0 {
+            return Ok(SliceFill {
+                sz: 0.0,
+                notional: 0.0,
+            });
+        }
+
+        let fills = info::user_fills(&self.info, self.account.user, order.placed_at)
+            .await
+            .map_err(|err| anyhow!("Failed to fetch slice fills: {}", err))?;
+        let (sz, notional) = fills
+            .iter()
+            .filter(|fill| fill.oid == status.order.oid)
+            .fold((0.0, 0.0), |(sz, notional), fill| {
+                (sz + fill.sz, notional + fill.sz * fill.px)
+            });
+
+        // Fills can lag behind the order status; price the slice at its limit
+        // until they show up.
+        Ok(if sz > 0.0 {
+            SliceFill { sz, notional }
+        } else {
+            SliceFill {
+                sz: filled,
+                notional: filled * order.limit_px,
+            }
+        })
+    }
+
+    /// Best bid and ask of the live book.
+    async fn best_prices(&self) -> anyhow::Result<BestPrices> {
+        let mut receiver = Feed::receiver(&self.book)
+            .await
+            .ok_or_else(|| anyhow!("Order book stream is closed"))?;
+
+        // The stream may not have delivered its first snapshot yet.
+        let latest = tokio::time::timeout(BOOK_TIMEOUT, receiver.wait_for(Option::is_some))
+            .await
+            .map_err(|_| anyhow!("No order book received"))?
+            .map_err(|_| anyhow!("Order book stream is closed"))?;
+        let Some(WSResponse::L2Book(book)) = latest.as_ref() else {
+            return Err(anyhow!("Unexpected order book message"));
+        };
+
+        Ok(BestPrices {
+            bid: book
+                .best_bid()
+                .ok_or_else(|| anyhow!("Order book has no bids"))?,
+            ask: book
+                .best_ask()
+                .ok_or_else(|| anyhow!("Order book has no asks"))?,
+        })
+    }
+}
+
+/// Limits on the TWAPs executing at the same time.
+#[derive(Debug, Clone, Copy)]
+pub struct TwapLimits {
+    /// Running TWAPs per owner.
+    pub per_user: usize,
+    /// Running TWAPs across all owners.
+    pub total: usize,
+}
+
+/// Starts the submitted TWAPs as their own tasks, in submission order and
+/// within [`TwapLimits`].
+pub struct TwapScheduler {
+    chain: Chain,
+    limits: TwapLimits,
+    /// Submitted jobs waiting for a slot.
+    waiting: VecDeque<Uuid>,
+    /// Owners of the running jobs.
+    running: HashMap<Uuid, Address>,
+    finished_tx: mpsc::UnboundedSender<Uuid>,
+    finished_rx: mpsc::UnboundedReceiver<Uuid>,
+}
+
+impl TwapScheduler {
+    /// Scheduler without any jobs.
+    pub fn new(chain: Chain, limits: TwapLimits) -> Self {
+        let (finished_tx, finished_rx) = mpsc::unbounded_channel();
+
+        Self {
+            chain,
+            limits,
+            waiting: VecDeque::new(),
+            running: HashMap::new(),
+            finished_tx,
+            finished_rx,
+        }
+    }
+
+    /// Queue the registered job `id`; it starts on the next
+    /// [`TwapScheduler::admit`] if a slot is free.
+    pub fn submit(&mut self, id: Uuid) {
+        self.waiting.push_back(id);
+    }
+
+    /// Wait until a running job ended, releasing its slot, or a waiting job
+    /// was resumed. The slot is released as soon as the end is received, so
+    /// this can race other futures in a `select!`; call
+    /// [`TwapScheduler::admit`] afterwards to hand out the free slots.
+    pub async fn reap(&mut self) {
+        tokio::select! {
+            Some(id) = self.finished_rx.recv() => {
+                self.running.remove(&id);
+            }
+            _ = RESUMED.notified() => {}
+        }
+    }
+
+    /// Start waiting jobs, oldest first, while their owner and the server
+    /// are below the limits. Jobs cancelled while waiting are dropped, and
+    /// paused ones keep waiting without taking a slot.
+    pub async fn admit(&mut self) {
+        let mut i = 0;
+        while i < self.waiting.len() && self.running.len() < self.limits.total {
+            let id = self.waiting[i];
+            let Some(job) = TWAPS.get(id).await.map(|entry| entry.job()) else {
+                self.waiting.remove(i);
+                continue;
+            };
+            if job.status.is_finished() {
+                self.waiting.remove(i);
+                continue;
+            }
+            if job.status == TwapStatus::Paused {
+                i += 1;
+                continue;
+            }
+
+            let owned = self
+                .running
+                .values()
+                .filter(|owner| **owner == job.owner)
+                .count();
+            if owned < self.limits.per_user {
+                self.waiting.remove(i);
+                self.start(id, job.owner);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn start(&mut self, id: Uuid, owner: Address) {
+        self.running.insert(id, owner);
+
+        let chain = self.chain;
+        let finished = self.finished_tx.clone();
+        tokio::spawn(async move {
+            // The job runs in a task of its own so that a panic only takes
+            // down this job.
+            if let Err(err) = tokio::spawn(Twaps::run(chain, id)).await {
+                tracing::error!("TWAP {} crashed: {}", id, err);
+                Twaps::fail(id, "TWAP crashed").await;
+            }
+            let _ = finished.send(id);
+        });
+    }
+}
+
+/// Best prices of an order book.
+#[derive(Debug, Clone, Copy)]
+pub struct BestPrices {
+    pub bid: f64,
+    pub ask: f64,
+}
+
+/// Limit price and time in force of a slice of `request` priced off `book`,
+/// or why the slice has to wait: its price would be further than the
+/// slippage cap from the mid price, or nothing is offered within the cap.
+pub fn quote(
+    request: &TwapOrderRequest,
+    market: &Market,
+    book: &BestPrices,
+) -> Result<(f64, Tif), String> {
+    let mid = (book.bid + book.ask) / 2.0;
+    let cap = mid * request.slippage_cap;
+    let side = if request.is_buy { 1.0 } else { -1.0 };
+    // Best price on the other side of the book, which the slice takes.
+    let (touch, opposite) = if request.is_buy {
+        (book.bid, book.ask)
+    } else {
+        (book.ask, book.bid)
+    };
+
+    let (px, tif) = match request.pricing {
+        TwapPricing::Passive => (touch, Tif::Alo),
+        TwapPricing::Marketable { ticks } => (
+            opposite + side * ticks as f64 * market.tick(opposite),
+            Tif::Ioc,
+        ),
+        TwapPricing::Ioc => (mid + side * cap, Tif::Ioc),
+    };
+    // Round towards the mid so rounding never adds slippage.
+    let px = market.round_px(px, !request.is_buy);
+
+    if side * (px - mid) > cap {
+        return Err(format!(
+            "Limit price {} is more than {}% away from mid {}",
+            px,
+            request.slippage_cap * 100.0,
+            mid
+        ));
+    }
+    if matches!(tif, Tif::Ioc) && side * (opposite - px) > 0.0 {
+        return Err(format!(
+            "Best {} {} is beyond the slippage cap",
+            if request.is_buy { "ask" } else { "bid" },
+            opposite
+        ));
+    }
+
+    Ok((px, tif))
+}
+
+/// Slice count without size constraints: one slice every `frequency`
+/// seconds over `runtime`, the first one right away.
+pub fn nominal_slices(request: &TwapOrderRequest) -> u64 {
+    request.runtime / request.frequency + 1
+}
+
+/// Number of slices `request` is split into on `market`: the
+/// [`nominal_slices`], reduced so that an even slice still meets the minimum
+/// order size.
+pub fn slice_count(request: &TwapOrderRequest, market: &Market) -> u64 {
+    let fits = (request.sz / market.min_sz() + 1e-9).floor() as u64;
+
+    nominal_slices(request).min(fits).max(1)
+}
+
+/// One slice of a TWAP schedule.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TwapSlice {
+    /// Wait after the previous slice, zero for the first one.
+    pub delay: Duration,
+    /// Planned size of the slice, before rounding and carry-over.
+    pub sz: f64,
+}
+
+/// Split `request` into `slices` slices spread evenly over the span of its
+/// [`nominal_slices`], i.e. `frequency` seconds apart unless the slice count
+/// was reduced.
+///
+/// With `randomize`, every interval and size is scaled by a random factor
+/// within ±[`JITTER`], then renormalised so the sizes still add up to `sz`
+/// and the last slice goes out exactly when it would without jitter.
+/// Renormalising divides by the mean factor, itself within ±`JITTER`, so
+/// every interval and size ends up between `(1 - JITTER) / (1 + JITTER)` and
+/// `(1 + JITTER) / (1 - JITTER)` times its even value. The schedule only
+/// depends on `rng`, so a seeded generator reproduces it.
+pub fn schedule(request: &TwapOrderRequest, slices: u64, rng: &mut impl Rng) -> Vec<TwapSlice> {
+    let slices = slices.max(1) as usize;
+    let mut factors = |n: usize| -> Vec<f64> {
+        (0..n)
+            .map(|_| {
+                if request.randomize {
+                    rng.gen_range(1.0 - JITTER..=1.0 + JITTER)
+                } else {
+                    1.0
+                }
+            })
+            .collect()
+    };
+
+    let sizes = factors(slices);
+    let gaps = factors(slices - 1);
+
+    let total_size: f64 = sizes.iter().sum();
+    let total_gap: f64 = gaps.iter().sum();
+    let span = ((nominal_slices(request) - 1) * request.frequency) as f64;
+
+    sizes
+        .iter()
+        .enumerate()
+        .map(|(i, size)| TwapSlice {
+            delay: match i {
+                0 => Duration::ZERO,
+                _ => Duration::from_secs_f64(span * gaps[i - 1] / total_gap),
+            },
+            sz: request.sz * size / total_size,
+        })
+        .collect()
+}
+
+/// Wait while the job is paused. Returns `false` once it is cancelled, which
+/// a pause longer than [`MAX_PAUSE`] does as well.
+async fn proceed(entry: &Entry, control: &mut watch::Receiver<Control>) -> bool {
+    let deadline = Instant::now() + MAX_PAUSE;
+
+    loop {
+        match *control.borrow_and_update() {
+            Control::Run => return true,
+            Control::Cancel => return false,
+            Control::Pause => {}
+        }
+        match tokio::time::timeout_at(deadline, control.changed()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(_)) => return false,
+            Err(_) => {
+                entry.update(|job| {
+                    if !job.status.is_finished() {
+                        job.record_error(format!(
+                            "Paused for longer than {} minutes",
+                            MAX_PAUSE.as_secs() / 60
+                        ));
+                        job.finish(TwapStatus::Cancelled);
+                    }
+                });
+                entry.save().await;
+                return false;
+            }
+        }
+    }
+}
+
+/// Cancel slice `cloid` an interrupted job left, if it still rests, with the
+/// job's agent.
+async fn cancel_slice(
+    info: &Info,
+    exchange: &Exchange,
+    entry: &Entry,
+    cloid: Uuid,
+) -> anyhow::Result<()> {
+    let job = entry.job();
+    let user = job.vault_address.unwrap_or(job.owner);
+
+    let Some(status) = info::order_status(info, user, cloid).await? else {
+        return Ok(());
+    };
+    if status.status != "open" {
+        return Ok(());
+    }
+
+    let cancel = CancelRequest {
+        asset: job.request.asset,
+        oid: status.order.oid,
+    };
+    let response = exchange
+        .cancel_order(entry.agent.clone(), vec![cancel], job.vault_address)
+        .await
+        .map_err(|err| anyhow!("Failed to cancel slice: {}", err))?;
+    if let ExchangeResponse::Err(err) = response {
+        return Err(anyhow!("Slice cancel rejected: {}", err));
+    }
+
+    Ok(())
+}
+
+/// Wallet and account the slices are placed for.
+struct Account {
+    agent: Arc<LocalWallet>,
+    /// Account holding the position, the vault if set.
+    user: Address,
+    vault_address: Option<Address>,
+}
+
+/// Size and notional filled by one slice.
+#[derive(Default)]
+struct SliceFill {
+    sz: f64,
+    notional: f64,
+}
+
+/// Slice resting on the book, or placed without a confirmation.
+#[derive(Debug, Clone)]
+struct OpenSlice {
+    cloid: Uuid,
+    /// Order id confirmed by the place response.
+    oid: Option<u64>,
+    placed_at: u64,
+    limit_px: f64,
+}
+
+/// What a POV job woke up for.
+enum Event {
+    /// More trades were counted.
+    Trades,
+    /// The next slice is due.
+    Slice,
+    /// The runtime elapsed.
+    Deadline,
+    /// The owner cancelled the job.
+    Cancelled,
+    /// The trades stream went away.
+    Closed,
+}
+
+/// Outcome of sending one slice.
+enum SliceResult {
+    /// The slice is done and filled this much.
+    Filled(SliceFill),
+    /// The slice rests on the book; it is settled before the next one.
+    Open,
+    /// Nothing to send, or too little to meet the minimum order size.
+    Skipped,
+    /// The book is outside the slippage cap.
+    Deferred(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::market::fixtures::{assert_close, market};
+
+    fn request(randomize: bool) -> TwapOrderRequest {
+        TwapOrderRequest {
+            asset: 0,
+            is_buy: true,
+            runtime: 600,
+            reduce_only: false,
+            sz: 10.0,
+            randomize,
+            frequency: 60,
+            pricing: TwapPricing::Ioc,
+            slippage_cap: 0.03,
+        }
+    }
+
+    fn randomized(seed: u64, slices: u64) -> Vec<TwapSlice> {
+        schedule(&request(true), slices, &mut StdRng::seed_from_u64(seed))
+    }
+
+    #[test]
+    fn randomized_sizes_add_up_to_the_request() {
+        for seed in 0..20 {
+            let total: f64 = randomized(seed, 11).iter().map(|slice| slice.sz).sum();
+
+            assert!((total - 10.0).abs() < 1e-9, "seed {}: {}", seed, total);
+        }
+    }
+
+    #[test]
+    fn randomized_slices_stay_within_the_jitter_bound() {
+        let (low, high) = (
+            (1.0 - JITTER) / (1.0 + JITTER),
+            (1.0 + JITTER) / (1.0 - JITTER),
+        );
+
+        for seed in 0..20 {
+            let schedule = randomized(seed, 11);
+
+            for slice in &schedule {
+                let size = slice.sz / (10.0 / 11.0);
+                assert!(size >= low && size <= high, "seed {}: size {}", seed, size);
+            }
+            for slice in &schedule[1..] {
+                let gap = slice.delay.as_secs_f64() / 60.0;
+                assert!(gap >= low && gap <= high, "seed {}: gap {}", seed, gap);
+            }
+        }
+    }
+
+    #[test]
+    fn last_slice_goes_out_at_the_end_of_the_span() {
+        // Fewer slices than nominal are spread over the same span.
+        for (randomize, slices) in [(false, 11), (true, 11), (true, 4)] {
+            let schedule = schedule(&request(randomize), slices, &mut StdRng::seed_from_u64(3));
+            let span: Duration = schedule.iter().map(|slice| slice.delay).sum();
+
+            assert_eq!(schedule.len(), slices as usize);
+            assert_eq!(schedule[0].delay, Duration::ZERO);
+            assert!((span.as_secs_f64() - 600.0).abs() < 1e-6, "{:?}", span);
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_schedule() {
+        assert_eq!(randomized(42, 11), randomized(42, 11));
+        assert_ne!(randomized(42, 11), randomized(43, 11));
+    }
+
+    #[test]
+    fn slice_count_keeps_even_slices_above_the_minimum() {
+        // $10 at 100 is 0.1, so 1.0 fits ten slices of the nominal eleven.
+        let market = market(100.0, 2);
+        let count = |sz| {
+            slice_count(
+                &TwapOrderRequest {
+                    sz,
+                    ..request(false)
+                },
+                &market,
+            )
+        };
+
+        assert_eq!(count(100.0), 11);
+        assert_eq!(count(1.0), 10);
+        assert_eq!(count(0.05), 1);
+    }
+
+    #[test]
+    fn quotes_follow_the_pricing_mode() {
+        let market = market(100.0, 2);
+        let book = BestPrices {
+            bid: 99.9,
+            ask: 100.1,
+        };
+        let quote_with = |is_buy, pricing| {
+            let request = TwapOrderRequest {
+                is_buy,
+                pricing,
+                ..request(false)
+            };
+            quote(&request, &market, &book).unwrap()
+        };
+
+        let (px, tif) = quote_with(true, TwapPricing::Passive);
+        assert_close(&[px], &[99.9]);
+        assert!(matches!(tif, Tif::Alo));
+
+        let (px, tif) = quote_with(false, TwapPricing::Passive);
+        assert_close(&[px], &[100.1]);
+        assert!(matches!(tif, Tif::Alo));
+
+        let (px, tif) = quote_with(true, TwapPricing::Marketable { ticks: 2 });
+        assert_close(&[px], &[100.12]);
+        assert!(matches!(tif, Tif::Ioc));
+
+        // Mid plus the 3% cap.
+        let (px, tif) = quote_with(false, TwapPricing::Ioc);
+        assert_close(&[px], &[97.0]);
+        assert!(matches!(tif, Tif::Ioc));
+    }
+
+    #[test]
+    fn quotes_beyond_the_slippage_cap_are_deferred() {
+        let market = market(100.0, 2);
+
+        let far = TwapOrderRequest {
+            pricing: TwapPricing::Marketable { ticks: 10 },
+            slippage_cap: 0.0005,
+            ..request(false)
+        };
+        let book = BestPrices {
+            bid: 99.9,
+            ask: 100.1,
+        };
+        assert!(quote(&far, &market, &book).is_err());
+
+        // Nothing offered within 3% of the mid.
+        let wide = BestPrices {
+            bid: 90.0,
+            ask: 110.0,
+        };
+        assert!(quote(&request(false), &market, &wide).is_err());
+    }
+
+    #[test]
+    fn unrandomized_slices_are_even() {
+        let schedule = schedule(&request(false), 11, &mut StdRng::seed_from_u64(0));
+
+        for (i, slice) in schedule.iter().enumerate() {
+            let delay = if i == 0 { 0 } else { 60 };
+            assert_eq!(slice.delay, Duration::from_secs(delay));
+            assert!((slice.sz - 10.0 / 11.0).abs() < 1e-12);
+        }
+    }
+}
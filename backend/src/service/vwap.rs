@@ -0,0 +1,215 @@
+//! Intraday volume profiles sizing the slices of VWAP orders.
+//!
+//! A VWAP runs through the TWAP executor like any other TWAP job; only the
+//! slice sizes differ. Before the first slice, the asset's candle history is
+//! averaged into a [`VolumeProfile`] of the volume traded per [`BUCKET`] of
+//! the UTC day, and every slice of the schedule is resized to the volume the
+//! profile expects until the next one goes out.
+
+use anyhow::anyhow;
+use hyperliquid::Info;
+
+use crate::service::{hyperliquid::info, twap::TwapSlice};
+
+/// Width of one profile bucket, in milliseconds.
+pub const BUCKET: u64 = 15 * 60 * 1000;
+
+/// Candle interval matching [`BUCKET`].
+const BUCKET_INTERVAL: &str = "15m";
+
+/// Length of a day, in milliseconds.
+const DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Number of buckets per day.
+const BUCKETS: usize = (DAY / BUCKET) as usize;
+
+/// Average traded volume of an asset per time-of-day bucket.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VolumeProfile {
+    buckets: Vec<f64>,
+}
+
+impl VolumeProfile {
+    /// Profile of `coin` over the `lookback_days` days before `now`, in
+    /// milliseconds since the Unix epoch.
+    pub async fn fetch(
+        info: &Info,
+        coin: &str,
+        lookback_days: u32,
+        now: u64,
+    ) -> anyhow::Result<Self> {
+        let start_time = now.saturating_sub(lookback_days as u64 * DAY);
+        let candles = info::candle_snapshot(
+            info,
+            coin.to_string(),
+            BUCKET_INTERVAL.to_string(),
+            start_time,
+            now,
+        )
+        .await
+        .map_err(|err| anyhow!("Failed to fetch candle history: {}", err))?;
+
+        let mut volumes = Vec::with_capacity(candles.len());
+        for candle in &candles {
+            // The open candle only holds part of its bucket's volume.
+            if candle.t_ >= now {
+                continue;
+            }
+            volumes.push((candle.t, candle.v.parse::<f64>()?));
+        }
+
+        Ok(Self::from_candles(volumes))
+    }
+
+    /// Profile averaging the volume of candles given as open time and
+    /// volume. Buckets without candles expect no volume.
+    pub fn from_candles(candles: impl IntoIterator<Item = (u64, f64)>) -> Self {
+        let mut totals = vec![0.0; BUCKETS];
+        let mut counts = vec![0u32; BUCKETS];
+        for (open_time, volume) in candles {
+            let bucket = bucket(open_time);
+            totals[bucket] += volume;
+            counts[bucket] += 1;
+        }
+
+        let buckets = totals
+            .iter()
+            .zip(&counts)
+            .map(|(total, &count)| match count {
+                0 => 0.0,
+                _ => total / count as f64,
+            })
+            .collect();
+
+        Self { buckets }
+    }
+
+    /// Volume expected between `from` and `to`, in milliseconds since the
+    /// Unix epoch, assuming it trades evenly within a bucket.
+    pub fn volume(&self, from: u64, to: u64) -> f64 {
+        let mut volume = 0.0;
+        let mut time = from;
+        while time < to {
+            let end = ((time / BUCKET + 1) * BUCKET).min(to);
+            volume += self.buckets[bucket(time)] * (end - time) as f64 / BUCKET as f64;
+            time = end;
+        }
+        volume
+    }
+
+    /// Resize the slices of `schedule`, whose first slice goes out at
+    /// `start`, to the volume expected until the next slice; the last one
+    /// covers `frequency` seconds. The total size and any jitter are kept,
+    /// and the schedule is left as is when the profile expects no volume.
+    pub fn weight(&self, schedule: &mut [TwapSlice], start: u64, frequency: u64) {
+        let mut times = Vec::with_capacity(schedule.len());
+        let mut time = start;
+        for slice in schedule.iter() {
+            time += slice.delay.as_millis() as u64;
+            times.push(time);
+        }
+
+        let volumes: Vec<f64> = times
+            .iter()
+            .enumerate()
+            .map(|(i, &from)| {
+                let to = times.get(i + 1).copied().unwrap_or(from + frequency * 1000);
+                self.volume(from, to)
+            })
+            .collect();
+
+        let total: f64 = schedule.iter().map(|slice| slice.sz).sum();
+        let weighted: f64 = schedule
+            .iter()
+            .zip(&volumes)
+            .map(|(slice, volume)| slice.sz * volume)
+            .sum();
+        if weighted <= 0.0 {
+            return;
+        }
+
+        for (slice, volume) in schedule.iter_mut().zip(&volumes) {
+            slice.sz = total * slice.sz * volume / weighted;
+        }
+    }
+}
+
+/// Time-of-day bucket of `time`, in milliseconds since the Unix epoch.
+fn bucket(time: u64) -> usize {
+    ((time % DAY) / BUCKET) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn slices(sizes: &[f64], interval: u64) -> Vec<TwapSlice> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &sz)| TwapSlice {
+                delay: Duration::from_millis(if i == 0 { 0 } else { interval }),
+                sz,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn averages_candles_per_time_of_day() {
+        let profile = VolumeProfile::from_candles([
+            (0, 10.0),
+            (DAY, 30.0),
+            (BUCKET, 4.0),
+            (2 * DAY + BUCKET + 60_000, 8.0),
+        ]);
+
+        assert_eq!(profile.buckets.len(), BUCKETS);
+        assert_eq!(profile.buckets[0], 20.0);
+        assert_eq!(profile.buckets[1], 6.0);
+        assert!(profile.buckets[2..].iter().all(|&volume| volume == 0.0));
+    }
+
+    #[test]
+    fn spreads_volume_evenly_within_a_bucket() {
+        let profile = VolumeProfile::from_candles([(0, 10.0), (BUCKET, 20.0)]);
+
+        assert_eq!(profile.volume(0, BUCKET / 2), 5.0);
+        assert_eq!(profile.volume(BUCKET / 2, BUCKET + BUCKET / 2), 15.0);
+        // The profile wraps around to the same time of day.
+        assert_eq!(profile.volume(DAY, DAY + BUCKET), 10.0);
+    }
+
+    #[test]
+    fn weight_sizes_slices_by_expected_volume_and_keeps_the_total() {
+        let profile = VolumeProfile::from_candles([(0, 10.0), (BUCKET, 30.0)]);
+        let mut schedule = slices(&[4.0, 4.0], BUCKET);
+
+        profile.weight(&mut schedule, 0, BUCKET / 1000);
+
+        assert!((schedule[0].sz - 2.0).abs() < 1e-9);
+        assert!((schedule[1].sz - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn weight_keeps_the_jitter_of_the_schedule() {
+        let profile = VolumeProfile::from_candles([(0, 10.0), (BUCKET, 10.0)]);
+        let mut schedule = slices(&[1.0, 3.0], BUCKET);
+
+        profile.weight(&mut schedule, 0, BUCKET / 1000);
+
+        assert!((schedule[0].sz - 1.0).abs() < 1e-9);
+        assert!((schedule[1].sz - 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn weight_leaves_the_schedule_without_expected_volume() {
+        let profile = VolumeProfile::from_candles([(2 * BUCKET, 10.0)]);
+        let mut schedule = slices(&[4.0, 4.0], BUCKET);
+
+        profile.weight(&mut schedule, 0, BUCKET / 1000);
+
+        assert_eq!(schedule, slices(&[4.0, 4.0], BUCKET));
+    }
+}
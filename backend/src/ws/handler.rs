@@ -1,10 +1,15 @@
-use crate::{prelude::Result, ws::hyperliquid::pairs_candle::PairsCandle};
+use crate::{
+    prelude::Result,
+    service::{chase::Chases, queue::TrailingLevels},
+    ws::hyperliquid::pairs_candle::PairsCandle,
+};
 use anyhow::Context;
 use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
 use tokio::net::TcpStream;
 use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
 use tracing::{debug, error, info, warn};
+use uuid::Uuid;
 
 /// Describes the supported client-initiated websocket requests.
 ///
@@ -21,8 +26,16 @@ pub enum WSRequest {
     Price {
         symbol: String,
     },
+    TrailingStop {
+        id: Uuid,
+    },
+    Chase {
+        id: Uuid,
+    },
 }
 // { "method": "pairs_candle", "data": { "symbol_left": "BTC", "symbol_right": "ETH" } }
+// { "method": "trailing_stop", "data": { "id": "67e55044-10b1-426f-9247-bb680e5fe0c8" } }
+// { "method": "chase", "data": { "id": "67e55044-10b1-426f-9247-bb680e5fe0c8" } }
 
 /// Accept a websocket upgrade and forward supported subscription requests to
 /// their dedicated handlers.
@@ -62,6 +75,12 @@ pub async fn handler(stream: TcpStream) -> Result<()> {
                 pairs_candle_handler(&mut stream, &symbol_left, &symbol_right).await?;
             }
             WSRequest::Price { symbol: _ } => {}
+            WSRequest::TrailingStop { id } => {
+                trailing_stop_handler(&mut stream, id).await?;
+            }
+            WSRequest::Chase { id } => {
+                chase_handler(&mut stream, id).await?;
+            }
         }
     }
 
@@ -97,3 +116,59 @@ pub async fn pairs_candle_handler(
 
     Ok(())
 }
+
+/// Stream the trailing-stop levels of a queued conditional order back to the
+/// client until the order leaves the queue.
+pub async fn trailing_stop_handler(
+    stream: &mut WebSocketStream<TcpStream>,
+    id: Uuid,
+) -> Result<()> {
+    let Some(mut receiver) = TrailingLevels::receiver(id) else {
+        warn!("No trailing stop queued under {id}");
+        return Ok(());
+    };
+
+    loop {
+        let msg = serde_json::to_string(&*receiver.borrow_and_update())
+            .context("Failed serializing trailing levels")?;
+        stream
+            .send(Message::text(msg))
+            .await
+            .context("Failed sending the trailing levels to the client")?;
+
+        if receiver.changed().await.is_err() {
+            break;
+        }
+    }
+    info!("Stopped sending trailing levels for {id}");
+
+    Ok(())
+}
+
+/// Stream the state of a chase-limit order back to the client until it
+/// finishes.
+pub async fn chase_handler(stream: &mut WebSocketStream<TcpStream>, id: Uuid) -> Result<()> {
+    let Some(mut receiver) = Chases::receiver(id).await else {
+        warn!("No chase order under {id}");
+        return Ok(());
+    };
+
+    loop {
+        let (msg, finished) = {
+            let chase = receiver.borrow_and_update();
+            let msg = serde_json::to_string(&*chase).context("Failed serializing chase state")?;
+            (msg, chase.finished_at.is_some())
+        };
+        stream
+            .send(Message::text(msg))
+            .await
+            .context("Failed sending the chase state to the client")?;
+
+        if finished || receiver.changed().await.is_err() {
+            break;
+        }
+    }
+    info!("Stopped sending chase state for {id}");
+
+    Ok(())
+}
@@ -4,10 +4,15 @@
 //! This module multiplexes a limited number of long-lived Hyperliquid socket
 //! connections across subscribers. It tracks which response patterns belong to
 //! each client, forwards updates via `watch` channels, and exposes helpers to
-//! acquire a connection that has spare capacity.
+//! acquire a connection that has spare capacity. Trades streams also keep a
+//! running [`TradeVolume`] tally, since their batches are not snapshots and
+//! consumers cannot afford to miss one.
 
-use crate::model::hyperliquid::{Subscribe, Subscription, WSMethod, WSResponse};
+use crate::model::hyperliquid::{
+    ChannelConnection, Subscribe, Subscription, TradeVolume, WSMethod, WSResponse, CONNECTIONS,
+};
 use crate::prelude::Result;
+use crate::service::clock::{Clock, SystemClock};
 use anyhow::Context;
 use futures_util::{SinkExt, StreamExt};
 use lazy_static::lazy_static;
@@ -34,7 +39,7 @@ pub type StreamSender = mpsc::Sender<(
     Subscription,
     ResponsePattern,
     oneshot::Receiver<()>,
-    watch::Sender<Option<WSResponse>>,
+    Forward,
 )>;
 
 /// Receiver counterpart yielding subscription instructions for execution.
@@ -42,8 +47,17 @@ pub type StreamReceiver = mpsc::Receiver<(
     Subscription,
     ResponsePattern,
     oneshot::Receiver<()>,
-    watch::Sender<Option<WSResponse>>,
+    Forward,
 )>;
+
+/// Channels the responses of one subscription are forwarded to.
+pub struct Forward {
+    /// Latest response.
+    pub latest: watch::Sender<Option<WSResponse>>,
+    /// Volume tally, updated for `trades` responses only.
+    pub volume: watch::Sender<TradeVolume>,
+}
+
 /// Metadata associated with an established websocket connection.
 pub struct WSSubscriptions {
     /// Number of active subscriptions currently pinned to the socket.
@@ -75,7 +89,7 @@ pub async fn find_free_websocket() -> Result<usize> {
         Subscription,
         ResponsePattern,
         oneshot::Receiver<()>,
-        watch::Sender<Option<WSResponse>>,
+        Forward,
     )>(16);
 
     let new_key = wsm.len() + 1;
@@ -105,8 +119,7 @@ async fn stream_recv(
     new_key: usize,
     mut recv_subs: StreamReceiver,
 ) -> Result<()> {
-    let subscription_map: Arc<RwLock<HashMap<ResponsePattern, watch::Sender<Option<WSResponse>>>>> =
-        Arc::default();
+    let subscription_map: Arc<RwLock<HashMap<ResponsePattern, Forward>>> = Arc::default();
     let mut unsubscription_list: Vec<(oneshot::Receiver<()>, WSMethod, ResponsePattern)> =
         Vec::new();
     let subscription_map_2 = subscription_map.clone();
@@ -134,8 +147,12 @@ async fn stream_recv(
             };
             let msg_resp_patrn = ResponsePattern::from(&msg);
 
-            if let Some(sender) = subscription_map.read().await.get(&msg_resp_patrn) {
-                sender
+            if let Some(forward) = subscription_map.read().await.get(&msg_resp_patrn) {
+                if let WSResponse::Trades(batch) = &msg {
+                    forward.volume.send_if_modified(|volume| volume.add(batch));
+                }
+                forward
+                    .latest
                     .send(Some(msg))
                     .context("Failed sending price to the receiver")
                     .unwrap();
@@ -145,7 +162,7 @@ async fn stream_recv(
     });
     loop {
         // Pick up new subscription requests pushed in by `find_free_websocket`.
-        if let Ok((sub, response_pattern, stop_reciver, sender)) = recv_subs.try_recv() {
+        if let Ok((sub, response_pattern, stop_reciver, forward)) = recv_subs.try_recv() {
             let method = WSMethod::Subscribe(sub.clone());
             let payload = serde_json::to_string(&method)
                 .context("Failed to serialize subscription payload")?;
@@ -156,7 +173,7 @@ async fn stream_recv(
             subscription_map_2
                 .write()
                 .await
-                .insert(response_pattern.clone(), sender);
+                .insert(response_pattern.clone(), forward);
 
             unsubscription_list.push((stop_reciver, WSMethod::Unsubscribe(sub), response_pattern));
         }
@@ -195,7 +212,9 @@ async fn stream_recv(
 #[derive(Eq, Hash, PartialEq, Clone)]
 pub enum ResponsePattern {
     L2Book(String),
-    Candle(String),
+    Candle(String, String),
+    Trades(String),
+    ActiveAssetCtx(String),
     SubscriptionResponse(String),
 }
 
@@ -205,35 +224,72 @@ impl From<&WSResponse> for ResponsePattern {
     fn from(value: &WSResponse) -> ResponsePattern {
         match value {
             WSResponse::L2Book(price) => ResponsePattern::L2Book(price.coin.clone()),
-            WSResponse::Candle(candle) => ResponsePattern::Candle(candle.symbol.clone()),
-            WSResponse::SubscriptionResponse(subscription) => match &subscription.subscription {
-                Subscribe::Candle { coin, interval: _ } => {
-                    ResponsePattern::SubscriptionResponse(coin.clone())
-                }
-                Subscribe::L2Book { coin } => ResponsePattern::SubscriptionResponse(coin.clone()),
-            },
+            WSResponse::Candle(candle) => {
+                ResponsePattern::Candle(candle.symbol.clone(), candle.interval.clone())
+            }
+            WSResponse::Trades(trades) => ResponsePattern::Trades(
+                trades
+                    .first()
+                    .map(|trade| trade.coin.clone())
+                    .unwrap_or_default(),
+            ),
+            WSResponse::ActiveAssetCtx(ctx) => ResponsePattern::ActiveAssetCtx(ctx.coin.clone()),
+            WSResponse::SubscriptionResponse(subscription) => {
+                ResponsePattern::SubscriptionResponse(subscription.subscription.coin().to_string())
+            }
         }
     }
 }
 
-/// Access point for consumers that need a live Hyperliquid L2 book stream.
-pub struct BookPrice;
+impl From<&Subscribe> for ResponsePattern {
+    /// Pattern under which the responses for a subscription are delivered.
+    fn from(value: &Subscribe) -> ResponsePattern {
+        match value {
+            Subscribe::Candle { coin, interval } => {
+                ResponsePattern::Candle(coin.clone(), interval.clone())
+            }
+            Subscribe::L2Book { coin } => ResponsePattern::L2Book(coin.clone()),
+            Subscribe::Trades { coin } => ResponsePattern::Trades(coin.clone()),
+            Subscribe::ActiveAssetCtx { coin } => ResponsePattern::ActiveAssetCtx(coin.clone()),
+        }
+    }
+}
 
-impl BookPrice {
-    /// Ensure a connection streaming the requested L2 book exists and return a
-    /// watcher that yields updates until the provided stop signal is fired.
+/// Refcounted access to shared Hyperliquid streams stored in [`CONNECTIONS`].
+///
+/// Any number of consumers can depend on the same subscription; the remote
+/// subscription is opened for the first one and closed after the last one
+/// releases it.
+pub struct Feed;
+
+impl Feed {
+    /// Open a new subscription for `topic` and return a watcher that yields
+    /// updates until the provided stop signal is fired.
     pub async fn init(
-        symbol: &str,
+        topic: &Subscribe,
     ) -> anyhow::Result<(watch::Receiver<Option<WSResponse>>, oneshot::Sender<()>)> {
-        let (sender, receiver) = tokio::sync::watch::channel::<Option<WSResponse>>(None);
+        let (receiver, _volume, stop_sender) = Self::open(topic).await?;
+
+        Ok((receiver, stop_sender))
+    }
+
+    /// Open a new subscription for `topic`, returning watchers of its latest
+    /// response and of its volume tally, and the stop signal.
+    async fn open(
+        topic: &Subscribe,
+    ) -> anyhow::Result<(
+        watch::Receiver<Option<WSResponse>>,
+        watch::Receiver<TradeVolume>,
+        oneshot::Sender<()>,
+    )> {
+        let (latest, receiver) = tokio::sync::watch::channel::<Option<WSResponse>>(None);
+        let (volume, volume_receiver) =
+            tokio::sync::watch::channel(TradeVolume::since(SystemClock.now()));
         let (stop_sender, stop_reciver) = tokio::sync::oneshot::channel::<()>();
 
-        let topic = Subscribe::L2Book {
-            coin: symbol.to_string(),
-        };
         let subscription = Subscription::new(topic.clone());
 
-        let response = ResponsePattern::L2Book(symbol.to_string());
+        let response = ResponsePattern::from(topic);
 
         let ws_key = find_free_websocket().await?;
 
@@ -242,9 +298,95 @@ impl BookPrice {
         let ws_stream = wsm.get_mut(&ws_key).unwrap();
         ws_stream
             .sender
-            .send((subscription, response, stop_reciver, sender))
+            .send((
+                subscription,
+                response,
+                stop_reciver,
+                Forward { latest, volume },
+            ))
             .await?;
 
-        Ok((receiver, stop_sender))
+        Ok((receiver, volume_receiver, stop_sender))
+    }
+
+    /// Take a reference on the shared stream for `topic`, opening the
+    /// websocket subscription for the first consumer.
+    pub async fn subscribe(topic: &Subscribe) -> anyhow::Result<()> {
+        let mut connections = CONNECTIONS.lock().await;
+
+        if let Some(connection) = connections.get_mut(topic) {
+            connection.count += 1;
+        } else {
+            let (receiver, volume, stop_sender) = Self::open(topic).await?;
+
+            connections.insert(
+                topic.clone(),
+                ChannelConnection {
+                    count: 1,
+                    receiver,
+                    volume,
+                    stop_sender,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Clone the receiver of an already subscribed stream, letting callers
+    /// await updates without holding the [`CONNECTIONS`] lock.
+    pub async fn receiver(topic: &Subscribe) -> Option<watch::Receiver<Option<WSResponse>>> {
+        CONNECTIONS
+            .lock()
+            .await
+            .get(topic)
+            .map(|connection| connection.receiver.clone())
+    }
+
+    /// Clone the volume tally of an already subscribed `trades` stream.
+    pub async fn volume(topic: &Subscribe) -> Option<watch::Receiver<TradeVolume>> {
+        CONNECTIONS
+            .lock()
+            .await
+            .get(topic)
+            .map(|connection| connection.volume.clone())
+    }
+
+    /// Release a reference taken with [`Feed::subscribe`], stopping the
+    /// underlying stream once the last consumer is gone.
+    pub async fn unsubscribe(topic: &Subscribe) {
+        let mut connections = CONNECTIONS.lock().await;
+
+        let Some(connection) = connections.get_mut(topic) else {
+            warn!("No connection to release for {:?}", topic);
+            return;
+        };
+
+        connection.count -= 1;
+        if connection.count == 0 {
+            if let Some(connection) = connections.remove(topic) {
+                let _ = connection.stop_sender.send(());
+            }
+        }
+    }
+}
+
+/// Access point for consumers that need a live Hyperliquid L2 book stream.
+pub struct BookPrice;
+
+impl BookPrice {
+    /// Ensure a connection streaming the requested L2 book exists and return a
+    /// watcher that yields updates until the provided stop signal is fired.
+    pub async fn init(
+        symbol: &str,
+    ) -> anyhow::Result<(watch::Receiver<Option<WSResponse>>, oneshot::Sender<()>)> {
+        Feed::init(&Self::topic(symbol)).await
+    }
+
+    /// Subscription describing the L2 book of `symbol`.
+    pub fn topic(symbol: &str) -> Subscribe {
+        Subscribe::L2Book {
+            coin: symbol.to_string(),
+        }
     }
 }
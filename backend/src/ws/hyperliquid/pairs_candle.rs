@@ -117,7 +117,7 @@ impl PairsCandle {
                             .context("Failed sending paired candle to the receiver")?;
                     }
                 }
-                WSResponse::L2Book(price) => (),
+                _ => (),
             }
         }
 